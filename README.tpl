@@ -94,6 +94,35 @@ or it's short variant `-c N` you can ask cargo-show-asm to include body of bar t
 This is done recursively up to N steps. See https://github.com/pacak/cargo-show-asm/issues/247
 
 
+# Per package defaults
+
+Some of the defaults can be set in `[package.metadata.show-asm]` table of the `Cargo.toml`,
+command line options and environment variables take priority over them.
+
+```toml
+[package.metadata.show-asm]
+# profile to use instead of release, same as --profile
+profile = "dev"
+# same as --target-cpu
+target-cpu = "native"
+# same as --simplify and --rust, --no-simplify and --no-rust turn them off
+simplify = true
+rust = true
+# "intel" or "att"
+syntax = "att"
+```
+
+
+# Exit codes
+
+Besides 0 for success `cargo-show-asm` uses several exit codes for failures scripts might
+want to tell apart:
+
+- 2 - requested function or item index doesn't exist
+- 3 - more than one function matches, a list of suggestions is printed instead
+- 4 - Rust sources are needed but `rust-src` component is not installed
+- 101 - cargo failed to build the project
+
 # What about `cargo-asm`?
 
 `cargo-asm` is not maintained: <https://github.com/gnzlbg/cargo-asm/issues/244>. This crate is a reimplementation which addresses a number of its shortcomings, including:
@@ -129,6 +158,14 @@ cargo install cargo-show-asm -F bright-color
 cargo install cargo-show-asm -F dull-color
 ```
 
+# Compressed inputs
+
+To disassemble gzip or zstd compressed objects or rlibs with `--file` install it with
+`compressed` feature
+```console
+cargo install cargo-show-asm -F compressed
+```
+
 # License
 This project is licensed under either of
 
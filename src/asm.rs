@@ -3,57 +3,182 @@ use crate::asm::statements::{GenericDirective, Label};
 use crate::cached_lines::CachedLines;
 use crate::demangle::LabelKind;
 use crate::{
-    color, demangle, esafeprintln, get_context_for, safeprintln, Dumpable, Item, RawLines, URange,
+    cargo_home, color, demangle, esafeprintln, exit_code, get_context_for, safeprint, safeprintln,
+    Dumpable, Item, RawLines, URange,
 };
 // TODO, use https://sourceware.org/binutils/docs/as/index.html
 use crate::opts::{Format, NameDisplay, RedundantLabels, SourcesFrom};
 
+mod nasm;
+mod operands;
 mod statements;
 
 use owo_colors::OwoColorize;
 use statements::{parse_statement, Loc};
 pub use statements::{Directive, Instruction, Statement};
+use std::borrow::Cow;
 use std::cell::RefCell;
 use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::ops::Range;
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 type SourceFile = (PathBuf, Option<(Source, CachedLines)>);
 
-pub fn parse_file(input: &str) -> anyhow::Result<Vec<Statement>> {
+pub fn parse_file(input: &str) -> anyhow::Result<Vec<Statement<'_>>> {
     // eat all statements until the eof, so we can report the proper errors on failed parse
     match nom::multi::many0(parse_statement)(input) {
         Ok(("", stmts)) => Ok(stmts),
-        Ok((leftovers, _)) =>
+        Ok((leftovers, _)) => {
+            let consumed = &input[..input.len() - leftovers.len()];
+            let line_no = consumed.matches('\n').count() + 1;
+            let line = leftovers.lines().next().unwrap_or_default();
+            anyhow::bail!(
+                "Couldn't parse line {line_no} of the .s file:\n{line}\n\
+                 Please open an issue at https://github.com/pacak/cargo-show-asm/issues and include this line"
+            )
+        }
+        Err(err) => anyhow::bail!("Couldn't parse the .s file: {err}"),
+    }
+}
+
+/// Parse only `.file` directives and functions with names containing `function`
+///
+/// Functions are located with a cheap scan for their labels and `.Lfunc_end` markers that
+/// doesn't build statements for the rest of the file. `None` if nothing matched.
+pub fn parse_selected<'a>(
+    contents: &'a str,
+    function: &str,
+) -> anyhow::Result<Option<Vec<Statement<'a>>>> {
+    let mut chunks = Vec::new();
+    let mut found = false;
+    // where the next function can start and where the matching one does
+    let mut chunk_start = 0;
+    let mut current = None;
+    let mut offset = 0;
+    for line in contents.split_inclusive('\n') {
+        let end = offset + line.len();
+        if line.starts_with("\t.file\t") || line.starts_with("\t.cv_file\t") {
+            if current.is_none() {
+                chunks.push(offset..end);
+                chunk_start = end;
+            }
+        } else if let Some((label, _)) = line
+            .split_once(':')
+            .filter(|_| !line.starts_with(['\t', ' ', '#']))
         {
-            #[allow(clippy::redundant_else)]
-            if leftovers.len() < 1000 {
-                anyhow::bail!("Didn't consume everything, leftovers: {leftovers:?}")
-            } else {
-                let head = &leftovers[..leftovers
-                    .char_indices()
-                    .nth(200)
-                    .expect("Shouldn't have that much unicode here...")
-                    .0];
-                anyhow::bail!("Didn't consume everything, leftovers prefix: {head:?}");
+            if label.starts_with(".Lfunc_end") || label.starts_with("Lfunc_end") {
+                if let Some(start) = current.take() {
+                    chunks.push(start..end);
+                }
+                chunk_start = end;
+            } else if current.is_none()
+                && demangle::demangled(label)
+                    .is_some_and(|dem| format!("{dem:#?}").contains(function))
+            {
+                current = Some(chunk_start);
+                found = true;
             }
         }
-        Err(err) => anyhow::bail!("Couldn't parse the .s file: {err}"),
+        offset = end;
+    }
+    if let Some(start) = current {
+        chunks.push(start..contents.len());
+    }
+    if !found {
+        return Ok(None);
+    }
+    let mut res = Vec::new();
+    for chunk in chunks {
+        res.extend(parse_file(&contents[chunk])?);
+    }
+    Ok(Some(res))
+}
+
+/// Record an item spanning `range`, its length is counted from the starting label
+fn close_item(
+    res: &mut BTreeMap<Item, Range<usize>>,
+    item: &mut Option<Item>,
+    range: Range<usize>,
+) {
+    if let Some(mut item) = item.take() {
+        item.len = range.end - item.len;
+        item.non_blank_len = item.len;
+        res.insert(item, range);
+    }
+}
+
+/// Record an item without an end marker, as long as it contains any code or data: labels
+/// for statics never get one
+fn close_unterminated(
+    lines: &[Statement],
+    res: &mut BTreeMap<Item, Range<usize>>,
+    item: &mut Option<Item>,
+    range: Range<usize>,
+) {
+    if lines[range.clone()].iter().any(|line| {
+        matches!(
+            line,
+            Statement::Instruction(_) | Statement::Directive(Directive::Data(..))
+        )
+    }) {
+        close_item(res, item, range);
+    } else {
+        *item = None;
     }
 }
 
+/// Is this a `.size` directive for the currently open item?
+fn closes_item(line: &Statement, item: Option<&Item>) -> bool {
+    matches!((line, item), (Statement::Directive(Directive::Size(name, _)), Some(item)) if item.mangled_name == *name)
+}
+
+/// Does an item starting at `start` end with `.Lfunc_end` or `.size`? Items that do are
+/// closed by those markers only, others end where the next section or function starts
+fn has_end_marker(lines: &[Statement], start: usize, item: &Item) -> bool {
+    for line in &lines[start + 1..] {
+        if line.is_end_of_fn() || closes_item(line, Some(item)) {
+            return true;
+        }
+        if let Statement::Label(label) = line {
+            if label.kind == LabelKind::Global || demangle::demangled(label.id).is_some() {
+                return false;
+            }
+        }
+    }
+    false
+}
+
 #[must_use]
 pub fn find_items(lines: &[Statement]) -> BTreeMap<Item, Range<usize>> {
     let mut res = BTreeMap::new();
 
     let mut sec_start = 0;
+    let mut sec_name = None;
     let mut item: Option<Item> = None;
+    // current item has an end marker
+    let mut terminated = false;
     let mut names = BTreeMap::new();
+    let weak = lines
+        .iter()
+        .filter_map(|line| match line {
+            Statement::Directive(Directive::Weak(_, name)) => Some(*name),
+            _ => None,
+        })
+        .collect::<BTreeSet<_>>();
 
     for (ix, line) in lines.iter().enumerate() {
-        if line.is_section_start() {
+        if let Statement::Directive(Directive::SectionStart(name)) = line {
             if item.is_none() {
                 sec_start = ix;
+                sec_name = Some(*name);
+            } else if sec_name != Some(*name) && !terminated {
+                // hand written code might not have .Lfunc_end labels, a different
+                // section is the next best thing to mark the end of a function
+                close_unterminated(lines, &mut res, &mut item, sec_start..ix);
+                sec_start = ix;
+                sec_name = Some(*name);
             } else {
                 // on Windows, when panic unwinding is enabled, the compiler can
                 // produce multiple blocks of exception-handling code for a
@@ -74,33 +199,48 @@ pub fn find_items(lines: &[Statement]) -> BTreeMap<Item, Range<usize>> {
             // deep enough within the current section treat it as a new section start.
             // This little hack allows to include full section on Windows/Linux but
             // still capture full function body on Mac.
-            sec_start = ix;
-        } else if line.is_end_of_fn() {
-            let sec_end = ix;
-            let range = sec_start..sec_end;
-            if let Some(mut item) = item.take() {
-                item.len = ix - item.len;
-                item.non_blank_len = item.len;
-                res.insert(item, range);
+            //
+            // Without .Lfunc_end labels this is also where the previous function ends.
+            if !terminated {
+                close_unterminated(lines, &mut res, &mut item, sec_start..ix);
             }
+            sec_start = ix;
+        } else if line.is_end_of_fn() || closes_item(line, item.as_ref()) {
+            // `.size sym, .-sym` is how some targets mark the end of a function
+            // without a .Lfunc_end label
+            close_item(&mut res, &mut item, sec_start..ix);
         } else if let Statement::Label(label) = line {
             if let Some(dem) = demangle::demangled(label.id) {
+                if item.is_some() {
+                    // previous function didn't have an end marker
+                    close_unterminated(lines, &mut res, &mut item, sec_start..ix);
+                    sec_start = ix;
+                }
                 let hashed = format!("{dem:?}");
                 let name = format!("{dem:#?}");
                 let name_entry = names.entry(name.clone()).or_insert(0);
-                item = Some(Item {
+                let new = Item {
                     mangled_name: label.id.to_owned(),
                     name,
                     hashed,
                     index: *name_entry,
                     len: ix,
+                    position: ix,
                     non_blank_len: 0,
-                });
+                    body_hash: None,
+                    merged: false,
+                    weak: weak.contains(label.id),
+                    object: false,
+                };
+                terminated = has_end_marker(lines, ix, &new);
+                item = Some(new);
                 *name_entry += 1;
             } else if matches!(label.kind, LabelKind::Unknown | LabelKind::Global) {
                 if let Some(mut i) = handle_non_mangled_labels(lines, ix, label, sec_start) {
                     let name_entry = names.entry(i.name.clone()).or_insert(0);
                     i.index = *name_entry;
+                    i.weak = weak.contains(label.id);
+                    terminated = has_end_marker(lines, ix, &i);
                     item = Some(i);
                     *name_entry += 1;
                 }
@@ -108,6 +248,9 @@ pub fn find_items(lines: &[Statement]) -> BTreeMap<Item, Range<usize>> {
         }
     }
 
+    // last function in a file without an end marker
+    close_unterminated(lines, &mut res, &mut item, sec_start..lines.len());
+
     // detect merged functions
     // we'll define merged function as something with a global label and a reference to a different
     // global label
@@ -172,7 +315,12 @@ pub fn find_items(lines: &[Statement]) -> BTreeMap<Item, Range<usize>> {
                     hashed,
                     index: *name_entry,
                     len: range.len(),
+                    position: range.start,
                     non_blank_len: range.len(),
+                    body_hash: None,
+                    merged: true,
+                    weak: weak.contains(sym),
+                    object: false,
                 },
                 range,
             );
@@ -180,7 +328,101 @@ pub fn find_items(lines: &[Statement]) -> BTreeMap<Item, Range<usize>> {
         }
     }
 
-    res
+    add_init_items(lines, &mut res, &mut names);
+
+    // statics are items without any instructions
+    res.into_iter()
+        .map(|(mut item, range)| {
+            item.object = !item.merged
+                && !lines[range.clone()]
+                    .iter()
+                    .any(|line| matches!(line, Statement::Instruction(_)));
+            (item, range)
+        })
+        .collect()
+}
+
+/// Name of a section with pointers to static constructors that run before `main`:
+/// `.init_array` and `.ctors` on ELF, `__mod_init_func` on Mach-O
+fn init_section_name(section: &str) -> Option<&str> {
+    let name = section.split(',').next()?.trim();
+    if name.starts_with(".init_array") || name.starts_with(".ctors") {
+        Some(name)
+    } else if section.starts_with("__DATA,__mod_init_func") {
+        Some("__mod_init_func")
+    } else {
+        None
+    }
+}
+
+/// Static constructors are not found by looking for function labels: the sections listing
+/// them contain only data and functions they point to are not always global. Both the
+/// sections and the functions become items
+fn add_init_items(
+    lines: &[Statement],
+    res: &mut BTreeMap<Item, Range<usize>>,
+    names: &mut BTreeMap<String, usize>,
+) {
+    let mut new_item = |res: &mut BTreeMap<Item, Range<usize>>, name: &str, range: Range<usize>| {
+        let name_entry = names.entry(name.to_owned()).or_insert(0);
+        let item = Item {
+            mangled_name: name.to_owned(),
+            name: name.to_owned(),
+            hashed: name.to_owned(),
+            index: *name_entry,
+            len: range.len(),
+            position: range.start,
+            non_blank_len: range.len(),
+            body_hash: None,
+            merged: false,
+            weak: false,
+            object: false,
+        };
+        *name_entry += 1;
+        res.insert(item, range);
+    };
+    let section_end = |start: usize| {
+        lines[start + 1..]
+            .iter()
+            .position(Statement::is_section_start)
+            .map_or(lines.len(), |len| start + 1 + len)
+    };
+
+    let mut constructors = BTreeSet::new();
+    for (start, line) in lines.iter().enumerate() {
+        let Statement::Directive(Directive::SectionStart(section)) = line else {
+            continue;
+        };
+        let Some(name) = init_section_name(section) else {
+            continue;
+        };
+        let end = section_end(start);
+        for line in &lines[start..end] {
+            if let Statement::Directive(Directive::Data(_, value)) = line {
+                constructors.insert(value.trim());
+            }
+        }
+        new_item(res, name, start..end);
+    }
+
+    let known = res
+        .keys()
+        .map(|item| item.mangled_name.clone())
+        .collect::<BTreeSet<_>>();
+    for (start, line) in lines.iter().enumerate() {
+        let Statement::Label(Label { id, .. }) = line else {
+            continue;
+        };
+        if !constructors.contains(id) || known.contains(*id) {
+            continue;
+        }
+        let end = lines[start + 1..]
+            .iter()
+            .position(|line| line.is_end_of_fn() || line.is_section_start())
+            .map_or(lines.len(), |len| start + 1 + len);
+        let name = demangle::name(id).unwrap_or_else(|| (*id).to_owned());
+        new_item(res, &name, start..end);
+    }
 }
 
 /// Handles the non-mangled labels found in the given lines of ASM statements.
@@ -206,7 +448,8 @@ fn handle_non_mangled_labels(
             if is_windows || is_mac {
                 // Search for .globl between sec_start and ix
                 for line in &lines[sec_start..ix] {
-                    if let Statement::Directive(Directive::Global(g)) = line {
+                    if let Statement::Directive(Directive::Global(g) | Directive::Weak(_, g)) = line
+                    {
                         // last bool is responsible for stripping leading underscore.
                         // Stripping is not needed on Linux and 64-bit Windows.
                         // Currently we want to strip underscore on MacOS
@@ -218,15 +461,22 @@ fn handle_non_mangled_labels(
                 }
                 None
             } else {
-                // Linux symbols each have their own section, named with this prefix.
-                get_item_in_section(ix, label, ss.strip_prefix(".text.")?, false)
+                // Linux symbols each have their own section, named with one of these prefixes.
+                const LINUX_SECTION_PREFIXES: &[&str] =
+                    &[".text.", ".rodata.", ".data.rel.ro.", ".data.", ".bss."];
+                let name = LINUX_SECTION_PREFIXES
+                    .iter()
+                    .find_map(|prefix| ss.strip_prefix(prefix))?;
+                get_item_in_section(ix, label, name, false)
             }
         }
         //        Some(Statement::Directive(Directive::Generic(GenericDirective(g)))) => {
         // macOS symbols after the first are matched here.
         //            get_item_in_section(PrefixKind::Global, ix, label, g, true)
         //        }
-        Some(Statement::Directive(Directive::Global(g))) => get_item_in_section(ix, label, g, true),
+        Some(Statement::Directive(Directive::Global(g) | Directive::Weak(_, g))) => {
+            get_item_in_section(ix, label, g, true)
+        }
         _ => None,
     }
 }
@@ -248,7 +498,12 @@ fn get_item_in_section(ix: usize, label: &Label, ss: &str, strip_underscore: boo
         hashed: name,
         index: 0, // Written later in find_items
         len: ix,
+        position: ix,
         non_blank_len: 0,
+        body_hash: None,
+        merged: false,
+        weak: false,
+        object: false,
     })
 }
 
@@ -261,10 +516,15 @@ fn used_labels<'a>(stmts: &'_ [Statement<'a>]) -> BTreeSet<&'a str> {
                 Directive::File(_)
                 | Directive::Loc(_)
                 | Directive::Global(_)
+                | Directive::Weak(..)
                 | Directive::SubsectionsViaSym
-                | Directive::SymIsFun(_) => None,
-                Directive::Data(_, val) | Directive::SetValue(_, val) => Some(*val),
+                | Directive::SymIsFun(_)
+                | Directive::Align(_) => None,
+                Directive::Data(_, val) | Directive::SetValue(_, val) | Directive::Size(_, val) => {
+                    Some(*val)
+                }
                 Directive::Generic(g) => Some(g.0),
+                Directive::Cfi(c) | Directive::Seh(c) => Some(*c),
                 Directive::SectionStart(ss) => Some(*ss),
             },
             Statement::Instruction(i) => i.args,
@@ -281,32 +541,530 @@ fn scan_constant(
     body: &[Statement],
 ) -> Option<URange> {
     let start = *sections.get(name)?;
-    let end = start
-        + body[start + 1..]
-            .iter()
-            .take_while(|s| matches!(s, Statement::Directive(Directive::Data(_, _))))
-            .count()
-        + 1;
+    let mut end = start + 1;
+    for (ix, stmt) in body.iter().enumerate().skip(start + 1) {
+        match stmt {
+            Statement::Directive(Directive::Data(_, _)) => end = ix + 1,
+            // padding between entries, only included when more data follows
+            Statement::Directive(Directive::Align(_)) => {}
+            _ => break,
+        }
+    }
     Some(URange { start, end })
 }
 
+/// Rough register pressure for --reg-pressure: number of distinct registers mentioned by
+/// instructions between a basic block label and the next one, keyed by the label's index.
+/// Other labels such as `.Ltmp3` don't split blocks. Sub-registers such as `eax` and `rax`
+/// are counted separately
+fn block_registers(stmts: &[Statement]) -> BTreeMap<usize, usize> {
+    let mut res = BTreeMap::new();
+    let mut current: Option<(usize, BTreeSet<&str>)> = None;
+    for (ix, stmt) in stmts.iter().enumerate() {
+        match stmt {
+            Statement::Label(Label { id, .. }) if is_block_label(id) => {
+                if let Some((start, regs)) = current.replace((ix, BTreeSet::new())) {
+                    res.insert(start, regs.len());
+                }
+            }
+            Statement::Instruction(Instruction {
+                op,
+                args: Some(args),
+                ..
+            }) if !op.starts_with('#') => {
+                if let Some((_, regs)) = &mut current {
+                    regs.extend(demangle::registers(args));
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some((start, regs)) = current {
+        res.insert(start, regs.len());
+    }
+    res
+}
+
+/// Basic block label: `.LBB0_3`, `LBB0_3` on Mac
+fn is_block_label(id: &str) -> bool {
+    id.trim_start_matches('.').starts_with("LBB")
+}
+
+/// Width in bits of a vector register: `xmm`, `ymm` and `zmm` on x86, `v` and `q` on ARM
+fn vector_width(reg: &str) -> Option<usize> {
+    match reg.get(..3) {
+        Some("xmm") => Some(128),
+        Some("ymm") => Some(256),
+        Some("zmm") => Some(512),
+        _ => reg.starts_with(['v', 'q']).then_some(128),
+    }
+}
+
+/// x86 instructions that use vector registers for a single value, without the `v` prefix
+/// AVX versions have. AT&T syntax adds size suffixes to conversions from integers
+const SCALAR_OPS: &[&str] = &[
+    "addss",
+    "addsd",
+    "subss",
+    "subsd",
+    "mulss",
+    "mulsd",
+    "divss",
+    "divsd",
+    "sqrtss",
+    "sqrtsd",
+    "minss",
+    "minsd",
+    "maxss",
+    "maxsd",
+    "rcpss",
+    "rsqrtss",
+    "roundss",
+    "roundsd",
+    "cmpss",
+    "cmpsd",
+    "comiss",
+    "comisd",
+    "ucomiss",
+    "ucomisd",
+    "movss",
+    "movsd",
+    "movd",
+    "movq",
+    "cvtss2sd",
+    "cvtsd2ss",
+    "cvtss2si",
+    "cvtsd2si",
+    "cvttss2si",
+    "cvttsd2si",
+    "cvtsi2ss",
+    "cvtsi2sd",
+    "cvtsi2ssl",
+    "cvtsi2ssq",
+    "cvtsi2sdl",
+    "cvtsi2sdq",
+    "cvtss2siq",
+    "cvtsd2siq",
+    "cvttss2siq",
+    "cvttsd2siq",
+    "cvtss2sil",
+    "cvtsd2sil",
+    "cvttss2sil",
+    "cvttsd2sil",
+    "fmadd132ss",
+    "fmadd213ss",
+    "fmadd231ss",
+    "fmadd132sd",
+    "fmadd213sd",
+    "fmadd231sd",
+    "fmsub132ss",
+    "fmsub213ss",
+    "fmsub231ss",
+    "fmsub132sd",
+    "fmsub213sd",
+    "fmsub231sd",
+    "fnmadd132ss",
+    "fnmadd213ss",
+    "fnmadd231ss",
+    "fnmadd132sd",
+    "fnmadd213sd",
+    "fnmadd231sd",
+    "fnmsub132ss",
+    "fnmsub213ss",
+    "fnmsub231ss",
+    "fnmsub132sd",
+    "fnmsub213sd",
+    "fnmsub231sd",
+];
+
+/// Number of instructions that operate on vector registers, total number of instructions
+/// and the widest vector width in bits, for --simd-summary
+///
+/// Scalar floating point and moves between general purpose and vector registers use vector
+/// registers on x86 too, but those are not counted
+fn simd_usage(stmts: &[Statement]) -> (usize, usize, usize) {
+    let mut simd = 0;
+    let mut total = 0;
+    let mut widest = 0;
+    for stmt in stmts {
+        let Statement::Instruction(Instruction { op, args, .. }) = stmt else {
+            continue;
+        };
+        if op.starts_with('#') {
+            continue;
+        }
+        total += 1;
+        let base = op.strip_prefix('v').unwrap_or(op);
+        let scalar = SCALAR_OPS.contains(&base);
+        let Some(width) = args
+            .iter()
+            .flat_map(|args| demangle::registers(args))
+            .filter_map(vector_width)
+            .max()
+        else {
+            continue;
+        };
+        if !scalar {
+            simd += 1;
+            widest = widest.max(width);
+        }
+    }
+    (simd, total, widest)
+}
+
+/// Deduplicated names of global functions called from `stmts`, for --calls
+fn called_functions<'a>(stmts: &'a [Statement<'a>]) -> BTreeSet<&'a str> {
+    stmts
+        .iter()
+        .filter_map(Statement::call_target)
+        .map(demangle::without_indirection)
+        .collect()
+}
+
+/// Jump tables generated for `match` are a `.LJTI` label (`LJTI` on Darwin) followed by data
+/// entries that refer to other labels: `.long .LBB0_2-.LJTI0_0`. Maps statement index to
+/// entry position and target
+fn jump_table_entries<'a>(stmts: &[Statement<'a>]) -> BTreeMap<usize, (usize, &'a str)> {
+    let mut res = BTreeMap::new();
+    for (start, stmt) in stmts.iter().enumerate() {
+        let Statement::Label(table) = stmt else {
+            continue;
+        };
+        if !table.id.trim_start_matches('.').starts_with("LJTI") {
+            continue;
+        }
+        let targets = stmts[start + 1..]
+            .iter()
+            .map_while(|stmt| match *stmt {
+                Statement::Directive(Directive::Data(
+                    "byte" | "short" | "hword" | "word" | "long" | "quad",
+                    val,
+                )) => demangle::local_labels(val).find(|label| *label != table.id),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        for (entry, target) in targets.into_iter().enumerate() {
+            res.insert(start + 1 + entry, (entry, target));
+        }
+    }
+    res
+}
+
+/// aarch64 materializes addresses in two steps: `adrp x0, sym@PAGE` loads the page and
+/// `add x0, x0, sym@PAGEOFF` or `ldr x0, [x0, sym@PAGEOFF]` adds the offset within it. ELF
+/// flavor spells these `sym` / `:lo12:sym` and `:got:sym` / `:got_lo12:sym` instead.
+///
+/// Returns the register and the symbol for the first half
+fn page_address<'a>(instr: &Instruction<'a>) -> Option<(&'a str, &'a str)> {
+    let ("adrp", Some(args)) = (instr.op, instr.args) else {
+        return None;
+    };
+    let (reg, sym) = args.split_once(',')?;
+    let sym = sym.trim();
+    let sym = sym.strip_prefix(":got:").unwrap_or(sym);
+    let sym = ["@GOTPAGE", "@PAGE"]
+        .iter()
+        .find_map(|suffix| sym.strip_suffix(suffix))
+        .unwrap_or(sym);
+    Some((reg.trim(), sym))
+}
+
+/// Symbol for the second half of an address materialization based on register `base`,
+/// see [`page_address`]
+fn page_offset<'a>(instr: &Instruction<'a>, base: &str) -> Option<&'a str> {
+    let ("add" | "ldr", Some(args)) = (instr.op, instr.args) else {
+        return None;
+    };
+    let mut operands = args
+        .split(',')
+        .map(|op| op.trim_matches(|c: char| c.is_whitespace() || matches!(c, '[' | ']' | '!')));
+    let _dst = operands.next()?;
+    if operands.next()? != base {
+        return None;
+    }
+    let offset = operands.next()?;
+    [":got_lo12:", ":lo12:"]
+        .iter()
+        .find_map(|prefix| offset.strip_prefix(prefix))
+        .or_else(|| {
+            ["@GOTPAGEOFF", "@PAGEOFF"]
+                .iter()
+                .find_map(|suffix| offset.strip_suffix(suffix))
+        })
+}
+
+/// Instructions grouped by the source line they were generated from, for `--source-json`
+///
+/// Output is a single JSON object, instructions without a known location go into "no source"
+fn source_json(file_names: &BTreeMap<u64, PathBuf>, stmts: &[Statement]) -> serde_json::Value {
+    use serde_json::{json, Value};
+
+    let mut no_source = Vec::new();
+    let mut by_loc = BTreeMap::<(u64, u64), Vec<Value>>::new();
+    let mut current = None;
+    for stmt in stmts {
+        match stmt {
+            Statement::Directive(Directive::Loc(loc)) => {
+                current = (loc.line != 0).then_some((loc.file, loc.line));
+            }
+            Statement::Instruction(instr) if !instr.op.starts_with('#') => {
+                let text = match instr.args {
+                    Some(args) => format!("{} {args}", instr.op),
+                    None => instr.op.to_owned(),
+                };
+                let references = instr
+                    .args
+                    .into_iter()
+                    .flat_map(demangle::global_references)
+                    .map(|r| json!({ "mangled": r, "name": demangle::name(r) }))
+                    .collect::<Vec<_>>();
+                let entry = json!({ "text": text, "references": references });
+                match current {
+                    Some(key) => by_loc.entry(key).or_default().push(entry),
+                    None => no_source.push(entry),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let locations = by_loc
+        .into_iter()
+        .map(|((file, line), instructions)| {
+            json!({
+                "file": file_names.get(&file).map(|p| p.display().to_string()),
+                "line": line,
+                "instructions": instructions,
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({ "locations": locations, "no source": no_source })
+}
+
+/// Print lines of the function's source file that never show up in any `.loc` directive
+///
+/// Function's own file is the one mentioned by the first `.loc` directive, the span is
+/// between the first and the last line mentioned in that file
+fn dump_dead_lines(files: &BTreeMap<u64, SourceFile>, stmts: &[Statement]) {
+    let locs = stmts.iter().filter_map(|stmt| match stmt {
+        Statement::Directive(Directive::Loc(loc)) if loc.line != 0 => Some(loc),
+        _ => None,
+    });
+    let Some(file) = locs.clone().next().map(|loc| loc.file) else {
+        return;
+    };
+    let used = locs
+        .filter(|loc| loc.file == file)
+        .map(|loc| loc.line)
+        .collect::<BTreeSet<_>>();
+    let (Some(&first), Some(&last)) = (used.first(), used.last()) else {
+        return;
+    };
+    let Some((fname, Some((_source, source_lines)))) = files.get(&file) else {
+        esafeprintln!("Can't locate the source file, unable to report lines without asm");
+        return;
+    };
+
+    safeprintln!("\n======================= Lines without asm =========================");
+    safeprintln!(
+        "{}",
+        color!(
+            "# lines expanded from macros or inlined elsewhere can be legitimately missing",
+            OwoColorize::cyan
+        )
+    );
+    for line in first..=last {
+        if used.contains(&line) {
+            continue;
+        }
+        let Some(rust_line) = source_lines.get(line as usize - 1) else {
+            break;
+        };
+        let rust_line = rust_line.trim();
+        if rust_line.is_empty() || rust_line.starts_with("//") {
+            continue;
+        }
+        safeprintln!(
+            "{} {}",
+            color!(
+                format_args!("{}:{line}", fname.display()),
+                OwoColorize::cyan
+            ),
+            color!(rust_line, OwoColorize::bright_red)
+        );
+    }
+}
+
+/// Replace function specific part of basic block labels: `.LBB12_3` becomes `.LBB_3`
+fn normalize_block_labels(input: &str) -> Cow<'_, str> {
+    if !input.contains("LBB") {
+        return Cow::Borrowed(input);
+    }
+    let mut res = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(pos) = rest.find("LBB") {
+        res.push_str(&rest[..pos + 3]);
+        rest = rest[pos + 3..].trim_start_matches(|c: char| c.is_ascii_digit());
+    }
+    res.push_str(rest);
+    Cow::Owned(res)
+}
+
+/// Hash instructions and basic block labels, ignoring debug info and other directives
+fn body_hash(stmts: &[Statement]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for stmt in stmts {
+        match stmt {
+            Statement::Instruction(Instruction { op, args, .. }) => {
+                op.hash(&mut hasher);
+                args.map(normalize_block_labels).hash(&mut hasher);
+            }
+            Statement::Label(Label {
+                kind: LabelKind::Local,
+                id,
+            }) => normalize_block_labels(id).hash(&mut hasher),
+            _ => {}
+        }
+    }
+    hasher.finish()
+}
+
+/// Collect file names declared with .file directives
+pub(crate) fn file_names(stmts: &[Statement]) -> BTreeMap<u64, PathBuf> {
+    stmts
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Statement::Directive(Directive::File(f)) => {
+                Some((f.index, f.path.as_full_path().into_owned()))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Items with a `.loc` pointing to `line` of a file whose path ends with `file`
+fn items_at(
+    stmts: &[Statement],
+    items: &BTreeMap<Item, Range<usize>>,
+    file: &Path,
+    line: u64,
+) -> Vec<Item> {
+    let ids = file_names(stmts)
+        .into_iter()
+        .filter(|(_, path)| path.ends_with(file))
+        .map(|(id, _)| id)
+        .collect::<BTreeSet<_>>();
+    items
+        .iter()
+        .filter(|(_, range)| {
+            stmts[(*range).clone()].iter().any(|stmt| {
+                matches!(stmt, Statement::Directive(Directive::Loc(loc))
+                    if loc.line == line && ids.contains(&loc.file))
+            })
+        })
+        .map(|(item, _)| item.clone())
+        .collect()
+}
+
+/// Function tail called by a jump at `ix`: a jump to a known function that ends the
+/// function, nothing but labels and directives can follow it
+fn tail_call_target<'a>(
+    stmts: &'a [Statement],
+    ix: usize,
+    globals: &BTreeSet<String>,
+) -> Option<&'a str> {
+    let target = stmts[ix].jump_target().filter(|t| globals.contains(*t))?;
+    let next = stmts[ix + 1..].iter().find(|stmt| match stmt {
+        Statement::Label(_) => true,
+        Statement::Instruction(instr) => !instr.op.starts_with('#'),
+        _ => false,
+    });
+    matches!(next, None | Some(Statement::Label(_))).then_some(target)
+}
+
+/// `// file : line` comment that precedes Rust source line, `//` comes from `--source-prefix`
+fn source_position(fname: &Path, loc: &Loc, fmt: &Format) -> String {
+    let prefix = &fmt.source_prefix;
+    let flags = loc.flags();
+    if fmt.loc_flags && !flags.is_empty() {
+        format!("\t\t{prefix} {} : {} ({flags})", fname.display(), loc.line)
+    } else {
+        format!("\t\t{prefix} {} : {}", fname.display(), loc.line)
+    }
+}
+
+/// Was this source line printed within the last `window` statements? If not it is
+/// remembered as printed at `ix`. Used by `--source-window`, 0 disables the check
+fn shown_recently(
+    recent: &mut BTreeMap<(Option<u64>, u64, u64), usize>,
+    window: usize,
+    loc: &Loc,
+    ix: usize,
+) -> bool {
+    if window == 0 {
+        return false;
+    }
+    let key = (loc.function, loc.file, loc.line);
+    if let Some(&seen) = recent.get(&key) {
+        if ix - seen <= window {
+            return true;
+        }
+    }
+    recent.insert(key, ix);
+    false
+}
+
+/// Statements `--simplify` leaves out, data directives are kept for objects
+fn hidden_by_simplify(line: &Statement, object: bool) -> bool {
+    line.boring() && !(object && matches!(line, Statement::Directive(Directive::Data(_, _))))
+}
+
 fn dump_range(
     files: &BTreeMap<u64, SourceFile>,
+    globals: &BTreeSet<String>,
     fmt: &Format,
     print_range: Range<usize>,
     body: &[Statement], // full body
 ) -> anyhow::Result<()> {
     let print_range = URange::from(print_range);
     let mut prev_loc = Loc::default();
+    // (function, file, line) => index of the statement where this source line was printed last
+    let mut recent_locs = BTreeMap::new();
 
     let stmts = &body[print_range];
+    // exception handling tail is folded into a single line with --no-eh
+    let (stmts, eh_tail) = match stmts.iter().position(Statement::is_eh_tail_start) {
+        Some(eh_start) if fmt.no_eh => (&stmts[..eh_start], stmts.len() - eh_start),
+        _ => (stmts, 0),
+    };
     let used = if fmt.redundant_labels == RedundantLabels::Keep {
         BTreeSet::new()
     } else {
         used_labels(stmts)
     };
+    let jump_tables = jump_table_entries(stmts);
+    // statics have no code, their data is what the user wants to see
+    let object = !stmts.iter().any(|s| matches!(s, Statement::Instruction(_)));
+    // widest instruction encoding, to align instructions with --encoding
+    let encoding_width = stmts
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Statement::Instruction(instr) if fmt.encoding => Some(instr.encoding_bytes()?.len()),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0);
+    let block_regs = if fmt.reg_pressure {
+        block_registers(stmts)
+    } else {
+        BTreeMap::new()
+    };
+    // register => symbol for `adrp` waiting for the matching `add` or `ldr`
+    let mut pages = BTreeMap::new();
 
     let mut empty_line = false;
+    // sequential number of an instruction for --offsets
+    let mut insn_ix = 0usize;
     for (ix, line) in stmts.iter().enumerate() {
         if fmt.verbosity > 2 {
             safeprintln!("{line:?}");
@@ -324,36 +1082,55 @@ fn dump_range(
                 continue;
             }
             prev_loc = *loc;
+            if shown_recently(&mut recent_locs, fmt.source_window, loc, ix) {
+                continue;
+            }
             match files.get(&loc.file) {
                 Some((fname, Some((source, file)))) => {
                     if source.show_for(fmt.sources_from) {
-                        let rust_line = &file.get(loc.line as usize - 1).expect(
-                            "Corrupted rust-src installation? Try re-adding rust-src component.",
-                        );
-                        let pos = format!("\t\t// {} : {}", fname.display(), loc.line);
+                        let pos = source_position(fname, loc, fmt);
                         safeprintln!("{}", color!(pos, OwoColorize::cyan));
-                        safeprintln!(
-                            "\t\t{}",
-                            color!(rust_line.trim_start(), OwoColorize::bright_red)
-                        );
+                        match file.get(loc.line as usize - 1) {
+                            _ if is_hidden_crate(fname, fmt) => {}
+                            Some(rust_line) => safeprintln!(
+                                "\t\t{}",
+                                color!(rust_line.trim_start(), OwoColorize::bright_red)
+                            ),
+                            None => {
+                                if fmt.verbosity > 0 {
+                                    esafeprintln!(
+                                        "{} has no line {}, corrupted rust-src installation? Try re-adding rust-src component.",
+                                        fname.display(),
+                                        loc.line
+                                    );
+                                }
+                            }
+                        }
                     }
                 }
                 Some((fname, None)) => {
                     if fmt.verbosity > 0 {
                         safeprintln!(
                             "\t\t{} {}",
-                            color!("//", OwoColorize::cyan),
+                            color!(fmt.source_prefix, OwoColorize::cyan),
                             color!(
                                 "Can't locate the file, please open a ticket with cargo-show-asm",
                                 OwoColorize::red
                             ),
                         );
                     }
-                    let pos = format!("\t\t// {} : {}", fname.display(), loc.line);
+                    let pos = source_position(fname, loc, fmt);
                     safeprintln!("{}", color!(pos, OwoColorize::cyan));
                 }
                 None => {
-                    panic!("DWARF file refers to an undefined location {loc:?}");
+                    if fmt.verbosity > 0 {
+                        esafeprintln!("DWARF file refers to an undefined location {loc:?}");
+                    }
+                    let pos = format!(
+                        "\t\t{} file #{} : {}",
+                        fmt.source_prefix, loc.file, loc.line
+                    );
+                    safeprintln!("{}", color!(pos, OwoColorize::cyan));
                 }
             }
             empty_line = false;
@@ -362,14 +1139,22 @@ fn dump_range(
             id,
         }) = line
         {
+            pages.clear();
+            let pressure = match block_regs.get(&ix) {
+                Some(n) => {
+                    let comment = format!("# ~{n} regs live");
+                    format!(" {}", color!(comment, OwoColorize::bright_black))
+                }
+                None => String::new(),
+            };
             match fmt.redundant_labels {
                 // We always include used labels and labels at the very
                 // beginning of the fragment - those are used for data declarations
                 _ if ix == 0 || used.contains(id) => {
-                    safeprintln!("{line}");
+                    safeprintln!("{line}{pressure}");
                 }
                 RedundantLabels::Keep => {
-                    safeprintln!("{line}");
+                    safeprintln!("{line}{pressure}");
                 }
                 RedundantLabels::Blanks => {
                     if !empty_line && *kind != LabelKind::Temp {
@@ -380,18 +1165,145 @@ fn dump_range(
                 RedundantLabels::Strip => {}
             }
         } else {
-            if fmt.simplify && line.boring() {
+            if fmt.simplify && hidden_by_simplify(line, object) && !(fmt.cfi && line.is_cfi()) {
+                continue;
+            }
+            if fmt.no_debug_values && line.is_debug_value() {
                 continue;
             }
 
             empty_line = false;
-            match fmt.name_display {
-                NameDisplay::Full => safeprintln!("{line:#}"),
-                NameDisplay::Short => safeprintln!("{line}"),
-                NameDisplay::Mangled => safeprintln!("{line:-}"),
+            let page_symbol = match line {
+                Statement::Instruction(instr) => {
+                    if let Some((reg, sym)) = page_address(instr) {
+                        pages.insert(reg, sym);
+                        None
+                    } else {
+                        pages.iter().find_map(|(base, sym)| {
+                            (page_offset(instr, base) == Some(*sym)).then_some(*sym)
+                        })
+                    }
+                }
+                _ => None,
+            }
+            .filter(|sym| demangle::global_reference(sym).is_some());
+            if let Some((entry, target)) = jump_tables.get(&ix) {
+                let entry = format!("[{entry}] =>");
+                safeprintln!(
+                    "\t{} {}",
+                    color!(entry, OwoColorize::bright_black),
+                    demangle::color_local_labels(target)
+                );
+                continue;
+            }
+            if fmt.offsets {
+                if let Statement::Instruction(Instruction { op, .. }) = line {
+                    if !op.starts_with('#') {
+                        safeprint!("{:>5} ", color!(insn_ix, OwoColorize::bright_black));
+                        insn_ix += 1;
+                    }
+                }
+            }
+            if fmt.nasm {
+                if let Statement::Directive(dir) = line {
+                    if let Some(text) = nasm::directive(dir) {
+                        safeprintln!("{}", color!(text, OwoColorize::bright_magenta));
+                        continue;
+                    }
+                }
+            }
+            if let (Statement::Instruction(instr), true) = (line, fmt.encoding) {
+                let hex = instr
+                    .encoding_bytes()
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|b| b.map_or_else(|| String::from("??"), |b| format!("{b:02x}")))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                safeprint!(
+                    "{}",
+                    color!(
+                        format_args!("{hex:width$} ", width = encoding_width * 3),
+                        OwoColorize::bright_black
+                    )
+                );
+            }
+            let rewritten = match (line, fmt.rewrite) {
+                (
+                    Statement::Instruction(Instruction {
+                        op,
+                        args: Some(args),
+                        ..
+                    }),
+                    _,
+                ) if fmt.nasm => Some(nasm::instruction(op, args)),
+                (
+                    Statement::Instruction(Instruction {
+                        op,
+                        args: Some(args),
+                        ..
+                    }),
+                    Some(style),
+                ) => operands::rewrite(op, args, style),
+                _ => None,
+            };
+            let rewritten = rewritten.as_ref().map(|(op, args)| {
+                Statement::Instruction(Instruction {
+                    op,
+                    sep: " ",
+                    args: Some(args),
+                    encoding: None,
+                })
+            });
+            let line = rewritten.as_ref().unwrap_or(line);
+            safeprint!("{}", line.styled(fmt));
+            let mangled = line.global_symbol().filter(|sym| {
+                fmt.show_mangled
+                    && fmt.name_display != NameDisplay::Mangled
+                    && demangle::demangled(sym).is_some()
+            });
+            match (tail_call_target(stmts, ix, globals), page_symbol, mangled) {
+                (_, _, Some(sym)) => safeprintln!(
+                    " {}",
+                    color!(format_args!("# {sym}"), OwoColorize::bright_black)
+                ),
+                (Some(target), _, _) => safeprintln!(
+                    " {} {}",
+                    color!("# tail call to", OwoColorize::cyan),
+                    demangle::contents(target, fmt.name_display)
+                ),
+                (_, Some(sym), _) => safeprintln!(
+                    " {} {}",
+                    color!("# =", OwoColorize::cyan),
+                    demangle::contents(sym, fmt.name_display)
+                ),
+                _ => match line
+                    .stack_access()
+                    .filter(|_| fmt.stack)
+                    .or_else(|| (fmt.highlight_nops && line.is_nop_like()).then_some("nop"))
+                {
+                    Some(access) => safeprintln!(
+                        " {}",
+                        color!(format_args!("# {access}"), OwoColorize::bright_yellow)
+                    ),
+                    None => safeprintln!(),
+                },
             }
         }
     }
+    if eh_tail > 0 {
+        let summary = format!("# exception handling ({eh_tail} lines hidden)");
+        safeprintln!("{}", color!(summary, OwoColorize::bright_black));
+    }
+    if fmt.simd_summary {
+        let (simd, total, widest) = simd_usage(stmts);
+        let summary = if simd == 0 {
+            format!("# SIMD: none of {total} instructions use vector registers")
+        } else {
+            format!("# SIMD: {simd} of {total} instructions, widest vector is {widest} bits")
+        };
+        safeprintln!("{}", color!(summary, OwoColorize::cyan));
+    }
 
     Ok(())
 }
@@ -436,7 +1348,12 @@ impl Source {
 // 4. rustc sources:
 //    /rustc/89e2160c4ca5808657ed55392620ed1dbbce78d1/compiler/rustc_span/src/span_encoding.rs
 //    $sysroot/lib/rustlib/rust-src/rust/compiler/rustc_span/src/span_encoding.rs
-fn locate_sources(sysroot: &Path, workspace: &Path, path: &Path) -> Option<(Source, PathBuf)> {
+fn locate_sources(
+    sysroot: &Path,
+    workspace: &Path,
+    path: &Path,
+    verbosity: usize,
+) -> Option<(Source, PathBuf)> {
     // a real file that simply exists
     if path.exists() {
         let source = if path.starts_with(workspace) {
@@ -453,7 +1370,7 @@ fn locate_sources(sysroot: &Path, workspace: &Path, path: &Path) -> Option<(Sour
             "You need to install rustc sources to be able to see the rust annotations, try\n\
                                        \trustup component add rust-src"
         );
-        std::process::exit(1);
+        crate::exit(exit_code::NO_RUST_SRC);
     };
 
     // /rustc/89e2160c4ca5808657ed55392620ed1dbbce78d1/compiler/rustc_span/src/span_encoding.rs
@@ -497,25 +1414,31 @@ fn locate_sources(sysroot: &Path, workspace: &Path, path: &Path) -> Option<(Sour
     }
 
     // cargo registry, Linux and macOS look for cargo/registry and .cargo/registry
-    if let Some(ix) = path
-        .components()
-        .position(|c| c.as_os_str() == "cargo" || c.as_os_str() == ".cargo")
-        .and_then(|ix| path.components().nth(ix).zip(Some(ix)))
-        .and_then(|(c, ix)| (c.as_os_str() == "registry").then_some(ix))
+    if let Some(ix) =
+        path.components()
+            .zip(path.components().skip(1))
+            .position(|(cargo, registry)| {
+                (cargo.as_os_str() == "cargo" || cargo.as_os_str() == ".cargo")
+                    && registry.as_os_str() == "registry"
+            })
     {
-        // It does what I want as far as *nix is concerned, might not work for Windows...
-        #[allow(deprecated)]
-        let mut source = std::env::home_dir().expect("No home dir?");
-
-        source.push(".cargo");
-        for part in path.components().skip(ix) {
+        let Some(mut source) = cargo_home() else {
+            if verbosity > 0 {
+                esafeprintln!(
+                    "Can't locate cargo home directory, set CARGO_HOME to resolve {path:?}"
+                );
+            }
+            return None;
+        };
+        for part in path.components().skip(ix + 1) {
             source.push(part);
         }
         if source.exists() {
             return Some((Source::External, source));
-        } else {
-            panic!(
-                "{path:?} looks like it can be a cargo registry reference but we failed to get it"
+        }
+        if verbosity > 0 {
+            esafeprintln!(
+                "{path:?} looks like it can be a cargo registry reference but we failed to find it in {source:?}"
             );
         }
     }
@@ -523,13 +1446,55 @@ fn locate_sources(sysroot: &Path, workspace: &Path, path: &Path) -> Option<(Sour
     None
 }
 
+/// Name of the registry crate a source file belongs to:
+/// `~/.cargo/registry/src/index.crates.io-6f17d22bba15001f/serde-1.0.200/src/de.rs` is `serde`
+fn registry_crate(path: &Path) -> Option<&str> {
+    let mut components = path.components().map(|c| c.as_os_str());
+    components.find(|c| *c == "registry")?;
+    if components.next()? != "src" {
+        return None;
+    }
+    let name_version = components.nth(1)?.to_str()?;
+    // crate names can contain dashes too, version is the part that starts with a digit
+    let (ix, _) = name_version
+        .match_indices('-')
+        .find(|(ix, _)| name_version[ix + 1..].starts_with(|c: char| c.is_ascii_digit()))?;
+    Some(&name_version[..ix])
+}
+
+/// Should source lines from this file be hidden with --hide-source-from?
+fn is_hidden_crate(path: &Path, fmt: &Format) -> bool {
+    !fmt.hide_source_from.is_empty()
+        && registry_crate(path).is_some_and(|name| {
+            fmt.hide_source_from
+                .iter()
+                .any(|hidden| hidden.replace('-', "_") == name.replace('-', "_"))
+        })
+}
+
 fn load_rust_sources(
     sysroot: &Path,
     workspace: &Path,
     statements: &[Statement],
     fmt: &Format,
+    asm_modified: Option<SystemTime>,
     files: &mut BTreeMap<u64, SourceFile>,
 ) {
+    if !statements
+        .iter()
+        .any(|line| matches!(line, Statement::Directive(Directive::File(_))))
+    {
+        // without .file directives .loc ones can't be resolved either,
+        // sources are loaded for every dumped file so warn only once
+        static WARNED: std::sync::Once = std::sync::Once::new();
+        WARNED.call_once(|| {
+            esafeprintln!(
+                "Warning: assembly has no debug info, Rust sources can't be shown. \
+                Was it disabled with -C debuginfo=0 or in the profile?"
+            );
+        });
+        return;
+    }
     for line in statements {
         if let Statement::Directive(Directive::File(f)) = line {
             files.entry(f.index).or_insert_with(|| {
@@ -538,11 +1503,28 @@ fn load_rust_sources(
                     safeprintln!("Reading file #{} {}", f.index, path.display());
                 }
 
-                if let Some((source, filepath)) = locate_sources(sysroot, workspace, &path) {
+                let located = locate_sources(sysroot, workspace, &path, fmt.verbosity);
+                if let Some((source, filepath)) = located {
                     if fmt.verbosity > 2 {
                         safeprintln!("Resolved name is {filepath:?}");
                     }
-                    let sources = std::fs::read_to_string(&filepath).expect("Can't read a file");
+                    if fmt.verbosity > 0 {
+                        let modified = std::fs::metadata(&filepath).and_then(|m| m.modified());
+                        if let (Ok(modified), Some(asm_modified)) = (modified, asm_modified) {
+                            if modified > asm_modified {
+                                esafeprintln!("{filepath:?} was modified after the assembly was generated, output might be stale");
+                            }
+                        }
+                    }
+                    let sources = match std::fs::read_to_string(&filepath) {
+                        Ok(sources) => sources,
+                        Err(err) => {
+                            if fmt.verbosity > 0 {
+                                esafeprintln!("Can't read {filepath:?}: {err}");
+                            }
+                            return (path, None);
+                        }
+                    };
                     if sources.is_empty() {
                         if fmt.verbosity > 0 {
                             safeprintln!("Ignoring empty file {filepath:?}!");
@@ -580,6 +1562,14 @@ pub struct Asm<'a> {
     workspace: &'a Path,
     sysroot: &'a Path,
     sources: RefCell<BTreeMap<u64, SourceFile>>,
+    /// mangled names of all the known items, used to detect tail calls
+    globals: RefCell<BTreeSet<String>>,
+    /// file names from the whole file, used by --source-json
+    file_names: RefCell<BTreeMap<u64, PathBuf>>,
+    /// modification time of the oldest asm file, used to detect stale output
+    modified: Option<SystemTime>,
+    /// Rust functions referenced by the context that are not in this file, for --context
+    external: RefCell<BTreeSet<String>>,
 }
 
 impl<'a> Asm<'a> {
@@ -588,8 +1578,22 @@ impl<'a> Asm<'a> {
             workspace,
             sysroot,
             sources: Default::default(),
+            globals: Default::default(),
+            file_names: Default::default(),
+            modified: None,
+            external: Default::default(),
         }
     }
+
+    /// Remember when the asm files were produced to warn about sources changed after that
+    #[must_use]
+    pub fn with_modified(mut self, paths: &[PathBuf]) -> Self {
+        self.modified = paths
+            .iter()
+            .filter_map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+            .min();
+        self
+    }
 }
 
 impl<'a> Dumpable for Asm<'a> {
@@ -599,12 +1603,71 @@ impl<'a> Dumpable for Asm<'a> {
         parse_file(contents)
     }
 
+    fn split_selected_lines<'l>(
+        contents: &'l str,
+        function: &str,
+    ) -> anyhow::Result<Option<Vec<Self::Line<'l>>>> {
+        parse_selected(contents, function)
+    }
+
     fn find_items(lines: &[Self::Line<'_>]) -> BTreeMap<Item, Range<usize>> {
         find_items(lines)
     }
 
+    fn body_hash(lines: &[Self::Line<'_>]) -> Option<u64> {
+        Some(body_hash(lines))
+    }
+
+    fn items_at(
+        lines: &[Self::Line<'_>],
+        items: &BTreeMap<Item, Range<usize>>,
+        file: &Path,
+        line: u64,
+    ) -> Vec<Item> {
+        items_at(lines, items, file, line)
+    }
+
+    fn external_references(&self) -> Vec<String> {
+        self.external.take().into_iter().collect()
+    }
+
     fn dump_range(&self, fmt: &Format, lines: &[Self::Line<'_>]) -> anyhow::Result<()> {
-        dump_range(&self.sources.borrow(), fmt, 0..lines.len(), lines)
+        if fmt.ndjson {
+            for line in lines {
+                safeprintln!("{}", line.to_json());
+            }
+            return Ok(());
+        }
+        if fmt.source_json {
+            let mut names = self.file_names.borrow().clone();
+            names.extend(file_names(lines));
+            safeprintln!("{}", source_json(&names, lines));
+            return Ok(());
+        }
+        dump_range(
+            &self.sources.borrow(),
+            &self.globals.borrow(),
+            fmt,
+            0..lines.len(),
+            lines,
+        )
+    }
+
+    fn dump_summary(&self, fmt: &Format, lines: &[Self::Line<'_>]) {
+        if fmt.dead_lines {
+            dump_dead_lines(&self.sources.borrow(), lines);
+        }
+        if fmt.calls {
+            let calls = called_functions(lines);
+            if calls.is_empty() {
+                esafeprintln!("No calls to other functions");
+            } else {
+                esafeprintln!("Calls {} function(s):", calls.len());
+                for name in calls {
+                    esafeprintln!("    {}", demangle::contents(name, fmt.name_display));
+                }
+            }
+        }
     }
 
     fn extra_context(
@@ -614,13 +1677,24 @@ impl<'a> Dumpable for Asm<'a> {
         range: Range<usize>,
         items: &BTreeMap<Item, Range<usize>>,
     ) -> Vec<Range<usize>> {
-        let mut res = get_context_for(fmt.context, lines, range.clone(), items);
-        if fmt.rust {
+        let mut res = get_context_for(
+            fmt.context,
+            lines,
+            range.clone(),
+            items,
+            &mut self.external.borrow_mut(),
+        );
+        *self.globals.borrow_mut() = items.keys().map(|i| i.mangled_name.clone()).collect();
+        if fmt.source_json {
+            *self.file_names.borrow_mut() = file_names(lines);
+        }
+        if fmt.rust || fmt.dead_lines {
             load_rust_sources(
                 self.sysroot,
                 self.workspace,
                 lines,
                 fmt,
+                self.modified,
                 &mut self.sources.borrow_mut(),
             );
         }
@@ -649,10 +1723,13 @@ impl<'a> Dumpable for Asm<'a> {
             while let Some(subset) = pending.pop() {
                 seen.insert(subset);
                 for s in &lines[subset] {
+                    // constants can refer to other constants: tables of string pointers
                     if let Statement::Instruction(Instruction {
                         args: Some(arg), ..
                     })
-                    | Statement::Directive(Directive::Generic(GenericDirective(arg))) = s
+                    | Statement::Directive(
+                        Directive::Generic(GenericDirective(arg)) | Directive::Data(_, arg),
+                    ) = s
                     {
                         for label in crate::demangle::local_labels(arg) {
                             if let Some(constant_range) = scan_constant(label, &constants, lines) {
@@ -683,3 +1760,478 @@ impl<'a> Dumpable for Asm<'a> {
         res
     }
 }
+
+#[test]
+fn identical_bodies_have_same_hash() {
+    let a =
+        parse_file("foo:\n\t.loc\t1 2 3\n\ttestq\t%rdi, %rdi\n\tje\t.LBB1_2\n.LBB1_2:\n\tretq\n")
+            .unwrap();
+    let b =
+        parse_file("bar:\n\t.loc\t1 5 3\n\ttestq\t%rdi, %rdi\n\tje\t.LBB7_2\n.LBB7_2:\n\tretq\n")
+            .unwrap();
+    let c = parse_file("baz:\n\ttestq\t%rsi, %rsi\n\tje\t.LBB7_2\n.LBB7_2:\n\tretq\n").unwrap();
+    assert_eq!(body_hash(&a), body_hash(&b));
+    assert_ne!(body_hash(&a), body_hash(&c));
+}
+
+#[test]
+fn size_directive_closes_item() {
+    let stmts = parse_file(
+        "\t.section\t.text.foo,\"ax\",@progbits\n\t.globl\tfoo\nfoo:\n\tretq\n\t.size\tfoo, .-foo\n\t.section\t.rodata,\"a\",@progbits\n\t.byte\t1\n",
+    )
+    .unwrap();
+    let items = find_items(&stmts);
+    let (item, range) = items.iter().next().unwrap();
+    assert_eq!(item.name, "foo");
+    assert_eq!(item.len, 2);
+    assert_eq!(*range, 0..4);
+}
+
+#[test]
+fn items_without_end_markers() {
+    // hand written code: no .Lfunc_end labels and no .size directives
+    const FIXTURE: &str = "\t.section\t.text.foo,\"ax\",@progbits
+\t.globl\tfoo
+\t.type\tfoo,@function
+foo:
+\tmovl\t$1, %eax
+\tretq
+\t.section\t.text.bar,\"ax\",@progbits
+\t.globl\tbar
+\t.type\tbar,@function
+bar:
+\txorl\t%eax, %eax
+\tretq
+\t.globl\tbaz
+\t.type\tbaz,@function
+baz:
+\tretq
+";
+    let stmts = parse_file(FIXTURE).unwrap();
+    let items = find_items(&stmts);
+    let ranges = items
+        .iter()
+        .map(|(item, range)| (item.name.as_str(), (item.len, range.clone())))
+        .collect::<BTreeMap<_, _>>();
+    assert_eq!(ranges["foo"], (3, 0..6));
+    assert_eq!(ranges["bar"], (3, 6..12));
+    assert_eq!(ranges["baz"], (2, 12..16));
+}
+
+#[test]
+fn only_selected_functions_are_parsed() {
+    const ASM: &str = "\t.file\t\"lib.rs\"
+\t.section\t.text._ZN3foo3bar17h0123456789abcdefE,\"ax\",@progbits
+_ZN3foo3bar17h0123456789abcdefE:
+\tretq
+.Lfunc_end0:
+\t.section\t.text._ZN3foo3baz17h0123456789abcdefE,\"ax\",@progbits
+_ZN3foo3baz17h0123456789abcdefE:
+\t.file\t1 \"src\" \"lib.rs\"
+\tmovl\t$1, %eax
+\tretq
+.Lfunc_end1:
+";
+    let stmts = parse_selected(ASM, "baz").unwrap().unwrap();
+    let items = find_items(&stmts);
+    assert_eq!(items.len(), 1);
+    assert_eq!(items.keys().next().unwrap().name, "foo::baz");
+    // both .file directives are kept, nothing from foo::bar
+    assert_eq!(stmts.len(), 7);
+    assert!(parse_selected(ASM, "quux").unwrap().is_none());
+}
+
+#[test]
+fn merged_functions_are_marked() {
+    let stmts = parse_file(
+        "\t.globl\t_ZN13sample_merged3two17h0afab563317f9d7bE\n.set _ZN13sample_merged3two17h0afab563317f9d7bE, _ZN13sample_merged12one_plus_one17h408b56cb936d6f10E\n",
+    )
+    .unwrap();
+    let items = find_items(&stmts);
+    let merged = items
+        .keys()
+        .map(|i| (i.name.as_str(), i.merged))
+        .collect::<Vec<_>>();
+    assert_eq!(merged, [("sample_merged::two", true)]);
+}
+
+#[test]
+fn aligned_constants_are_scanned_fully() {
+    let stmts = parse_file(
+        ".Lanon.1:\n\t.ascii\t\"hello\"\n\t.p2align\t3, 0x0\n\t.quad\t42\n\t.zero\t4\n\t.p2align\t4, 0x0\n\t.section\t.text.foo,\"ax\",@progbits\n",
+    )
+    .unwrap();
+    let sections = BTreeMap::from([(".Lanon.1", 0)]);
+    let range = scan_constant(".Lanon.1", &sections, &stmts).unwrap();
+    assert_eq!((range.start, range.end), (0, 5));
+}
+
+#[test]
+fn parse_errors_point_to_the_line() {
+    // unknown lines are kept as is, but every line must end with a newline
+    let err = parse_file("foo:\n\tmovl\t$1, %eax\n\tretq").unwrap_err();
+    let msg = err.to_string();
+    assert!(
+        msg.starts_with("Couldn't parse line 3 of the .s file:\n\tretq\n"),
+        "{msg}"
+    );
+}
+
+#[test]
+fn statics_are_found() {
+    let stmts = parse_file(
+        "\t.type\tTABLE,@object\n\t.section\t.rodata.TABLE,\"a\",@progbits\n\t.globl\tTABLE\n\t.p2align\t2, 0x0\nTABLE:\n\t.long\t1\n\t.long\t2\n\t.size\tTABLE, 8\n\t.section\t.bss._ZN4test7COUNTER17h0123456789abcdefE,\"aw\",@nobits\n\t.p2align\t3, 0x0\n_ZN4test7COUNTER17h0123456789abcdefE:\n\t.zero\t8\n",
+    )
+    .unwrap();
+    let items = find_items(&stmts);
+    let found = items
+        .iter()
+        .map(|(item, range)| (item.name.as_str(), (item.object, range.clone())))
+        .collect::<BTreeMap<_, _>>();
+    assert_eq!(found["TABLE"], (true, 1..7));
+    assert_eq!(found["test::COUNTER"], (true, 8..12));
+}
+
+#[test]
+fn static_contents_are_kept_with_simplify() {
+    let stmts = parse_file(
+        "\t.type\tTABLE,@object\n\t.section\t.rodata.TABLE,\"a\",@progbits\n\t.globl\tTABLE\n\t.p2align\t2, 0x0\nTABLE:\n\t.long\t1\n\t.long\t2\n\t.size\tTABLE, 8\n\t.section\t.text.foo,\"ax\",@progbits\nfoo:\n\tret\n\t.long\t3\n",
+    )
+    .unwrap();
+    let opts = crate::opts::options()
+        .run_inner(&["--simplify", "TABLE"])
+        .unwrap();
+    let items = find_items(&stmts);
+    let data = |name: &str, object: bool| {
+        let (_, range) = items.iter().find(|(item, _)| item.name == name).unwrap();
+        let shown = stmts[range.clone()]
+            .iter()
+            .filter(|s| !hidden_by_simplify(s, object))
+            .filter(|s| matches!(s, Statement::Directive(Directive::Data(_, _))))
+            .count();
+        dump_range(
+            &BTreeMap::new(),
+            &BTreeSet::new(),
+            &opts.format,
+            range.clone(),
+            &stmts,
+        )
+        .unwrap();
+        shown
+    };
+    assert_eq!(data("TABLE", true), 2);
+    assert_eq!(data("foo", false), 0);
+}
+
+#[test]
+fn static_constructors_are_found() {
+    let stmts = parse_file(
+        "\t.section\t__TEXT,__text,regular,pure_instructions\n_init_logger:\n\tret\n\t.section\t__DATA,__mod_init_func,mod_init_funcs\n\t.p2align\t3\n\t.quad\t_init_logger\n\t.section\t.init_array.00099,\"aw\",@init_array\n\t.p2align\t3\n\t.quad\t_init_logger\n",
+    )
+    .unwrap();
+    let items = find_items(&stmts);
+    let found = items
+        .iter()
+        .map(|(item, range)| (item.name.as_str(), range.clone()))
+        .collect::<BTreeMap<_, _>>();
+    assert_eq!(found[".init_array.00099"], 6..9);
+    assert_eq!(found["__mod_init_func"], 3..6);
+    assert_eq!(found["_init_logger"], 1..3);
+}
+
+#[test]
+fn weak_functions_are_marked() {
+    let elf = parse_file(
+        "\t.section\t.text._ZN4weak3foo17h0123456789abcdefE,\"ax\",@progbits\n\t.weak\t_ZN4weak3foo17h0123456789abcdefE\n\t.type\t_ZN4weak3foo17h0123456789abcdefE,@function\n_ZN4weak3foo17h0123456789abcdefE:\n\tretq\n.Lfunc_end0:\n",
+    )
+    .unwrap();
+    let macho = parse_file(
+        "\t.section\t__TEXT,__text,regular,pure_instructions\n\t.globl\t__ZN4weak3foo17h0123456789abcdefE\n\t.weak_definition\t__ZN4weak3foo17h0123456789abcdefE\n__ZN4weak3foo17h0123456789abcdefE:\n\tret\nLfunc_end0:\n",
+    )
+    .unwrap();
+    assert_eq!(
+        macho[2],
+        Statement::Directive(Directive::Weak(
+            "weak_definition",
+            "__ZN4weak3foo17h0123456789abcdefE"
+        ))
+    );
+    for stmts in [elf, macho] {
+        let items = find_items(&stmts);
+        let weak = items
+            .keys()
+            .map(|i| (i.name.as_str(), i.weak))
+            .collect::<Vec<_>>();
+        assert_eq!(weak, [("weak::foo", true)]);
+    }
+}
+
+#[test]
+fn intel_rip_relative_constants_are_included() {
+    let stmts = parse_file(
+        "\t.section\t.text.foo,\"ax\",@progbits\nfoo:\n\tlea\trax, [rip + .Lanon.123]\n\tmov\trcx, qword ptr [rip + .LCPI0_0+8]\n\tret\n.Lfunc_end0:\n\t.section\t.rodata..Lanon.123,\"a\",@progbits\n.Lanon.123:\n\t.quad\t.Lanon.7\n\t.section\t.rodata..Lanon.7,\"a\",@progbits\n.Lanon.7:\n\t.ascii\t\"hello\"\n\t.section\t.rodata.cst16,\"aM\",@progbits,16\n.LCPI0_0:\n\t.quad\t1\n\t.quad\t2\n",
+    )
+    .unwrap();
+    let opts = crate::opts::options()
+        .run_inner(&["--include-constants", "foo"])
+        .unwrap();
+    let items = find_items(&stmts);
+    let range = items.values().next().unwrap().clone();
+    let asm = Asm::new(Path::new("."), Path::new("."));
+    let context = asm.extra_context(&opts.format, &stmts, range, &items);
+    assert_eq!(context, [7..9, 10..12, 13..16]);
+}
+
+#[test]
+fn instructions_are_grouped_by_source_line() {
+    let stmts = parse_file(
+        "foo:\n\t.loc\t1 3 0\n\tmov\trax, rdi\n\t.loc\t1 4 0\n\tcall\t_ZN4core3fmt5write17h0123456789abcdefE\n\t.loc\t1 3 0\n\tadd\trax, 1\n\t.loc\t1 0 0\n\tret\n",
+    )
+    .unwrap();
+    let names = BTreeMap::from([(1, PathBuf::from("src/lib.rs"))]);
+    assert_eq!(
+        source_json(&names, &stmts),
+        serde_json::json!({
+            "locations": [
+                {
+                    "file": "src/lib.rs",
+                    "line": 3,
+                    "instructions": [
+                        { "text": "mov rax, rdi", "references": [] },
+                        { "text": "add rax, 1", "references": [] },
+                    ],
+                },
+                {
+                    "file": "src/lib.rs",
+                    "line": 4,
+                    "instructions": [{
+                        "text": "call _ZN4core3fmt5write17h0123456789abcdefE",
+                        "references": [{
+                            "mangled": "_ZN4core3fmt5write17h0123456789abcdefE",
+                            "name": "core::fmt::write",
+                        }],
+                    }],
+                },
+            ],
+            "no source": [{ "text": "ret", "references": [] }],
+        })
+    );
+}
+
+#[test]
+fn recent_source_lines_are_not_repeated() {
+    let stmts = parse_file(
+        "\t.loc\t1 3 0\n\t.loc\t1 4 0\n\t.loc\t1 3 0\n\t.loc\t1 4 0\n\t.loc\t1 5 0\n\t.loc\t1 6 0\n\t.loc\t1 3 0\n",
+    )
+    .unwrap();
+    let shown = |window| {
+        let mut recent = BTreeMap::new();
+        stmts
+            .iter()
+            .enumerate()
+            .filter_map(|(ix, stmt)| match stmt {
+                Statement::Directive(Directive::Loc(loc)) => {
+                    (!shown_recently(&mut recent, window, loc, ix)).then_some(loc.line)
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(shown(0), [3, 4, 3, 4, 5, 6, 3]);
+    assert_eq!(shown(2), [3, 4, 5, 6, 3]);
+    assert_eq!(shown(10), [3, 4, 5, 6]);
+
+    // the same line inlined into a different CodeView function is shown again
+    let stmts = parse_file("\t.cv_loc\t0 1 3 0\n\t.cv_loc\t1 1 3 0\n\t.cv_loc\t0 1 3 0\n").unwrap();
+    let mut recent = BTreeMap::new();
+    let shown = stmts
+        .iter()
+        .enumerate()
+        .filter(|(ix, stmt)| match stmt {
+            Statement::Directive(Directive::Loc(loc)) => !shown_recently(&mut recent, 10, loc, *ix),
+            _ => false,
+        })
+        .count();
+    assert_eq!(shown, 2);
+}
+
+#[test]
+fn end_markers_close_items() {
+    // cold code placed in a different section still belongs to the function
+    const FIXTURE: &str = "\t.section\t.text.foo,\"ax\",@progbits
+\t.globl\t_ZN4test3foo17h0123456789abcdefE
+\t.type\t_ZN4test3foo17h0123456789abcdefE,@function
+_ZN4test3foo17h0123456789abcdefE:
+\ttestl\t%edi, %edi
+\tjne\t.LBB0_1
+\tretq
+\t.section\t.text.unlikely.foo,\"ax\",@progbits
+.LBB0_1:
+\tud2
+.Lfunc_end0:
+\t.size\t_ZN4test3foo17h0123456789abcdefE, .Lfunc_end0-_ZN4test3foo17h0123456789abcdefE
+";
+    let stmts = parse_file(FIXTURE).unwrap();
+    let items = find_items(&stmts);
+    let (item, range) = items.iter().next().unwrap();
+    assert_eq!(items.len(), 1);
+    assert_eq!((item.name.as_str(), range.clone()), ("test::foo", 0..10));
+}
+
+#[test]
+fn loc_with_unknown_file_is_not_fatal() {
+    let stmts = parse_file("foo:\n\t.loc\t42 2 3\n\tretq\n").unwrap();
+    let opts = crate::opts::options()
+        .run_inner(&["--rust", "foo"])
+        .unwrap();
+    dump_range(
+        &BTreeMap::new(),
+        &BTreeSet::new(),
+        &opts.format,
+        0..stmts.len(),
+        &stmts,
+    )
+    .unwrap();
+}
+
+#[test]
+fn jump_tables_are_detected() {
+    let stmts = parse_file(
+        ".LJTI0_0:\n\t.long\t.LBB0_2-.LJTI0_0\n\t.long\t.LBB0_5-.LJTI0_0\n\t.long\t.LBB0_2-.LJTI0_0\n.L__unnamed_1:\n\t.quad\t.Lanon.0\n\t.quad\t.Lanon.1\n",
+    )
+    .unwrap();
+    let entries = jump_table_entries(&stmts);
+    assert_eq!(
+        entries.into_iter().collect::<Vec<_>>(),
+        [
+            (1, (0, ".LBB0_2")),
+            (2, (1, ".LBB0_5")),
+            (3, (2, ".LBB0_2"))
+        ]
+    );
+}
+
+#[test]
+fn registers_are_counted_per_block() {
+    let stmts = parse_file(
+        "foo:\n\tmovq\t%rdi, %rax\n.LBB0_1:\n\taddq\t%rsi, %rax\n\taddq\t(%rip), %rax\n\tjne\t.LBB0_1\n.LBB0_2:\n\tmovl\t%eax, %ecx\n\tmovq\t%rax, %rdx\n\tretq\n",
+    )
+    .unwrap();
+    assert_eq!(
+        block_registers(&stmts).into_iter().collect::<Vec<_>>(),
+        [(2, 2), (6, 4)]
+    );
+
+    // .Ltmp labels mark positions for debug info and exception handling, not blocks
+    let stmts = parse_file(
+        "foo:\n.LBB0_1:\n\tmovq\t%rdi, %rax\n.Ltmp0:\n\tcallq\tbar\n.Ltmp1:\n\taddq\t%rsi, %rdx\n\tretq\n",
+    )
+    .unwrap();
+    assert_eq!(
+        block_registers(&stmts).into_iter().collect::<Vec<_>>(),
+        [(1, 4)]
+    );
+}
+
+#[test]
+fn simd_usage_is_counted() {
+    let stmts = parse_file(
+        "foo:\n\tvmovups\tymm0, ymmword ptr [rdi]\n\tvaddps\tymm0, ymm0, ymmword ptr [rsi]\n\taddsd\txmm0, xmm1\n\tmovq\trax, xmm0\n\tret\n",
+    )
+    .unwrap();
+    assert_eq!(simd_usage(&stmts), (2, 5, 256));
+    let stmts = parse_file(
+        "baz:\n\tpminsd\txmm0, xmm1\n\tpabsd\txmm0, xmm0\n\tvbroadcastss\tymm0, xmm0\n\tvfmadd213sd\txmm0, xmm1, xmm2\n",
+    )
+    .unwrap();
+    assert_eq!(simd_usage(&stmts), (3, 4, 256));
+    let stmts = parse_file("bar:\n\tadd\tv0.4s, v0.4s, v1.4s\n\tfadd\ts0, s0, s1\n").unwrap();
+    assert_eq!(simd_usage(&stmts), (1, 2, 128));
+}
+
+#[test]
+fn called_functions_are_deduplicated() {
+    let stmts = parse_file(
+        "foo:\n\tcall\t_ZN3foo3bar17h0123456789abcdefE\n\tcall\t_ZN3foo3bar17h0123456789abcdefE@PLT\n\tcall\t.LBB0_1\n\tjmp\t_ZN3foo3baz17h0123456789abcdefE\n\tret\n",
+    )
+    .unwrap();
+    assert_eq!(
+        called_functions(&stmts).into_iter().collect::<Vec<_>>(),
+        ["_ZN3foo3bar17h0123456789abcdefE"]
+    );
+}
+
+#[test]
+fn registry_crate_is_detected() {
+    let path = |p| registry_crate(Path::new(p));
+    assert_eq!(
+        path("/home/user/.cargo/registry/src/index.crates.io-6f17d22bba15001f/serde-1.0.200/src/de.rs"),
+        Some("serde")
+    );
+    assert_eq!(
+        path("/home/user/.cargo/registry/src/github.com-1ecc6299db9ec823/wasm-bindgen-0.2.92/src/lib.rs"),
+        Some("wasm-bindgen")
+    );
+    assert_eq!(path("/home/user/work/foo/src/lib.rs"), None);
+}
+
+#[test]
+fn page_address_pairs_are_matched() {
+    let adrp = |args| Instruction {
+        op: "adrp",
+        sep: "\t",
+        args: Some(args),
+        encoding: None,
+    };
+    let instr = |op, args| Instruction {
+        op,
+        sep: "\t",
+        args: Some(args),
+        encoding: None,
+    };
+    assert_eq!(page_address(&adrp("x0, _foo@PAGE")), Some(("x0", "_foo")));
+    assert_eq!(page_address(&adrp("x8, :got:foo")), Some(("x8", "foo")));
+    assert_eq!(
+        page_offset(&instr("add", "x0, x0, _foo@PAGEOFF"), "x0"),
+        Some("_foo")
+    );
+    assert_eq!(
+        page_offset(&instr("ldr", "x8, [x8, :got_lo12:foo]"), "x8"),
+        Some("foo")
+    );
+    assert_eq!(page_offset(&instr("add", "x0, x1, :lo12:foo"), "x0"), None);
+    assert_eq!(page_offset(&instr("add", "x0, x0, #16"), "x0"), None);
+}
+
+#[test]
+fn items_are_found_by_location() {
+    let stmts = parse_file(
+        "\t.file\t1 \"/home/user/demo\" \"src/lib.rs\"\n\t.file\t2 \"/rustc/library/core/src/num/mod.rs\"\n\t.section\t.text._ZN4demo3foo17h0123456789abcdefE,\"ax\",@progbits\n_ZN4demo3foo17h0123456789abcdefE:\n\t.loc\t1 4 5\n\tretq\n.Lfunc_end0:\n\t.section\t.text._ZN4demo3bar17h0123456789abcdefE,\"ax\",@progbits\n_ZN4demo3bar17h0123456789abcdefE:\n\t.loc\t1 9 5\n\t.loc\t2 4 5\n\tretq\n.Lfunc_end1:\n",
+    )
+    .unwrap();
+    let items = find_items(&stmts);
+    let names = |file: &str, line| {
+        items_at(&stmts, &items, Path::new(file), line)
+            .into_iter()
+            .map(|item| item.name)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(names("src/lib.rs", 4), ["demo::foo"]);
+    assert_eq!(names("lib.rs", 9), ["demo::bar"]);
+    assert_eq!(names("num/mod.rs", 4), ["demo::bar"]);
+    assert!(names("src/lib.rs", 5).is_empty());
+    assert!(names("rc/lib.rs", 4).is_empty());
+}
+
+#[test]
+fn only_final_jumps_are_tail_calls() {
+    let stmts = parse_file(
+        "_ZN4demo3foo17h0123456789abcdefE:\n\ttestq\t%rdi, %rdi\n\tjmp\t_ZN4demo3bar17h0123456789abcdefE\n\tretq\n.LBB0_2:\n\tjmp\t_ZN4demo3bar17h0123456789abcdefE\n.LBB0_3:\n\t.cfi_def_cfa_offset 8\n\tjmp\t_ZN4demo3bar17h0123456789abcdefE\n.Lfunc_end0:\n",
+    )
+    .unwrap();
+    let globals = BTreeSet::from([String::from("_ZN4demo3bar17h0123456789abcdefE")]);
+    let tail_calls = (0..stmts.len())
+        .filter(|&ix| tail_call_target(&stmts, ix, &globals).is_some())
+        .collect::<Vec<_>>();
+    assert_eq!(tail_calls, [5, 8]);
+}
@@ -0,0 +1,107 @@
+//! Experimental rewriting of x86 output towards NASM syntax, for `--nasm`
+//!
+//! Only common cases are covered: sized memory operands, rip relative addressing, `offset`,
+//! sections, symbol visibility, alignment and data declarations. The result is a starting point
+//! for manual editing rather than something NASM is guaranteed to accept as is.
+
+use super::operands;
+use super::statements::Directive;
+use crate::opts::OutputStyle;
+
+const SIZES: &[&str] = &[
+    "byte", "word", "dword", "qword", "tbyte", "xmmword", "ymmword", "zmmword",
+];
+
+/// Rewrite instruction: `qword ptr [rdi]` becomes `qword [rdi]`, `[rip + sym]` becomes
+/// `[rel sym]` and `offset sym` becomes `sym`. AT&T instructions are converted to Intel first
+pub(crate) fn instruction(op: &str, args: &str) -> (String, String) {
+    let (op, args) = operands::rewrite(op, args, OutputStyle::Intel)
+        .unwrap_or_else(|| (op.to_owned(), args.to_owned()));
+    let op = match op.as_str() {
+        "movabs" => "mov".to_owned(),
+        _ => op,
+    };
+    let args = args
+        .split(", ")
+        .map(|operand| {
+            let operand = operand.strip_prefix("offset ").unwrap_or(operand);
+            let operand = match operand.split_once(" ptr ") {
+                Some((size, rest)) if SIZES.contains(&size) => format!("{size} {rest}"),
+                _ => operand.to_owned(),
+            };
+            operand.replace("[rip + ", "[rel ")
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    (op, args)
+}
+
+/// NASM version of a directive, `None` if there's no direct equivalent
+pub(crate) fn directive(directive: &Directive) -> Option<String> {
+    match directive {
+        Directive::SectionStart(section) => {
+            let name = section.split(',').next().unwrap_or(section).trim();
+            Some(format!("section {name}"))
+        }
+        Directive::Global(name) => Some(format!("global {name}")),
+        Directive::Align(align) => Some(format!("\talign {}", align.bytes()?)),
+        Directive::Data(kind, value) => {
+            let res = match *kind {
+                "byte" => format!("\tdb {value}"),
+                "short" | "2byte" | "value" => format!("\tdw {value}"),
+                "long" | "4byte" | "int" => format!("\tdd {value}"),
+                "quad" | "8byte" => format!("\tdq {value}"),
+                "zero" | "space" => format!("\ttimes {value} db 0"),
+                "ascii" => format!("\tdb {}", string(value)?),
+                "asciz" => format!("\tdb {}, 0", string(value)?),
+                _ => return None,
+            };
+            Some(res)
+        }
+        _ => None,
+    }
+}
+
+/// GAS string literal as NASM backquoted string, both understand the same C style escapes
+fn string(value: &str) -> Option<String> {
+    let body = value.strip_prefix('"')?.strip_suffix('"')?;
+    Some(format!("`{}`", body.replace('`', "\\`")))
+}
+
+#[test]
+fn instructions_are_rewritten() {
+    let r = |op, args| instruction(op, args);
+    assert_eq!(
+        r("mov", "rax, qword ptr [rdi + 8]"),
+        ("mov".into(), "rax, qword [rdi + 8]".into())
+    );
+    assert_eq!(
+        r("lea", "rdi, [rip + .Lanon.1]"),
+        ("lea".into(), "rdi, [rel .Lanon.1]".into())
+    );
+    assert_eq!(
+        r("movabs", "rax, offset foo"),
+        ("mov".into(), "rax, foo".into())
+    );
+    assert_eq!(
+        r("movl", "$42, -8(%rbp)"),
+        ("mov".into(), "dword [rbp - 8], 42".into())
+    );
+}
+
+#[test]
+fn directives_are_rewritten() {
+    assert_eq!(
+        directive(&Directive::SectionStart(".text.foo,\"ax\",@progbits")),
+        Some("section .text.foo".into())
+    );
+    assert_eq!(
+        directive(&Directive::Data("asciz", "\"hi\\n\"")),
+        Some("\tdb `hi\\n`, 0".into())
+    );
+    assert_eq!(
+        directive(&Directive::Data("quad", "foo")),
+        Some("\tdq foo".into())
+    );
+    assert_eq!(directive(&Directive::Cfi("cfi_startproc")), None);
+}
@@ -0,0 +1,528 @@
+//! A limited model of x86 instruction operands, used to rewrite instructions between
+//! AT&T and Intel syntax when the compiler can't be asked to produce the desired one.
+//!
+//! Only common forms are handled: registers, immediates, symbols and memory operands with
+//! base, index, scale and displacement. Anything else is left untouched.
+
+use crate::opts::OutputStyle;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Size {
+    Byte,
+    Word,
+    Dword,
+    Qword,
+}
+
+impl Size {
+    fn from_suffix(suffix: char) -> Option<Self> {
+        match suffix {
+            'b' => Some(Size::Byte),
+            'w' => Some(Size::Word),
+            'l' => Some(Size::Dword),
+            'q' => Some(Size::Qword),
+            _ => None,
+        }
+    }
+
+    fn from_ptr(ptr: &str) -> Option<Self> {
+        match ptr {
+            "byte" => Some(Size::Byte),
+            "word" => Some(Size::Word),
+            "dword" => Some(Size::Dword),
+            "qword" => Some(Size::Qword),
+            _ => None,
+        }
+    }
+
+    fn suffix(self) -> char {
+        match self {
+            Size::Byte => 'b',
+            Size::Word => 'w',
+            Size::Dword => 'l',
+            Size::Qword => 'q',
+        }
+    }
+
+    fn ptr(self) -> &'static str {
+        match self {
+            Size::Byte => "byte",
+            Size::Word => "word",
+            Size::Dword => "dword",
+            Size::Qword => "qword",
+        }
+    }
+}
+
+/// Instructions that take AT&T size suffix
+const SIZED_OPS: &[&str] = &[
+    "mov", "add", "sub", "and", "or", "xor", "cmp", "test", "lea", "push", "pop", "inc", "dec",
+    "neg", "not", "shl", "shr", "sar", "imul", "adc", "sbb", "xchg", "adcx", "adox",
+];
+
+/// Size of a general purpose register, `None` if this is not one
+fn gpr_size(reg: &str) -> Option<Size> {
+    const QWORD: &[&str] = &["rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp"];
+    const DWORD: &[&str] = &["eax", "ebx", "ecx", "edx", "esi", "edi", "ebp", "esp"];
+    const WORD: &[&str] = &["ax", "bx", "cx", "dx", "si", "di", "bp", "sp"];
+    const BYTE: &[&str] = &[
+        "al", "bl", "cl", "dl", "ah", "bh", "ch", "dh", "sil", "dil", "bpl", "spl",
+    ];
+    if QWORD.contains(&reg) {
+        return Some(Size::Qword);
+    } else if DWORD.contains(&reg) {
+        return Some(Size::Dword);
+    } else if WORD.contains(&reg) {
+        return Some(Size::Word);
+    } else if BYTE.contains(&reg) {
+        return Some(Size::Byte);
+    }
+    let num = reg.strip_prefix('r')?;
+    let digits = num.trim_end_matches(['d', 'w', 'b']);
+    if !matches!(digits.parse::<u8>(), Ok(8..=15)) {
+        return None;
+    }
+    match &num[digits.len()..] {
+        "" => Some(Size::Qword),
+        "d" => Some(Size::Dword),
+        "w" => Some(Size::Word),
+        "b" => Some(Size::Byte),
+        _ => None,
+    }
+}
+
+fn is_vector_register(reg: &str) -> bool {
+    ["xmm", "ymm", "zmm"]
+        .iter()
+        .any(|p| reg.strip_prefix(p).is_some_and(|n| n.parse::<u8>().is_ok()))
+}
+
+fn is_register(reg: &str) -> bool {
+    gpr_size(reg).is_some()
+        || reg == "rip"
+        || ["cs", "ds", "es", "fs", "gs", "ss"].contains(&reg)
+        || is_vector_register(reg)
+}
+
+/// Bare symbols are only used alone by calls and jumps, next to other operands they are
+/// registers of some other architecture such as aarch64 `x0` and the instruction is left alone
+fn is_x86(operands: &[Operand]) -> bool {
+    operands.len() == 1 || !operands.iter().any(|o| matches!(o, Operand::Sym(_)))
+}
+
+/// Symbol names, labels and numbers
+fn is_plain(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '$' | '@' | '+' | '-'))
+}
+
+fn is_number(s: &str) -> bool {
+    s.strip_prefix('-')
+        .unwrap_or(s)
+        .starts_with(|c: char| c.is_ascii_digit())
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct Mem<'a> {
+    size: Option<Size>,
+    segment: Option<&'a str>,
+    base: Option<&'a str>,
+    index: Option<&'a str>,
+    scale: Option<&'a str>,
+    /// Displacement, symbolic or numeric, without the sign
+    disp: Option<&'a str>,
+    negative: bool,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Operand<'a> {
+    Reg(&'a str),
+    Imm(&'a str),
+    Mem(Mem<'a>),
+    /// Bare symbol or label, used by calls and jumps
+    Sym(&'a str),
+}
+
+/// Split operands by commas, ignoring the ones inside of brackets or parens
+pub(super) fn split_operands(args: &str) -> Vec<&str> {
+    let mut res = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (ix, c) in args.char_indices() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                res.push(args[start..ix].trim());
+                start = ix + 1;
+            }
+            _ => {}
+        }
+    }
+    res.push(args[start..].trim());
+    res
+}
+
+fn parse_intel(op: &str) -> Option<Operand<'_>> {
+    let Some((prefix, rest)) = op.split_once('[') else {
+        return Some(if is_register(op) {
+            Operand::Reg(op)
+        } else if is_number(op) {
+            Operand::Imm(op)
+        } else if is_plain(op) {
+            Operand::Sym(op)
+        } else {
+            return None;
+        });
+    };
+    let body = rest.strip_suffix(']')?;
+    let mut mem = Mem::default();
+
+    let mut prefix = prefix.trim();
+    if let Some((ptr, rest)) = prefix.split_once(" ptr") {
+        mem.size = Some(Size::from_ptr(ptr)?);
+        prefix = rest.trim();
+    }
+    if !prefix.is_empty() {
+        let segment = prefix.strip_suffix(':')?;
+        if !is_register(segment) {
+            return None;
+        }
+        mem.segment = Some(segment);
+    }
+
+    // LLVM prints terms as "base + scale*index + disp", negative displacement as "- disp"
+    let mut negative = false;
+    for term in body.split_whitespace() {
+        match term {
+            "+" => negative = false,
+            "-" => negative = true,
+            _ if negative => {
+                if mem.disp.is_some() || !is_plain(term) {
+                    return None;
+                }
+                mem.negative = true;
+                mem.disp = Some(term);
+            }
+            _ => {
+                if let Some((scale, index)) = term.split_once('*') {
+                    if !is_register(index) || mem.index.is_some() {
+                        return None;
+                    }
+                    mem.scale = Some(scale);
+                    mem.index = Some(index);
+                } else if is_register(term) {
+                    if mem.base.is_none() {
+                        mem.base = Some(term);
+                    } else if mem.index.is_none() {
+                        mem.index = Some(term);
+                    } else {
+                        return None;
+                    }
+                } else if mem.disp.is_none() && is_plain(term) {
+                    mem.disp = Some(term);
+                } else {
+                    return None;
+                }
+            }
+        }
+    }
+    Some(Operand::Mem(mem))
+}
+
+/// Optional AT&T register inside of a memory operand
+fn att_mem_reg(reg: Option<&str>) -> Option<Option<&str>> {
+    match reg {
+        None | Some("") => Some(None),
+        Some(r) => {
+            let r = r.strip_prefix('%')?;
+            is_register(r).then_some(Some(r))
+        }
+    }
+}
+
+fn parse_att(op: &str) -> Option<Operand<'_>> {
+    let op = op.strip_prefix('*').unwrap_or(op);
+    if let Some(reg) = op.strip_prefix('%') {
+        return is_register(reg).then_some(Operand::Reg(reg));
+    }
+    if let Some(imm) = op.strip_prefix('$') {
+        return is_plain(imm).then_some(Operand::Imm(imm));
+    }
+    let mut mem = Mem::default();
+    let mut op = op;
+    if let Some((segment, rest)) = op.split_once(':') {
+        let segment = segment.strip_prefix('%')?;
+        if !is_register(segment) {
+            return None;
+        }
+        mem.segment = Some(segment);
+        op = rest;
+    }
+    let Some((disp, rest)) = op.split_once('(') else {
+        if mem.segment.is_none() && !is_number(op) {
+            return is_plain(op).then_some(Operand::Sym(op));
+        }
+        if !is_plain(op) {
+            return None;
+        }
+        mem.negative = op.starts_with('-');
+        mem.disp = Some(op.trim_start_matches('-'));
+        return Some(Operand::Mem(mem));
+    };
+    if !disp.is_empty() {
+        if !is_plain(disp) {
+            return None;
+        }
+        mem.negative = disp.starts_with('-');
+        mem.disp = Some(disp.trim_start_matches('-'));
+    }
+    let mut parts = rest.strip_suffix(')')?.split(',').map(str::trim);
+    mem.base = att_mem_reg(parts.next())?;
+    mem.index = att_mem_reg(parts.next())?;
+    mem.scale = parts.next().filter(|s| !s.is_empty());
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(Operand::Mem(mem))
+}
+
+fn write_intel(op: &Operand, lea: bool, out: &mut String) {
+    use std::fmt::Write;
+    match op {
+        Operand::Reg(r) | Operand::Imm(r) | Operand::Sym(r) => out.push_str(r),
+        Operand::Mem(mem) => {
+            if let (Some(size), false) = (mem.size, lea) {
+                write!(out, "{} ptr ", size.ptr()).unwrap();
+            }
+            if let Some(seg) = mem.segment {
+                write!(out, "{seg}:").unwrap();
+            }
+            out.push('[');
+            let mut first = true;
+            let mut term = |t: &str, negative: bool, out: &mut String| {
+                match (first, negative) {
+                    (true, false) => out.push_str(t),
+                    (true, true) => write!(out, "-{t}").unwrap(),
+                    (false, false) => write!(out, " + {t}").unwrap(),
+                    (false, true) => write!(out, " - {t}").unwrap(),
+                }
+                first = false;
+            };
+            if let Some(base) = mem.base {
+                term(base, false, out);
+            }
+            if let Some(index) = mem.index {
+                match mem.scale {
+                    Some(scale) if scale != "1" => term(&format!("{scale}*{index}"), false, out),
+                    _ => term(index, false, out),
+                }
+            }
+            if let Some(disp) = mem.disp {
+                term(disp, mem.negative, out);
+            }
+            out.push(']');
+        }
+    }
+}
+
+fn write_att(op: &Operand, indirect: bool, out: &mut String) {
+    use std::fmt::Write;
+    match op {
+        Operand::Reg(r) => {
+            if indirect {
+                out.push('*');
+            }
+            write!(out, "%{r}").unwrap();
+        }
+        Operand::Imm(i) => write!(out, "${i}").unwrap(),
+        Operand::Sym(s) => out.push_str(s),
+        Operand::Mem(mem) => {
+            if indirect {
+                out.push('*');
+            }
+            if let Some(seg) = mem.segment {
+                write!(out, "%{seg}:").unwrap();
+            }
+            if let Some(disp) = mem.disp {
+                if mem.negative {
+                    out.push('-');
+                }
+                out.push_str(disp);
+            }
+            if mem.base.is_some() || mem.index.is_some() {
+                out.push('(');
+                if let Some(base) = mem.base {
+                    write!(out, "%{base}").unwrap();
+                }
+                if let Some(index) = mem.index {
+                    write!(out, ",%{index}").unwrap();
+                    if let Some(scale) = mem.scale {
+                        write!(out, ",{scale}").unwrap();
+                    }
+                }
+                out.push(')');
+            }
+        }
+    }
+}
+
+/// Rewrite instruction into a different style
+///
+/// Returns `None` if instruction is already in the requested style or if it uses something
+/// this module doesn't understand
+pub fn rewrite(op: &str, args: &str, target: OutputStyle) -> Option<(String, String)> {
+    let is_att = args.contains('%') || args.starts_with('$');
+    match target {
+        OutputStyle::Intel if is_att => att_to_intel(op, args),
+        OutputStyle::Att if !is_att => intel_to_att(op, args),
+        OutputStyle::Intel | OutputStyle::Att => None,
+    }
+}
+
+fn att_to_intel(op: &str, args: &str) -> Option<(String, String)> {
+    let mut operands = split_operands(args)
+        .into_iter()
+        .map(parse_att)
+        .collect::<Option<Vec<_>>>()?;
+    if !is_x86(&operands) {
+        return None;
+    }
+    operands.reverse();
+
+    // `adcx` is not `adc` with a suffix, `movq` with a vector register is an SSE instruction
+    // and keeps its name
+    let suffix = match op.char_indices().last() {
+        Some((ix, suffix)) if SIZED_OPS.contains(&&op[..ix]) => {
+            Size::from_suffix(suffix).map(|size| (ix, size))
+        }
+        _ => None,
+    };
+    let vector = operands
+        .iter()
+        .any(|o| matches!(o, Operand::Reg(r) if is_vector_register(r)));
+    let (op, size) = match suffix {
+        Some((ix, size)) if !vector => (&op[..ix], Some(size)),
+        Some((_, size)) => (op, Some(size)),
+        None => (op, None),
+    };
+    // "call" and "jmp" used to get "q" suffix as well
+    let op = match op {
+        "callq" => "call",
+        "jmpq" => "jmp",
+        "retq" => "ret",
+        op => op,
+    };
+    for operand in &mut operands {
+        if let Operand::Mem(mem) = operand {
+            mem.size = mem.size.or(size);
+        }
+    }
+
+    let lea = op == "lea";
+    let mut res = String::new();
+    for (ix, operand) in operands.iter().enumerate() {
+        if ix > 0 {
+            res.push_str(", ");
+        }
+        write_intel(operand, lea, &mut res);
+    }
+    Some((op.to_owned(), res))
+}
+
+fn intel_to_att(op: &str, args: &str) -> Option<(String, String)> {
+    let mut operands = split_operands(args)
+        .into_iter()
+        .map(parse_intel)
+        .collect::<Option<Vec<_>>>()?;
+    if !is_x86(&operands) {
+        return None;
+    }
+
+    let size = operands.iter().find_map(|o| match o {
+        Operand::Reg(r) => gpr_size(r),
+        Operand::Mem(mem) => mem.size,
+        Operand::Imm(_) | Operand::Sym(_) => None,
+    });
+    let op = match size {
+        Some(size) if SIZED_OPS.contains(&op) => format!("{op}{}", size.suffix()),
+        _ => op.to_owned(),
+    };
+    let indirect = op.starts_with('j') || op.starts_with("call");
+
+    operands.reverse();
+    let mut res = String::new();
+    for (ix, operand) in operands.iter().enumerate() {
+        if ix > 0 {
+            res.push_str(", ");
+        }
+        write_att(operand, indirect, &mut res);
+    }
+    Some((op, res))
+}
+
+#[test]
+fn intel_to_att_works() {
+    let r = |op, args| rewrite(op, args, OutputStyle::Att);
+    assert_eq!(
+        r("mov", "rax, qword ptr [rdi + 8*rcx + 16]"),
+        Some(("movq".into(), "16(%rdi,%rcx,8), %rax".into()))
+    );
+    assert_eq!(
+        r("mov", "dword ptr [rbp - 8], 42"),
+        Some(("movl".into(), "$42, -8(%rbp)".into()))
+    );
+    assert_eq!(
+        r("lea", "rdi, [rip + .Lanon.1]"),
+        Some(("leaq".into(), ".Lanon.1(%rip), %rdi".into()))
+    );
+    assert_eq!(r("call", "rax"), Some(("call".into(), "*%rax".into())));
+    assert_eq!(r("jmp", ".LBB0_3"), Some(("jmp".into(), ".LBB0_3".into())));
+    assert_eq!(r("mov", "%rdi, %rax"), None);
+    assert_eq!(r("ldr", "x0, [sp, #16]"), None);
+    assert_eq!(r("add", "x0, x1, x2"), None);
+    assert_eq!(
+        r("movq", "rax, xmm0"),
+        Some(("movq".into(), "%xmm0, %rax".into()))
+    );
+}
+
+#[test]
+fn att_to_intel_works() {
+    let r = |op, args| rewrite(op, args, OutputStyle::Intel);
+    assert_eq!(
+        r("movq", "16(%rdi,%rcx,8), %rax"),
+        Some(("mov".into(), "rax, qword ptr [rdi + 8*rcx + 16]".into()))
+    );
+    assert_eq!(
+        r("movl", "$42, -8(%rbp)"),
+        Some(("mov".into(), "dword ptr [rbp - 8], 42".into()))
+    );
+    assert_eq!(
+        r("leaq", ".Lanon.1(%rip), %rdi"),
+        Some(("lea".into(), "rdi, [rip + .Lanon.1]".into()))
+    );
+    assert_eq!(r("callq", "*%rax"), Some(("call".into(), "rax".into())));
+    assert_eq!(r("mov", "rax, rdi"), None);
+    assert_eq!(
+        r("movq", "%xmm0, %rax"),
+        Some(("movq".into(), "rax, xmm0".into()))
+    );
+    assert_eq!(
+        r("movd", "%xmm0, %eax"),
+        Some(("movd".into(), "eax, xmm0".into()))
+    );
+    assert_eq!(
+        r("movq", "(%rdi), %xmm0"),
+        Some(("movq".into(), "xmm0, qword ptr [rdi]".into()))
+    );
+    assert_eq!(
+        r("adcxq", "%rdi, %rax"),
+        Some(("adcx".into(), "rax, rdi".into()))
+    );
+    assert_eq!(
+        r("adcx", "%rdi, %rax"),
+        Some(("adcx".into(), "rax, rdi".into()))
+    );
+}
@@ -5,15 +5,16 @@ use std::sync::OnceLock;
 use nom::branch::alt;
 use nom::bytes::complete::{escaped_transform, tag, take_while1, take_while_m_n};
 use nom::character::complete::{self, newline, none_of, not_line_ending, one_of, space0, space1};
-use nom::combinator::{map, opt, recognize, value, verify};
+use nom::combinator::{map, opt, peek, recognize, value, verify};
 use nom::multi::count;
 use nom::sequence::{delimited, pair, preceded, terminated, tuple};
 use nom::{AsChar, IResult};
 use owo_colors::OwoColorize;
 use regex::Regex;
+use serde_json::{json, Value};
 
 use crate::demangle::LabelKind;
-use crate::opts::NameDisplay;
+use crate::opts::{Format, NameDisplay};
 use crate::{color, demangle};
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -28,7 +29,12 @@ pub enum Statement<'a> {
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Instruction<'a> {
     pub op: &'a str,
+    /// Whitespace between `op` and `args` in the source, used with --raw-format
+    pub sep: &'a str,
     pub args: Option<&'a str>,
+    /// Contents of `# encoding: [0x48,0x89,0xf8]` comment LLVM adds with `-show-mc-encoding`,
+    /// kept apart from `args` so it doesn't get in the way of anything looking at operands
+    pub encoding: Option<&'a str>,
 }
 
 impl<'a> Instruction<'a> {
@@ -39,19 +45,111 @@ impl<'a> Instruction<'a> {
     fn parse_sharp(input: &'a str) -> IResult<&'a str, Self> {
         let sharps = take_while_m_n(1, 2, |c| c == '#');
         let sharp_tag = pair(sharps, not_line_ending);
-        map(recognize(sharp_tag), |op| Instruction { op, args: None })(input)
+        map(recognize(sharp_tag), |op| Instruction {
+            op,
+            sep: "",
+            args: None,
+            encoding: None,
+        })(input)
+    }
+
+    /// Encoded bytes of this instruction, bytes that are filled in by relocations later,
+    /// such as call targets, are `None`
+    pub(crate) fn encoding_bytes(&self) -> Option<Vec<Option<u8>>> {
+        let bytes = self
+            .encoding?
+            .split(',')
+            .map(|b| {
+                let hex = b.trim().strip_prefix("0x")?;
+                u8::from_str_radix(hex, 16).ok()
+            })
+            .collect();
+        Some(bytes)
     }
 
     fn parse_regular(input: &'a str) -> IResult<&'a str, Self> {
         // NOTE: ARM allows `.` inside instruction names e.g. `b.ne` for branch not equal
         //       Wasm also uses `.` in instr names, and uses `_` for `end_function`
         let op = take_while1(|c| AsChar::is_alphanum(c) || matches!(c, '.' | '_'));
-        let args = opt(preceded(space1, not_line_ending));
-        map(pair(op, args), |(op, args)| Instruction { op, args })(input)
+        let args = opt(pair(space1, not_line_ending));
+        map(pair(op, args), |(op, args)| {
+            let (sep, args) = args.unwrap_or(("", ""));
+            let (args, encoding) = split_encoding(args);
+            Instruction {
+                op,
+                sep: if args.is_empty() { "" } else { sep },
+                args: (!args.is_empty()).then_some(args),
+                encoding,
+            }
+        })(input)
+    }
+}
+
+/// Split off `# encoding: [...]` comment from instruction arguments
+fn split_encoding(args: &str) -> (&str, Option<&str>) {
+    let encoding = args
+        .split_once("# encoding: [")
+        .or_else(|| args.split_once("// encoding: ["))
+        .and_then(|(rest, encoding)| Some((rest, encoding.strip_suffix(']')?)));
+    match encoding {
+        Some((rest, encoding)) => (rest.trim_end(), Some(encoding)),
+        None => (args, None),
     }
 }
 
-fn parse_data_dec(input: &str) -> IResult<&str, Directive> {
+#[test]
+fn instruction_separator_is_kept() {
+    let (_, instr) = Instruction::parse("\tmovq\t  %rdi, %rax").unwrap();
+    assert_eq!(
+        (instr.op, instr.sep, instr.args),
+        ("movq", "\t  ", Some("%rdi, %rax"))
+    );
+    let (_, instr) = Instruction::parse("\tretq").unwrap();
+    assert_eq!((instr.sep, instr.args), ("", None));
+}
+
+#[test]
+fn encoding_is_split() {
+    let parse = |line| Instruction::parse(line).unwrap().1;
+    let mov = parse("\tmovq\t%rdi, %rax                # encoding: [0x48,0x89,0xf8]");
+    assert_eq!(
+        (mov.args, mov.encoding),
+        (Some("%rdi, %rax"), Some("0x48,0x89,0xf8"))
+    );
+    assert_eq!(
+        mov.encoding_bytes(),
+        Some(vec![Some(0x48), Some(0x89), Some(0xf8)])
+    );
+
+    let call = parse("\tcallq\tfoo                  # encoding: [0xe8,A,A,A,A]");
+    assert_eq!(call.args, Some("foo"));
+    assert_eq!(
+        call.encoding_bytes(),
+        Some(vec![Some(0xe8), None, None, None, None])
+    );
+
+    let ret = parse("\tretq                            # encoding: [0xc3]");
+    assert_eq!((ret.sep, ret.args), ("", None));
+    assert_eq!(ret.encoding_bytes(), Some(vec![Some(0xc3)]));
+
+    let ldr = parse("\tldr\tx0, [x1]                  // encoding: [0x20,0x00,0x40,0xf9]");
+    assert_eq!(ldr.args, Some("x0, [x1]"));
+    assert_eq!(ldr.encoding, Some("0x20,0x00,0x40,0xf9"));
+
+    let call =
+        parse_statement("\tcallq\t_ZN3foo3bar17h0123456789abcdefE # encoding: [0xe8,A,A,A,A]\n")
+            .unwrap()
+            .1;
+    assert_eq!(call.call_target(), Some("_ZN3foo3bar17h0123456789abcdefE"));
+
+    let plain = parse("\tmovq\t%rdi, %rax");
+    assert_eq!(
+        (plain.args, plain.encoding_bytes()),
+        (Some("%rdi, %rax"), None)
+    );
+}
+
+fn parse_data_dec(input: &str) -> IResult<&str, Directive<'_>> {
     static DATA_DEC: OnceLock<Regex> = OnceLock::new();
     // all of those can insert something as well... Not sure if it's a full list or not
     // .long, .short .octa, .quad, .word,
@@ -83,10 +181,80 @@ fn parse_data_dec(input: &str) -> IResult<&str, Directive> {
     ))
 }
 
+impl Statement<'_> {
+    /// Parsed statement as a JSON object tagged with its `type`, used by `--ndjson`
+    #[must_use]
+    pub fn to_json(&self) -> Value {
+        match self {
+            Statement::Label(Label { id, kind }) => {
+                let kind = match kind {
+                    LabelKind::Global => "global",
+                    LabelKind::Local => "local",
+                    LabelKind::Temp => "temp",
+                    LabelKind::Unknown => "unknown",
+                };
+                json!({ "type": "label", "id": id, "kind": kind })
+            }
+            Statement::Directive(d) => d.to_json(),
+            Statement::Instruction(Instruction { op, args, .. }) => {
+                json!({ "type": "instruction", "op": op, "args": args })
+            }
+            Statement::Nothing => json!({ "type": "nothing" }),
+            Statement::Dunno(text) => json!({ "type": "unknown", "text": text }),
+        }
+    }
+}
+
+impl Directive<'_> {
+    #[must_use]
+    pub fn to_json(&self) -> Value {
+        match self {
+            Directive::File(File { index, path, md5 }) => json!({
+                "type": "file",
+                "index": index,
+                "path": path.as_full_path().display().to_string(),
+                "md5": md5,
+            }),
+            Directive::Loc(loc) => json!({
+                "type": "loc",
+                "file": loc.file,
+                "line": loc.line,
+                "column": loc.column,
+                "extra": loc.extra,
+            }),
+            Directive::Global(name) => json!({ "type": "global", "name": name }),
+            Directive::Weak(_, name) => json!({ "type": "weak", "name": name }),
+            Directive::Generic(GenericDirective(text)) => {
+                json!({ "type": "directive", "text": text })
+            }
+            Directive::SymIsFun(name) => json!({ "type": "function", "name": name }),
+            Directive::SetValue(name, value) => {
+                json!({ "type": "set", "name": name, "value": value })
+            }
+            Directive::SubsectionsViaSym => json!({ "type": "subsections_via_symbols" }),
+            Directive::SectionStart(name) => json!({ "type": "section", "name": name }),
+            Directive::Data(kind, value) => {
+                json!({ "type": "data", "kind": kind, "value": value })
+            }
+            Directive::Cfi(text) => json!({ "type": "cfi", "text": text }),
+            Directive::Seh(text) => json!({ "type": "seh", "text": text }),
+            Directive::Size(name, expr) => json!({ "type": "size", "name": name, "expr": expr }),
+            Directive::Align(align) => json!({
+                "type": "align",
+                "kind": align.kind,
+                "value": align.value,
+                "fill": align.fill,
+                "max_skip": align.max_skip,
+                "bytes": align.bytes(),
+            }),
+        }
+    }
+}
+
 impl<'a> Statement<'a> {
     /// Should we skip it for --simplify output?
     pub fn boring(&self) -> bool {
-        if let Statement::Directive(Directive::SetValue(_, _)) = self {
+        if let Statement::Directive(Directive::SetValue(_, _) | Directive::Align(_)) = self {
             return false;
         }
         if let Statement::Directive(Directive::SectionStart(name)) = self {
@@ -98,18 +266,83 @@ impl<'a> Statement<'a> {
     }
 }
 
+impl<'a> Statement<'a> {
+    /// Display with the options from `fmt` formatter flags can't carry, such as register
+    /// coloring, names are displayed according to `fmt.name_display`
+    pub fn styled<'b>(&'b self, fmt: &'b Format) -> Styled<'b, 'a> {
+        Styled {
+            statement: self,
+            fmt,
+        }
+    }
+}
+
+/// [`Statement`] with display options, see [`Statement::styled`]
+pub struct Styled<'b, 'a> {
+    statement: &'b Statement<'a>,
+    fmt: &'b Format,
+}
+
+impl std::fmt::Display for Styled<'_, '_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.statement, self.fmt.name_display) {
+            (Statement::Instruction(i), display) => {
+                f.write_str("\t")?;
+                i.write(f, display, Some(self.fmt))
+            }
+            (s, NameDisplay::Full) => write!(f, "{s:#}"),
+            (s, NameDisplay::Verbose) => write!(f, "{s:+}"),
+            (s, NameDisplay::Short) => write!(f, "{s}"),
+            (s, NameDisplay::Mangled) => write!(f, "{s:-}"),
+        }
+    }
+}
+
 impl std::fmt::Display for Instruction<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let display = NameDisplay::from(&*f);
+        self.write(f, display, None)
+    }
+}
+
+impl Instruction<'_> {
+    /// Plain [`Display`](std::fmt::Display) passes no `fmt`, operands are printed without
+    /// the extra coloring
+    fn write(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        display: NameDisplay,
+        fmt: Option<&Format>,
+    ) -> std::fmt::Result {
         if self.op.starts_with("#DEBUG_VALUE:") {
             write!(f, "{}", color!(self.op, OwoColorize::blue))?;
         } else {
             write!(f, "{}", color!(self.op, OwoColorize::bright_blue))?;
         }
         if let Some(args) = self.args {
-            let args = demangle::contents(args, display);
+            // immediates go first, escape sequences from other coloring contain numbers too
+            let args = if fmt.is_some_and(|fmt| fmt.color_imms) {
+                demangle::color_immediates(args)
+            } else {
+                Cow::Borrowed(args)
+            };
+            let args = if fmt.is_some_and(|fmt| fmt.color_regs) {
+                demangle::color_registers(&args)
+            } else {
+                Cow::Borrowed(&*args)
+            };
+            let args = demangle::contents(&args, display);
             let w_label = demangle::color_local_labels(&args);
-            write!(f, " {w_label}")?;
+            // --raw-format keeps the whitespace from the source
+            let sep = if fmt.is_some_and(|fmt| fmt.raw_format) {
+                self.sep
+            } else {
+                " "
+            };
+            match fmt.and_then(|fmt| fmt.grep.as_ref()) {
+                Some(regex) => write!(f, "{sep}{}", demangle::highlight(&w_label, regex))?,
+                None => write!(f, "{sep}{w_label}")?,
+            }
         }
         Ok(())
     }
@@ -119,21 +352,11 @@ impl std::fmt::Display for Statement<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Statement::Label(l) => l.fmt(f),
-            Statement::Directive(d) => {
-                if f.alternate() {
-                    write!(f, "{d:#}")
-                } else {
-                    write!(f, "{d}")
-                }
-            }
+            // formatter flags carry name display, pass them along
+            Statement::Directive(d) => d.fmt(f),
             Statement::Instruction(i) => {
-                if f.sign_minus() {
-                    write!(f, "\t{i:-#}")
-                } else if f.alternate() {
-                    write!(f, "\t{i:#}")
-                } else {
-                    write!(f, "\t{i}")
-                }
+                f.write_str("\t")?;
+                i.fmt(f)
             }
             Statement::Nothing => Ok(()),
             Statement::Dunno(l) => write!(f, "{l}"),
@@ -147,6 +370,7 @@ impl std::fmt::Display for Directive<'_> {
         match self {
             Directive::File(ff) => ff.fmt(f),
             Directive::Loc(l) => l.fmt(f),
+            Directive::Align(a) => a.fmt(f),
             Directive::Generic(g) => g.fmt(f),
             Directive::SetValue(key, val) => {
                 let key = demangle::contents(key, display);
@@ -186,6 +410,16 @@ impl std::fmt::Display for Directive<'_> {
                     color!(w_label, OwoColorize::bright_cyan)
                 )
             }
+            Directive::Cfi(cfi) => write!(f, "\t.{}", color!(cfi, OwoColorize::yellow)),
+            Directive::Seh(seh) => write!(f, "\t.{}", color!(seh, OwoColorize::yellow)),
+            Directive::Size(name, expr) => {
+                let name = demangle::contents(name, display);
+                write!(
+                    f,
+                    "\t.{}\t{name}, {expr}",
+                    color!("size", OwoColorize::bright_magenta)
+                )
+            }
             Directive::Global(data) => {
                 let data = demangle::contents(data, display);
                 let w_label = demangle::color_local_labels(&data);
@@ -196,6 +430,15 @@ impl std::fmt::Display for Directive<'_> {
                     color!(w_label, OwoColorize::bright_cyan)
                 )
             }
+            Directive::Weak(kind, name) => {
+                let name = demangle::contents(name, display);
+                write!(
+                    f,
+                    "\t.{}\t{}",
+                    color!(kind, OwoColorize::bright_magenta),
+                    color!(name, OwoColorize::bright_cyan)
+                )
+            }
         }
     }
 }
@@ -255,6 +498,8 @@ impl From<&std::fmt::Formatter<'_>> for NameDisplay {
     fn from(f: &std::fmt::Formatter) -> Self {
         if f.sign_minus() {
             NameDisplay::Mangled
+        } else if f.sign_plus() {
+            NameDisplay::Verbose
         } else if f.alternate() {
             NameDisplay::Full
         } else {
@@ -307,6 +552,8 @@ impl<'a> Label<'a> {
 
 #[derive(Copy, Clone, Debug, Eq, Default)]
 pub struct Loc<'a> {
+    /// CodeView function id, tells apart inlined functions, `None` for DWARF `.loc`
+    pub function: Option<u64>,
     pub file: u64,
     pub line: u64,
     pub column: u64,
@@ -315,7 +562,7 @@ pub struct Loc<'a> {
 
 impl<'a> PartialEq for Loc<'a> {
     fn eq(&self, other: &Self) -> bool {
-        self.file == other.file && self.line == other.line
+        self.function == other.function && self.file == other.file && self.line == other.line
     }
 }
 
@@ -326,8 +573,8 @@ impl<'a> Loc<'a> {
         map(
             tuple((
                 alt((
-                    tag("\t.loc\t"),
-                    terminated(tag("\t.cv_loc\t"), tuple((complete::u64, space1))),
+                    map(tag("\t.loc\t"), |_| None),
+                    map(delimited(tag("\t.cv_loc\t"), complete::u64, space1), Some),
                 )),
                 complete::u64,
                 space1,
@@ -336,7 +583,8 @@ impl<'a> Loc<'a> {
                 complete::u64,
                 opt(preceded(tag(" "), take_while1(|c| c != '\n'))),
             )),
-            |(_, file, _, line, _, column, extra)| Loc {
+            |(function, file, _, line, _, column, extra)| Loc {
+                function,
                 file,
                 line,
                 column,
@@ -344,6 +592,156 @@ impl<'a> Loc<'a> {
             },
         )(input)
     }
+
+    /// Parse optional flags that follow line and column
+    ///
+    /// LLVM doesn't encode the inlining stack here, that lives in DWARF
+    /// sections, but flags still help to tell the prologue, epilogue and
+    /// different basic blocks generated from the same line apart.
+    #[must_use]
+    pub fn flags(&self) -> LocFlags<'a> {
+        self.extra.map(LocFlags::parse).unwrap_or_default()
+    }
+}
+
+/// Flags that can follow `.loc fileno lineno column` or its CodeView version
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct LocFlags<'a> {
+    pub prologue_end: bool,
+    pub epilogue_begin: bool,
+    pub is_stmt: Option<bool>,
+    pub discriminator: Option<u64>,
+    /// Instruction set architecture: `isa 1`
+    pub isa: Option<u64>,
+    /// Location view numbering, a label or a number: `view .Lview0`
+    pub view: Option<&'a str>,
+}
+
+impl<'a> LocFlags<'a> {
+    fn parse(input: &'a str) -> Self {
+        let mut flags = Self::default();
+        let mut words = input.split_whitespace();
+        while let Some(word) = words.next() {
+            match word {
+                "prologue_end" => flags.prologue_end = true,
+                "epilogue_begin" => flags.epilogue_begin = true,
+                "is_stmt" => flags.is_stmt = words.next().map(|v| v != "0"),
+                "discriminator" => flags.discriminator = words.next().and_then(|v| v.parse().ok()),
+                "isa" => flags.isa = words.next().and_then(|v| v.parse().ok()),
+                "view" => flags.view = words.next(),
+                _ => {}
+            }
+        }
+        flags
+    }
+
+    /// Are there no flags worth showing? View numbering and `is_stmt 1`, the default,
+    /// are not shown
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        Self {
+            view: None,
+            is_stmt: self.is_stmt.filter(|is_stmt| !is_stmt),
+            ..*self
+        } == Self::default()
+    }
+}
+
+impl std::fmt::Display for LocFlags<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = Vec::new();
+        if self.prologue_end {
+            parts.push("prologue end".to_owned());
+        }
+        if self.epilogue_begin {
+            parts.push("epilogue begin".to_owned());
+        }
+        if self.is_stmt == Some(false) {
+            parts.push("not a statement".to_owned());
+        }
+        if let Some(d) = self.discriminator {
+            parts.push(format!("discriminator {d}"));
+        }
+        if let Some(isa) = self.isa {
+            parts.push(format!("isa {isa}"));
+        }
+        f.write_str(&parts.join(", "))
+    }
+}
+
+/// `.p2align`, `.balign` or `.align` with optional fill value and max skip
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Align<'a> {
+    /// Directive name as written: `p2align`, `balign` or `align`
+    pub kind: &'a str,
+    pub value: u64,
+    pub fill: Option<&'a str>,
+    pub max_skip: Option<u64>,
+}
+
+impl<'a> Align<'a> {
+    pub fn parse(input: &'a str) -> IResult<&'a str, Self> {
+        // .p2align pow2 [, fill [, max_skip]]
+        // .balign  bytes [, fill [, max_skip]]
+        // .align   bytes or pow2, depending on the target
+        let comma = || tuple((space0, tag(","), space0));
+        let fill = take_while1(|c: char| c != ',' && c != '\n' && !c.is_whitespace());
+        map(
+            terminated(
+                tuple((
+                    tag("\t."),
+                    alt((tag("p2align"), tag("balign"), tag("align"))),
+                    space1,
+                    complete::u64,
+                    opt(preceded(comma(), opt(fill))),
+                    opt(preceded(comma(), complete::u64)),
+                    space0,
+                )),
+                peek(newline),
+            ),
+            |(_, kind, _, value, fill, max_skip, _)| Align {
+                kind,
+                value,
+                fill: fill.flatten(),
+                max_skip,
+            },
+        )(input)
+    }
+
+    /// Alignment in bytes, `None` for `.align` since its meaning depends on the target
+    #[must_use]
+    pub fn bytes(&self) -> Option<u64> {
+        match self.kind {
+            "p2align" => 1u64.checked_shl(u32::try_from(self.value).ok()?),
+            "balign" => Some(self.value),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Align<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "\t.{}\t{}",
+            color!(self.kind, OwoColorize::bright_magenta),
+            self.value
+        )?;
+        match (self.fill, self.max_skip) {
+            (Some(fill), Some(max)) => write!(f, ", {fill}, {max}")?,
+            (Some(fill), None) => write!(f, ", {fill}")?,
+            (None, Some(max)) => write!(f, ", , {max}")?,
+            (None, None) => {}
+        }
+        let comment = match (self.bytes(), self.max_skip) {
+            (Some(bytes), Some(max)) => {
+                format!("# align to {bytes} bytes, skipping at most {max} bytes")
+            }
+            (Some(bytes), None) => format!("# align to {bytes} bytes"),
+            (None, _) => format!("# align to {}, target specific units", self.value),
+        };
+        write!(f, " {}", color!(comment, OwoColorize::cyan))
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -558,6 +956,7 @@ fn test_parse_loc() {
         Ok((
             "",
             Loc {
+                function: None,
                 file: 31,
                 line: 26,
                 column: 29,
@@ -570,6 +969,7 @@ fn test_parse_loc() {
         Ok((
             "",
             Loc {
+                function: None,
                 file: 31,
                 line: 26,
                 column: 29,
@@ -582,6 +982,7 @@ fn test_parse_loc() {
         Ok((
             "",
             Loc {
+                function: None,
                 file: 31,
                 line: 26,
                 column: 29,
@@ -594,6 +995,7 @@ fn test_parse_loc() {
         Ok((
             "",
             Loc {
+                function: Some(9),
                 file: 6,
                 line: 1,
                 column: 0,
@@ -606,6 +1008,7 @@ fn test_parse_loc() {
         Ok((
             "",
             Loc {
+                function: Some(9),
                 file: 6,
                 line: 1,
                 column: 0,
@@ -615,6 +1018,54 @@ fn test_parse_loc() {
     );
 }
 
+#[test]
+fn cv_loc_function_id_is_kept() {
+    let (_, a) = Loc::parse("\t.cv_loc\t1 6 10 0").unwrap();
+    let (_, b) = Loc::parse("\t.cv_loc\t2 6 10 0").unwrap();
+    assert_eq!((a.function, b.function), (Some(1), Some(2)));
+    assert_ne!(a, b);
+}
+
+#[test]
+fn test_loc_flags() {
+    let (_, loc) = Loc::parse("\t.loc\t1 26 29 prologue_end discriminator 3").unwrap();
+    let flags = loc.flags();
+    assert_eq!(
+        flags,
+        LocFlags {
+            prologue_end: true,
+            epilogue_begin: false,
+            is_stmt: None,
+            discriminator: Some(3),
+            isa: None,
+            view: None,
+        }
+    );
+    assert_eq!(flags.to_string(), "prologue end, discriminator 3");
+
+    let (_, loc) = Loc::parse("\t.loc\t1 26 29 is_stmt 0").unwrap();
+    assert_eq!(loc.flags().to_string(), "not a statement");
+
+    let (_, loc) = Loc::parse("\t.loc\t1 26 29").unwrap();
+    assert!(loc.flags().is_empty());
+}
+
+#[test]
+fn test_loc_view_and_isa() {
+    let input = "\t.loc\t1 2 3 view .Lview0 is_stmt 1";
+    let (_, loc) = Loc::parse(input).unwrap();
+    let flags = loc.flags();
+    assert_eq!(flags.view, Some(".Lview0"));
+    assert_eq!(flags.is_stmt, Some(true));
+    assert!(flags.is_empty());
+    assert_eq!(loc.to_string(), input);
+
+    let (_, loc) = Loc::parse("\t.loc\t1 2 3 isa 1 discriminator 2").unwrap();
+    let flags = loc.flags();
+    assert_eq!((flags.isa, flags.discriminator), (Some(1), Some(2)));
+    assert_eq!(flags.to_string(), "discriminator 2, isa 1");
+}
+
 #[test]
 fn test_parse_file() {
     let (rest, file) = File::parse("\t.file\t9 \"/home/ubuntu/buf-test/src/main.rs\"").unwrap();
@@ -751,33 +1202,243 @@ fn parse_data_decl() {
     );
 }
 
+#[test]
+fn detect_jump_target() {
+    let jmp = parse_statement("\tjmp\t_ZN4core9panicking5panic17h1f7a2b3c4d5e6f70E\n")
+        .unwrap()
+        .1;
+    assert_eq!(
+        jmp.jump_target(),
+        Some("_ZN4core9panicking5panic17h1f7a2b3c4d5e6f70E")
+    );
+    let plt =
+        parse_statement("\tjmp\tqword ptr [rip + _ZN3foo3bar17h0123456789abcdefE@GOTPCREL]\n")
+            .unwrap()
+            .1;
+    assert_eq!(plt.jump_target(), Some("_ZN3foo3bar17h0123456789abcdefE"));
+    let local = parse_statement("\tjmp\t.LBB0_3\n").unwrap().1;
+    assert_eq!(local.jump_target(), None);
+    let call = parse_statement("\tcall\t_ZN3foo3bar17h0123456789abcdefE\n")
+        .unwrap()
+        .1;
+    assert_eq!(call.jump_target(), None);
+}
+
+#[test]
+fn parse_cfi() {
+    assert_eq!(
+        parse_statement("\t.cfi_def_cfa_offset 16\n").unwrap().1,
+        Statement::Directive(Directive::Cfi("cfi_def_cfa_offset 16"))
+    );
+    assert_eq!(
+        parse_statement("\t.cfi_startproc\n").unwrap().1,
+        Statement::Directive(Directive::Cfi("cfi_startproc"))
+    );
+}
+
+#[test]
+fn parse_seh() {
+    assert_eq!(
+        parse_statement(".seh_proc _ZN3foo3bar17h0123456789abcdefE\n")
+            .unwrap()
+            .1,
+        Statement::Directive(Directive::Seh("seh_proc _ZN3foo3bar17h0123456789abcdefE"))
+    );
+    assert_eq!(
+        parse_statement("\t.seh_pushreg %rsi\n").unwrap().1,
+        Statement::Directive(Directive::Seh("seh_pushreg %rsi"))
+    );
+    let handler_data = parse_statement("\t.seh_handlerdata\n").unwrap().1;
+    assert!(handler_data.is_eh_tail_start());
+    let funclet = parse_statement("\t.def\t\"?dtor$5@?0?_ZN3foo3bar17h0123456789abcdefE@4HA\";\n")
+        .unwrap()
+        .1;
+    assert!(funclet.is_eh_tail_start());
+    let endprologue = parse_statement("\t.seh_endprologue\n").unwrap().1;
+    assert!(!endprologue.is_eh_tail_start());
+}
+
+#[test]
+fn parse_size() {
+    assert_eq!(
+        parse_statement("\t.size\tfoo, .-foo\n").unwrap().1,
+        Statement::Directive(Directive::Size("foo", ".-foo"))
+    );
+    assert_eq!(
+        parse_statement("\t.size\t_ZN3foo3bar17h0123456789abcdefE, .Lfunc_end0-_ZN3foo3bar17h0123456789abcdefE\n")
+            .unwrap()
+            .1,
+        Statement::Directive(Directive::Size(
+            "_ZN3foo3bar17h0123456789abcdefE",
+            ".Lfunc_end0-_ZN3foo3bar17h0123456789abcdefE"
+        ))
+    );
+}
+
+#[test]
+fn parse_align() {
+    let align = |input| match parse_statement(input).unwrap().1 {
+        Statement::Directive(Directive::Align(align)) => align,
+        other => panic!("expected alignment, got {other:?}"),
+    };
+
+    let a = align("\t.p2align\t4, 0x90\n");
+    assert_eq!(
+        a,
+        Align {
+            kind: "p2align",
+            value: 4,
+            fill: Some("0x90"),
+            max_skip: None
+        }
+    );
+    assert_eq!(a.bytes(), Some(16));
+
+    let a = align("\t.p2align\t4, , 10\n");
+    assert_eq!((a.fill, a.max_skip, a.bytes()), (None, Some(10), Some(16)));
+
+    let a = align("\t.p2align 5, 0x0, 16\n");
+    assert_eq!(
+        (a.fill, a.max_skip, a.bytes()),
+        (Some("0x0"), Some(16), Some(32))
+    );
+
+    let a = align("\t.p2align\t2\n");
+    assert_eq!((a.fill, a.max_skip, a.bytes()), (None, None, Some(4)));
+
+    let a = align("\t.balign 8\n");
+    assert_eq!((a.kind, a.bytes()), ("balign", Some(8)));
+
+    let a = align("\t.align\t2\n");
+    assert_eq!((a.kind, a.value, a.bytes()), ("align", 2, None));
+
+    assert!(!parse_statement("\t.p2align\t4, 0x90\n").unwrap().1.boring());
+}
+
+#[test]
+fn statements_as_json() {
+    let json = |input| parse_statement(input).unwrap().1.to_json().to_string();
+    assert_eq!(
+        json("\tmovq\t%rdi, %rax\n"),
+        r#"{"args":"%rdi, %rax","op":"movq","type":"instruction"}"#
+    );
+    assert_eq!(
+        json("\t.loc\t1 12 5 prologue_end\n"),
+        r#"{"column":5,"extra":"prologue_end","file":1,"line":12,"type":"loc"}"#
+    );
+    assert_eq!(
+        json(".LBB0_3:\n"),
+        r#"{"id":".LBB0_3","kind":"local","type":"label"}"#
+    );
+}
+
+#[test]
+fn detect_debug_value() {
+    let stmt = parse_statement("\t#DEBUG_VALUE: foo:x <- $rdi\n")
+        .unwrap()
+        .1;
+    assert!(stmt.is_debug_value());
+    let stmt = parse_statement("\tmovq\t%rdi, %rax\n").unwrap().1;
+    assert!(!stmt.is_debug_value());
+}
+
+#[test]
+fn stack_access_is_detected() {
+    let access = |s| parse_statement(s).unwrap().1.stack_access();
+    assert_eq!(access("\tmov\tqword ptr [rsp + 8], rax\n"), Some("spill"));
+    assert_eq!(access("\tmovq\t8(%rsp), %rax\n"), Some("reload"));
+    assert_eq!(access("\tstr\tx0, [sp, #8]\n"), Some("spill"));
+    assert_eq!(access("\tldp\tx29, x30, [sp], #16\n"), Some("reload"));
+    assert_eq!(access("\tpushq\t%rbx\n"), Some("stack"));
+    assert_eq!(access("\tlea\trdi, [rsp + 16]\n"), Some("stack"));
+    assert_eq!(access("\tmov\trax, qword ptr [rdi]\n"), None);
+}
+
+#[test]
+fn global_symbol_is_found() {
+    let sym = |s: &str| {
+        parse_statement(s)
+            .unwrap()
+            .1
+            .global_symbol()
+            .map(str::to_owned)
+    };
+    let name = "_ZN3foo3bar17h0123456789abcdefE";
+    assert_eq!(sym(&format!("{name}:\n")).as_deref(), Some(name));
+    assert_eq!(sym(&format!("\t.globl\t{name}\n")).as_deref(), Some(name));
+    assert_eq!(sym(".LBB0_1:\n"), None);
+}
+
+#[test]
+fn nop_like_is_detected() {
+    let nop = |s| parse_statement(s).unwrap().1.is_nop_like();
+    assert!(nop("\tnop\n"));
+    assert!(nop("\tnopw\t%cs:(%rax,%rax)\n"));
+    assert!(nop("\tnop\tword ptr cs:[rax + rax]\n"));
+    assert!(nop("\txchg\tax, ax\n"));
+    assert!(nop("\tmov\trax, rax\n"));
+    assert!(nop("\tmovq\t%rbx, %rbx\n"));
+    assert!(nop("\tmov\tx0, x0\n"));
+    assert!(!nop("\tmov\teax, eax\n"));
+    assert!(!nop("\tmovl\t%r8d, %r8d\n"));
+    assert!(!nop("\tmov\trax, rbx\n"));
+    assert!(!nop("\tmov\trax, qword ptr [rax]\n"));
+    assert!(nop("\tmovaps\txmm0, xmm0\n"));
+    assert!(!nop("\tvmovaps\txmm0, xmm0\n"));
+    assert!(!nop("\tvmovaps\t%xmm1, %xmm1\n"));
+    assert!(!nop("\tmovq\t%xmm0, %xmm0\n"));
+    assert!(!nop("\tmovd\txmm1, xmm1\n"));
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Directive<'a> {
     File(File<'a>),
     Loc(Loc<'a>),
     Global(&'a str),
+    /// `.weak sym` on ELF or `.weak_definition sym` on Mach-O
+    Weak(&'a str, &'a str),
     Generic(GenericDirective<'a>),
     SymIsFun(&'a str),
     SetValue(&'a str, &'a str),
     SubsectionsViaSym,
     SectionStart(&'a str),
     Data(&'a str, &'a str),
+    Cfi(&'a str),
+    /// Windows structured exception handling: `.seh_proc`, `.seh_pushreg`, ...
+    Seh(&'a str),
+    /// `.size symbol, expression`
+    Size(&'a str, &'a str),
+    Align(Align<'a>),
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct GenericDirective<'a>(pub &'a str);
 
-pub fn parse_statement(input: &str) -> IResult<&str, Statement> {
+pub fn parse_statement(input: &str) -> IResult<&str, Statement<'_>> {
     let label = map(Label::parse, Statement::Label);
 
     let file = map(File::parse, Directive::File);
 
     let loc = map(Loc::parse, Directive::Loc);
 
+    let align = map(Align::parse, Directive::Align);
+
     let section = map(
         preceded(tag("\t.section"), take_while1(|c| c != '\n')),
         |s: &str| Directive::SectionStart(s.trim()),
     );
+    let cfi = map(
+        preceded(tag("\t."), recognize(pair(tag("cfi_"), not_line_ending))),
+        Directive::Cfi,
+    );
+    // unlike the rest `.seh_proc` comes without indentation
+    let seh = map(
+        preceded(
+            alt((tag("\t."), tag("."))),
+            recognize(pair(tag("seh_"), not_line_ending)),
+        ),
+        Directive::Seh,
+    );
     let generic = map(preceded(tag("\t."), take_while1(|c| c != '\n')), |s| {
         Directive::Generic(GenericDirective(s))
     });
@@ -792,6 +1453,17 @@ pub fn parse_statement(input: &str) -> IResult<&str, Statement> {
         )),
         |(_, _, name, _, _, val)| Directive::SetValue(name, val),
     );
+    let size = map(
+        tuple((
+            tag("\t.size"),
+            space1,
+            take_while1(good_for_label),
+            tag(","),
+            space0,
+            take_while1(|c| c != '\n'),
+        )),
+        |(_, _, name, _, _, expr)| Directive::Size(name, expr),
+    );
     let ssvs = map(tag(".subsections_via_symbols"), |_| {
         Directive::SubsectionsViaSym
     });
@@ -823,16 +1495,32 @@ pub fn parse_statement(input: &str) -> IResult<&str, Statement> {
         )),
         |(_, _, _, name)| Directive::Global(name),
     );
+
+    let weak = map(
+        tuple((
+            space0,
+            tag("."),
+            alt((tag("weak_definition"), tag("weak"))),
+            space1,
+            take_while1(|c| good_for_label(c) || c == '@'),
+        )),
+        |(_, _, kind, _, name)| Directive::Weak(kind, name),
+    );
     let dir = map(
         alt((
             file,
             global,
+            weak,
             loc,
+            align,
             set,
             ssvs,
             section,
             typ,
+            size,
             parse_data_dec,
+            cfi,
+            seh,
             generic,
         )),
         Statement::Directive,
@@ -858,8 +1546,145 @@ impl Statement<'_> {
         matches!(self, Statement::Directive(Directive::SectionStart(_)))
     }
 
+    /// If this is an unconditional jump to a global symbol - return that symbol
+    ///
+    /// Jumping into a different function is what a tail call looks like
+    pub(crate) fn jump_target(&self) -> Option<&str> {
+        let Statement::Instruction(Instruction {
+            op: "jmp" | "jmpq" | "b",
+            args: Some(args),
+            ..
+        }) = self
+        else {
+            return None;
+        };
+        demangle::global_reference(args)
+    }
+
+    /// If this is a call to a global symbol - return that symbol
+    pub(crate) fn call_target(&self) -> Option<&str> {
+        let Statement::Instruction(Instruction {
+            op: "call" | "callq" | "bl",
+            args: Some(args),
+            ..
+        }) = self
+        else {
+            return None;
+        };
+        demangle::global_reference(args)
+    }
+
+    /// Is this a .cfi_* directive?
+    pub(crate) fn is_cfi(&self) -> bool {
+        matches!(self, Statement::Directive(Directive::Cfi(_)))
+    }
+
+    /// Is this a `#DEBUG_VALUE:` comment?
+    pub(crate) fn is_debug_value(&self) -> bool {
+        matches!(self, Statement::Instruction(Instruction { op, .. }) if op.starts_with("#DEBUG_VALUE:"))
+    }
+
+    /// Does exception handling code or data that follows the function body on Windows
+    /// start here? That's a cleanup or catch funclet or unwind handler data
+    pub(crate) fn is_eh_tail_start(&self) -> bool {
+        let funclet = |name: &str| {
+            let name = name.trim_start_matches('"');
+            name.starts_with("?dtor$") || name.starts_with("?catch$")
+        };
+        match self {
+            Statement::Directive(Directive::Seh(seh)) => seh.starts_with("seh_handlerdata"),
+            Statement::Directive(Directive::Generic(GenericDirective(g))) => g
+                .strip_prefix("def")
+                .is_some_and(|name| funclet(name.trim_start())),
+            Statement::Label(Label { id, .. }) => funclet(id),
+            Statement::Dunno(line) => funclet(line),
+            _ => false,
+        }
+    }
+
+    /// How does this instruction use the stack? A register stored into a stack slot is a
+    /// `"spill"`, loaded from one is a `"reload"`, anything else that refers to the stack or
+    /// frame pointer, including `push` and `pop`, is `"stack"`
+    pub(crate) fn stack_access(&self) -> Option<&'static str> {
+        let Statement::Instruction(Instruction { op, args, .. }) = self else {
+            return None;
+        };
+        if matches!(*op, "push" | "pushq" | "pop" | "popq") {
+            return Some("stack");
+        }
+        let args = (*args)?;
+        let on_stack = |s: &str| {
+            demangle::registers(s)
+                .any(|r| matches!(r, "rsp" | "rbp" | "esp" | "ebp" | "sp" | "fp" | "x29"))
+        };
+        if op.starts_with('#') || !on_stack(args) {
+            return None;
+        }
+        let operands = super::operands::split_operands(args);
+        let is_slot = |o: &&str| (o.contains('[') || o.contains('(')) && on_stack(o);
+        // destination is the first operand in Intel syntax and the last one in AT&T
+        let (dst, src) = if args.contains('%') {
+            (operands.last(), operands.first())
+        } else {
+            (operands.first(), operands.last())
+        };
+        if op.starts_with("st") && operands.iter().any(is_slot) {
+            Some("spill")
+        } else if op.starts_with("ld") && operands.iter().any(is_slot) {
+            Some("reload")
+        } else if op.contains("mov") && dst.is_some_and(is_slot) {
+            Some("spill")
+        } else if op.contains("mov") && src.is_some_and(is_slot) {
+            Some("reload")
+        } else {
+            Some("stack")
+        }
+    }
+
+    /// Does this instruction do nothing? Covers `nop` in all its forms used for alignment
+    /// padding and moves or exchanges of a register with itself. 32 bit moves such as
+    /// `mov eax, eax` clear the upper half of the register and are not included
+    pub(crate) fn is_nop_like(&self) -> bool {
+        let Statement::Instruction(Instruction { op, args, .. }) = self else {
+            return false;
+        };
+        if op.starts_with("nop") || *op == "data16" {
+            return true;
+        }
+        let Some(args) = args else {
+            return false;
+        };
+        // VEX encoded moves such as `vmovaps xmm0, xmm0` clear the upper part of the vector
+        // register, so they are left out
+        if !(op.starts_with("mov") || op.starts_with("xchg")) {
+            return false;
+        }
+        let args = args.split_once(['#', ';']).map_or(*args, |(args, _)| args);
+        let [a, b] = super::operands::split_operands(args)[..] else {
+            return false;
+        };
+        let reg = a.trim_start_matches('%');
+        let zero_extends = reg.starts_with(['e', 'w'])
+            || (reg.starts_with('r') && reg.ends_with('d'))
+            // `movq xmm0, xmm0` and `movd` clear the rest of the vector register
+            || (matches!(*op, "movq" | "movd") && reg.contains("mm"));
+        a == b && demangle::registers(a).next() == Some(reg) && !zero_extends
+    }
+
     /// Is this a .global directive?
     pub(crate) fn is_global(&self) -> bool {
         matches!(self, Statement::Directive(Directive::Global(_)))
     }
+
+    /// Symbol defined or exported by a global label or a .global directive
+    pub(crate) fn global_symbol(&self) -> Option<&str> {
+        match self {
+            Statement::Label(Label {
+                id,
+                kind: LabelKind::Global,
+            })
+            | Statement::Directive(Directive::Global(id) | Directive::Weak(_, id)) => Some(id),
+            _ => None,
+        }
+    }
 }
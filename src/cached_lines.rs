@@ -8,13 +8,24 @@ pub struct CachedLines {
 
 impl CachedLines {
     #[must_use]
-    pub fn without_ending(content: String) -> Self {
-        let splits = content.line_spans().map(|s| s.range()).collect::<Vec<_>>();
+    pub fn without_ending(mut content: String) -> Self {
+        // files saved on Windows can start with a byte order mark and use CRLF
+        if content.starts_with('\u{feff}') {
+            content.drain(..'\u{feff}'.len_utf8());
+        }
+        let splits = content
+            .line_spans()
+            .map(|s| {
+                let range = s.range();
+                let len = content[range.clone()].trim_end_matches('\r').len();
+                range.start..range.start + len
+            })
+            .collect::<Vec<_>>();
         Self { content, splits }
     }
 
     #[must_use]
-    pub fn iter(&self) -> LineIter {
+    pub fn iter(&self) -> LineIter<'_> {
         LineIter {
             payload: self,
             current: 0,
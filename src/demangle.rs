@@ -13,7 +13,7 @@ pub fn name(input: &str) -> Option<String> {
 }
 
 #[must_use]
-pub fn demangled(input: &str) -> Option<Demangle> {
+pub fn demangled(input: &str) -> Option<Demangle<'_>> {
     let name = if input.starts_with("__") {
         #[allow(clippy::string_slice)]
         rustc_demangle::try_demangle(&input[1..]).ok()?
@@ -23,6 +23,13 @@ pub fn demangled(input: &str) -> Option<Demangle> {
     Some(name)
 }
 
+/// Is this a v0 mangled name? Unlike legacy mangling v0 keeps generic parameters,
+/// including const generic values
+#[must_use]
+pub fn is_v0(input: &str) -> bool {
+    input.starts_with("_R") || input.starts_with("__R")
+}
+
 pub(self) const GLOBAL_LABELS_REGEX: &str = r"\b_?(_[a-zA-Z0-9_$\.]+)";
 
 // This regex is two parts
@@ -43,6 +50,9 @@ pub(self) const LOCAL_LABELS_REGEX: &str = r"(?:[^\w\d\$\.]|^)(\.L[a-zA-Z0-9_\$\
 // temporary labels
 pub(self) const TEMP_LABELS_REGEX: &str = r"\b(Ltmp[0-9]+)\b";
 
+// x86 general purpose, vector and mask registers, ARM64 general purpose and vector registers
+pub(self) const REGISTERS_REGEX: &str = r"\b(?:[re]?(?:ax|bx|cx|dx|si|di|bp|sp|ip)|[abcd][lh]|(?:si|di|bp|sp)l|r(?:[89]|1[0-5])[dwb]?|[xyz]mm[0-9]{1,2}|k[0-7]|[xwvqdsbh](?:[12]?[0-9]|3[01])|xzr|wzr|lr|fp)\b";
+
 pub(self) fn global_labels_reg() -> &'static Regex {
     static GLOBAL_LABELS: OnceLock<Regex> = OnceLock::new();
     GLOBAL_LABELS.get_or_init(|| Regex::new(GLOBAL_LABELS_REGEX).expect("regexp should be valid"))
@@ -53,6 +63,19 @@ pub(self) fn local_labels_reg() -> &'static Regex {
     LOCAL_LABELS.get_or_init(|| Regex::new(LOCAL_LABELS_REGEX).expect("regexp should be valid"))
 }
 
+pub(self) fn registers_reg() -> &'static Regex {
+    static REGISTERS: OnceLock<Regex> = OnceLock::new();
+    REGISTERS.get_or_init(|| Regex::new(REGISTERS_REGEX).expect("regexp should be valid"))
+}
+
+/// Immediate values and numeric offsets: `$0x10`, `#42`, `-8`, but not digits in symbol names
+pub(self) const IMMEDIATES_REGEX: &str = r"(^|[^\w.$#])([$#]?-?(?:0x[0-9a-fA-F]+|[0-9]+))\b";
+
+pub(self) fn immediates_reg() -> &'static Regex {
+    static IMMEDIATES: OnceLock<Regex> = OnceLock::new();
+    IMMEDIATES.get_or_init(|| Regex::new(IMMEDIATES_REGEX).expect("regexp should be valid"))
+}
+
 pub(self) fn label_kinds_reg() -> &'static RegexSet {
     static LABEL_KINDS: OnceLock<RegexSet> = OnceLock::new();
     LABEL_KINDS.get_or_init(|| {
@@ -81,12 +104,81 @@ fn local_labels_works() {
     assert_eq!(local_labels(s2).collect::<Vec<_>>(), [".Lexception"]);
 }
 
+#[test]
+fn registers_works() {
+    let regs = |s| {
+        registers_reg()
+            .find_iter(s)
+            .map(|m| m.as_str())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        regs("vmovaps xmm0, xmmword ptr [rip + .LCPI0_0]"),
+        ["xmm0", "rip"]
+    );
+    assert_eq!(regs("mov rax, qword ptr [rdi + 8]"), ["rax", "rdi"]);
+    assert_eq!(regs("movl %r8d, %eax"), ["r8d", "eax"]);
+    assert_eq!(regs("ldp x29, x30, [sp], #16"), ["x29", "x30", "sp"]);
+    assert_eq!(regs("jmp .LBB0_3"), [] as [&str; 0]);
+}
+
+#[test]
+fn registers_map_to_their_family() {
+    let families: [(&str, &[&str]); 12] = [
+        ("rax", &["al", "ah", "ax", "eax", "rax"]),
+        ("rdx", &["dl", "dh", "dx", "edx", "rdx"]),
+        ("rsi", &["sil", "si", "esi", "rsi"]),
+        ("rsp", &["spl", "sp", "esp", "rsp"]),
+        ("r8", &["r8b", "r8w", "r8d", "r8"]),
+        ("r15", &["r15b", "r15w", "r15d", "r15"]),
+        ("zmm3", &["xmm3", "ymm3", "zmm3"]),
+        ("x0", &["w0", "x0"]),
+        ("x29", &["w29", "x29", "fp"]),
+        ("v12", &["b12", "h12", "s12", "d12", "q12", "v12"]),
+        ("xzr", &["wzr", "xzr"]),
+        ("k1", &["k1"]),
+    ];
+    for (family, regs) in families {
+        for reg in regs {
+            assert_eq!(register_family(reg), family, "{reg}");
+        }
+    }
+    assert_ne!(register_family("r8"), register_family("rax"));
+    assert_ne!(register_family("al"), register_family("bl"));
+}
+
+#[test]
+fn immediates_works() {
+    let imms = |s| {
+        immediates_reg()
+            .captures_iter(s)
+            .map(|c| c.get(2).unwrap().as_str())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(imms("movq\t$0x10, -8(%rbp)"), ["$0x10", "-8"]);
+    assert_eq!(imms("mov rax, qword ptr [rdi + 8]"), ["8"]);
+    assert_eq!(imms("ldp x29, x30, [sp], #16"), ["#16"]);
+    assert_eq!(imms("lea rdi, [rip + .Lanon.abc.1]"), [] as [&str; 0]);
+    assert_eq!(
+        imms("call _ZN3foo3bar17h0123456789abcdefE"),
+        [] as [&str; 0]
+    );
+}
+
 pub(crate) fn local_labels(input: &str) -> impl Iterator<Item = &str> {
     local_labels_reg()
         .captures_iter(input)
         .filter_map(|c| Some(c.get(1)?.as_str()))
 }
 
+/// Register names mentioned in instruction arguments, instruction pointer is not included
+pub(crate) fn registers(input: &str) -> impl Iterator<Item = &str> {
+    registers_reg()
+        .find_iter(input)
+        .map(|m| m.as_str())
+        .filter(|r| !matches!(*r, "rip" | "eip" | "ip"))
+}
+
 #[must_use]
 pub fn label_kind(input: &str) -> LabelKind {
     match label_kinds_reg().matches(input).into_iter().next() {
@@ -109,6 +201,134 @@ pub fn color_local_labels(input: &str) -> Cow<'_, str> {
     local_labels_reg().replace_all(input, LabelColorizer)
 }
 
+/// Registers that alias each other (`rax`, `eax`, `al` or `x0`, `w0`) get the same color,
+/// every sub-register maps to its widest register
+fn register_family(reg: &str) -> Cow<'_, str> {
+    const LEGACY: [&str; 9] = ["ax", "bx", "cx", "dx", "si", "di", "bp", "sp", "ip"];
+    let legacy = match reg {
+        "al" | "ah" => "ax",
+        "bl" | "bh" => "bx",
+        "cl" | "ch" => "cx",
+        "dl" | "dh" => "dx",
+        "sil" => "si",
+        "dil" => "di",
+        "bpl" => "bp",
+        "spl" => "sp",
+        _ => reg.strip_prefix(['e', 'r']).unwrap_or(reg),
+    };
+    if LEGACY.contains(&legacy) {
+        return Cow::Owned(format!("r{legacy}"));
+    }
+    let number = |rest: &str| !rest.is_empty() && rest.bytes().all(|b| b.is_ascii_digit());
+    // x86 r8b, r8w, r8d, r8
+    if let Some(rest) = reg.strip_prefix('r') {
+        let rest = rest.trim_end_matches(['b', 'w', 'd']);
+        if number(rest) {
+            return Cow::Owned(format!("r{rest}"));
+        }
+    }
+    // x86 xmm0, ymm0, zmm0
+    if let Some(rest) = ["xmm", "ymm", "zmm"]
+        .into_iter()
+        .find_map(|prefix| reg.strip_prefix(prefix))
+    {
+        return Cow::Owned(format!("zmm{rest}"));
+    }
+    // ARM64 w0, x0 and b0, h0, s0, d0, q0, v0
+    match reg.split_at(1) {
+        ("x" | "w", rest) if number(rest) => Cow::Owned(format!("x{rest}")),
+        ("b" | "h" | "s" | "d" | "q" | "v", rest) if number(rest) => Cow::Owned(format!("v{rest}")),
+        _ => match reg {
+            "wzr" => Cow::Borrowed("xzr"),
+            "fp" => Cow::Borrowed("x29"),
+            "lr" => Cow::Borrowed("x30"),
+            _ => Cow::Borrowed(reg),
+        },
+    }
+}
+
+struct RegisterColorizer;
+impl Replacer for RegisterColorizer {
+    fn replace_append(&mut self, caps: &regex::Captures<'_>, dst: &mut String) {
+        use owo_colors::AnsiColors::*;
+        use std::fmt::Write;
+        const PALETTE: [owo_colors::AnsiColors; 10] = [
+            Red,
+            Green,
+            Yellow,
+            Magenta,
+            Cyan,
+            BrightRed,
+            BrightGreen,
+            BrightMagenta,
+            BrightCyan,
+            BrightWhite,
+        ];
+        let reg = &caps[0];
+        // simple and stable between runs, unlike DefaultHasher
+        let hash = register_family(reg).bytes().fold(0usize, |acc, b| {
+            acc.wrapping_mul(31).wrapping_add(b as usize)
+        });
+        let c = PALETTE[hash % PALETTE.len()];
+        write!(dst, "{}", color!(reg, |r| r.color(c))).unwrap();
+    }
+}
+
+/// Colorize register names, registers that alias each other share the color
+pub fn color_registers(input: &str) -> Cow<'_, str> {
+    registers_reg().replace_all(input, RegisterColorizer)
+}
+
+struct ImmediateColorizer;
+impl Replacer for ImmediateColorizer {
+    fn replace_append(&mut self, caps: &regex::Captures<'_>, dst: &mut String) {
+        use std::fmt::Write;
+        dst.push_str(&caps[1]);
+        write!(dst, "{}", color!(&caps[2], OwoColorize::blue)).unwrap();
+    }
+}
+
+/// Colorize immediate values
+///
+/// Must be applied before any other coloring, escape sequences contain numbers too
+pub fn color_immediates(input: &str) -> Cow<'_, str> {
+    immediates_reg().replace_all(input, ImmediateColorizer)
+}
+
+/// Highlight matches of `regex`
+///
+/// Input can be already colored, escape sequences are left intact and matches are
+/// looked for only in the text between them, so coloring stays properly nested
+pub fn highlight<'a>(input: &'a str, regex: &Regex) -> Cow<'a, str> {
+    use std::fmt::Write;
+    if !regex.is_match(input) {
+        return Cow::Borrowed(input);
+    }
+    let mut res = String::with_capacity(input.len());
+    let mut rest = input;
+    while !rest.is_empty() {
+        let (text, escape) = match rest.find('\x1b') {
+            Some(start) => {
+                let len = rest[start..]
+                    .find('m')
+                    .map_or(rest.len() - start, |end| end + 1);
+                (&rest[..start], &rest[start..start + len])
+            }
+            None => (rest, ""),
+        };
+        let mut last = 0;
+        for m in regex.find_iter(text) {
+            res.push_str(&text[last..m.start()]);
+            write!(res, "{}", color!(m.as_str(), OwoColorize::on_blue)).unwrap();
+            last = m.end();
+        }
+        res.push_str(&text[last..]);
+        res.push_str(escape);
+        rest = &rest[text.len() + escape.len()..];
+    }
+    Cow::Owned(res)
+}
+
 struct Demangler {
     display: NameDisplay,
 }
@@ -120,6 +340,13 @@ impl Replacer for Demangler {
                 NameDisplay::Full => {
                     write!(dst, "{:?}", color!(dem, OwoColorize::green)).unwrap();
                 }
+                NameDisplay::Verbose => {
+                    write!(dst, "{:?}", color!(dem, OwoColorize::green)).unwrap();
+                    if !is_v0(&cap[1]) {
+                        let mangled = format!("[{}]", &cap[1]);
+                        write!(dst, " {}", color!(mangled, OwoColorize::bright_black)).unwrap();
+                    }
+                }
                 NameDisplay::Short => {
                     write!(dst, "{:#?}", color!(dem, OwoColorize::green)).unwrap();
                 }
@@ -143,6 +370,18 @@ pub fn global_reference(input: &str) -> Option<&str> {
     global_labels_reg().find(input).map(|m| m.as_str())
 }
 
+/// Strip relocation suffixes and prefixes used to refer to a symbol indirectly:
+/// `foo@GOTPCREL`, `foo@PLT` or `__imp_foo` on Windows become `foo`
+#[must_use]
+pub fn without_indirection(name: &str) -> &str {
+    let name = name.split_once('@').map_or(name, |(name, _)| name);
+    name.strip_prefix("__imp_").unwrap_or(name)
+}
+
+pub fn global_references(input: &str) -> impl Iterator<Item = &str> {
+    global_labels_reg().find_iter(input).map(|m| m.as_str())
+}
+
 #[cfg(test)]
 mod test {
     use owo_colors::set_override;
@@ -157,6 +396,51 @@ mod test {
     const CALL_M: &str = "[rip + __ZN58_$LT$nom..error..ErrorKind$u20$as$u20$core..fmt..Debug$GT$3fmt17hb98704099c11c31fE]";
     const CALL_L: &str = "[rip + _ZN58_$LT$nom..error..ErrorKind$u20$as$u20$core..fmt..Debug$GT$3fmt17hb98704099c11c31fE]";
 
+    const V0: &str = "_RNvMCs3nilrLLJwz5_1gINtB2_3FooKj4_E3getB2_";
+
+    #[test]
+    fn indirection_is_removed() {
+        let got = "qword ptr [rip + _ZN3foo3bar17h0123456789abcdefE@GOTPCREL]";
+        let imp = "qword ptr [rip + __imp__ZN3foo3bar17h0123456789abcdefE]";
+        for input in [got, imp] {
+            let reference = super::global_reference(input).map(super::without_indirection);
+            assert_eq!(reference, Some("_ZN3foo3bar17h0123456789abcdefE"));
+        }
+        assert_eq!(
+            super::without_indirection("_ZN3foo3bar17h0123456789abcdefE@PLT"),
+            "_ZN3foo3bar17h0123456789abcdefE"
+        );
+    }
+
+    #[test]
+    fn v0_const_generics() {
+        set_override(true);
+        assert!(super::is_v0(V0));
+        assert!(!super::is_v0(LINUX));
+        assert_eq!(
+            contents(V0, NameDisplay::Short),
+            "\u{1b}[32m<g::Foo<4>>::get\u{1b}[39m"
+        );
+        assert_eq!(
+            contents(V0, NameDisplay::Full),
+            "\u{1b}[32m<g[2751bffeb96d0be9]::Foo<4usize>>::get\u{1b}[39m"
+        );
+        assert_eq!(
+            contents(V0, NameDisplay::Verbose),
+            contents(V0, NameDisplay::Full)
+        );
+    }
+
+    #[test]
+    fn verbose_names_keep_legacy_symbols() {
+        set_override(true);
+        let x = contents(CALL_L, NameDisplay::Verbose);
+        assert_eq!(
+            x,
+            "[rip + \u{1b}[32m<nom::error::ErrorKind as core::fmt::Debug>::fmt::hb98704099c11c31f\u{1b}[39m \u{1b}[90m[_ZN58_$LT$nom..error..ErrorKind$u20$as$u20$core..fmt..Debug$GT$3fmt17hb98704099c11c31fE]\u{1b}[39m]"
+        );
+    }
+
     #[test]
     fn linux_demangle() {
         assert!(name(LINUX).is_some());
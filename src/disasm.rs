@@ -1,19 +1,20 @@
 use crate::{
     color,
     demangle::{self, demangled},
+    esafeprintln,
     opts::{Format, NameDisplay, OutputStyle, ToDump},
     pick_dump_item, safeprintln, Item,
 };
 use ar::Archive;
 use capstone::{Capstone, Insn};
 use object::{
-    Architecture, Object, ObjectSection, ObjectSymbol, Relocation, RelocationTarget, SectionIndex,
-    SymbolKind,
+    Architecture, Object, ObjectSection, ObjectSymbol, ObjectSymbolTable, Relocation,
+    RelocationTarget, SectionIndex, SymbolIndex, SymbolKind, SymbolScope,
 };
 use owo_colors::OwoColorize;
 use std::{
     collections::{BTreeMap, BTreeSet},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
 /// Reference to some other symbol
@@ -49,45 +50,117 @@ impl std::fmt::Display for HexDump<'_> {
     }
 }
 
-/// disassemble rlib or exe, one file at a time
+impl HexDump<'_> {
+    /// Printable ASCII characters of the bytes, `.` for everything else
+    fn ascii(&self) -> String {
+        self.bytes
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    char::from(b)
+                } else {
+                    '.'
+                }
+            })
+            .collect()
+    }
+}
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Decompress gzip or zstd compressed data, anything else is returned as is
+#[cfg(feature = "compressed")]
+fn decompress(bytes: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    use std::io::Read;
+    let mut res = Vec::new();
+    if bytes.starts_with(GZIP_MAGIC) {
+        flate2::read::GzDecoder::new(bytes.as_slice()).read_to_end(&mut res)?;
+    } else if bytes.starts_with(ZSTD_MAGIC) {
+        ruzstd::streaming_decoder::StreamingDecoder::new(bytes.as_slice())
+            .map_err(|e| anyhow::anyhow!("Can't decompress zstd data: {e}"))?
+            .read_to_end(&mut res)?;
+    } else {
+        return Ok(bytes);
+    }
+    Ok(res)
+}
+
+#[cfg(not(feature = "compressed"))]
+fn decompress(bytes: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    if bytes.starts_with(GZIP_MAGIC) || bytes.starts_with(ZSTD_MAGIC) {
+        anyhow::bail!(
+            "Input looks compressed, cargo-show-asm needs to be compiled with compressed feature to handle it"
+        );
+    }
+    Ok(bytes)
+}
+
+/// disassemble rlibs, executables or object files, symbols from all of them are listed
+/// and searched together
+///
+/// Both the file itself and archive members can be gzip or zstd compressed
 pub fn dump_disasm(
     goal: ToDump,
-    file: &Path,
+    files: &[PathBuf],
     fmt: &Format,
     syntax: OutputStyle,
 ) -> anyhow::Result<()> {
-    if file.extension().map_or(false, |e| e == "rlib") {
-        let mut slices = Vec::new();
-        let mut archive = Archive::new(std::fs::File::open(file)?);
+    let mut slices = Vec::new();
+    for file in files {
+        read_slices(file, &mut slices)?;
+    }
+    dump_slices(goal, slices.as_slice(), fmt, syntax)
+}
+
+/// Read a file, archives such as rlib are split into object files they contain
+fn read_slices(file: &Path, slices: &mut Vec<Vec<u8>>) -> anyhow::Result<()> {
+    let binary_data = decompress(std::fs::read(file)?)?;
+    if binary_data.starts_with(b"!<arch>\n") {
+        let mut archive = Archive::new(binary_data.as_slice());
 
         while let Some(entry) = archive.next_entry() {
             let mut entry = entry?;
             let name = std::str::from_utf8(entry.header().identifier())?;
+            let name = name
+                .strip_suffix(".gz")
+                .or_else(|| name.strip_suffix(".zst"))
+                .unwrap_or(name);
             if !name.ends_with(".o") {
                 continue;
             }
             let mut bytes = Vec::new();
             std::io::Read::read_to_end(&mut entry, &mut bytes)?;
-            slices.push(bytes);
+            slices.push(decompress(bytes)?);
         }
-        dump_slices(goal, slices.as_slice(), fmt, syntax)
     } else {
-        let binary_data = std::fs::read(file)?;
-        dump_slices(goal, &[binary_data][..], fmt, syntax)
+        slices.push(binary_data);
     }
+    Ok(())
 }
 
-fn pick_item<'a>(
-    goal: ToDump,
+/// Where to find the code of a symbol: file, symbol, section, address and length
+type Target<'a> = (
+    &'a object::File<'a>,
+    SymbolIndex,
+    SectionIndex,
+    usize,
+    usize,
+);
+
+fn collect_items<'a>(
     files: &'a [object::File],
     fmt: &Format,
-) -> anyhow::Result<(&'a object::File<'a>, SectionIndex, usize, usize)> {
+) -> anyhow::Result<BTreeMap<Item, Target<'a>>> {
     let mut items = BTreeMap::new();
 
     for file in files {
         for (index, symbol) in file
             .symbols()
-            .filter(|s| s.is_definition() && s.kind() == SymbolKind::Text)
+            .filter(|s| {
+                s.is_definition()
+                    && (s.kind() == SymbolKind::Text || (fmt.data && s.kind() == SymbolKind::Data))
+            })
             .enumerate()
         {
             let raw_name = symbol.name()?;
@@ -111,17 +184,18 @@ fn pick_item<'a>(
                 hashed,
                 index,
                 len,
+                position: addr,
                 non_blank_len: len,
+                body_hash: None,
+                merged: false,
+                weak: symbol.is_weak(),
+                object: symbol.kind() == SymbolKind::Data,
                 mangled_name: raw_name.to_owned(),
             };
-            items.insert(item, (file, section_index, addr, len));
+            items.insert(item, (file, symbol.index(), section_index, addr, len));
         }
     }
-
-    // there are things that can be supported and there are things that I consider useful to
-    // support. --everything with --disasm is not one of them for now
-    pick_dump_item(goal, fmt, &items)
-        .ok_or_else(|| anyhow::anyhow!("no can do --everything with --disasm"))
+    Ok(items)
 }
 
 /// Get printable name from relocation info
@@ -146,6 +220,63 @@ fn reloc_info<'a>(
     })
 }
 
+/// Names for PLT stubs and GOT slots of dynamically linked functions in `x86_64` executables,
+/// such as `printf@plt`: unlike object files executables have no relocations for those
+fn dynamic_names(file: &object::File) -> anyhow::Result<Vec<(u64, String)>> {
+    let mut res = Vec::new();
+    let (Architecture::X86_64, Some(relocs), Some(dynsyms)) = (
+        file.architecture(),
+        file.dynamic_relocations(),
+        file.dynamic_symbol_table(),
+    ) else {
+        return Ok(res);
+    };
+    let got = relocs
+        .filter_map(|(slot, reloc)| match reloc.target() {
+            RelocationTarget::Symbol(sym) => {
+                Some((slot, dynsyms.symbol_by_index(sym).ok()?.name().ok()?))
+            }
+            _ => None,
+        })
+        .collect::<BTreeMap<_, _>>();
+    for (slot, name) in &got {
+        res.push((*slot, format!("{name}@GOTPCREL")));
+    }
+
+    // Each stub jumps to an address stored in a GOT slot: `jmp qword ptr [rip + disp]`,
+    // possibly after `endbr64` and followed by more code for lazy binding and padding
+    // mnemonics below are in Intel syntax
+    let cs = make_capstone(file, OutputStyle::Intel, false)?;
+    for section in file.sections() {
+        if !matches!(section.name(), Ok(".plt" | ".plt.sec" | ".plt.got")) {
+            continue;
+        }
+        let insns = cs.disasm_all(section.data()?, section.address())?;
+        let mut entry = None;
+        for insn in insns.iter() {
+            let mnemonic = insn.mnemonic().unwrap_or_default();
+            if mnemonic.starts_with("nop") {
+                continue;
+            }
+            let entry_addr = *entry.get_or_insert(insn.address());
+            if mnemonic.ends_with("jmp") {
+                if let Some(name) = rip_relative(&cs, insn).and_then(|slot| got.get(&slot)) {
+                    res.push((entry_addr, format!("{name}@plt")));
+                }
+                entry = None;
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Version of the linked Capstone library
+#[must_use]
+pub fn capstone_version() -> String {
+    let (major, minor) = Capstone::lib_version();
+    format!("{major}.{minor}")
+}
+
 fn dump_slices(
     goal: ToDump,
     binary_data: &[Vec<u8>],
@@ -156,31 +287,104 @@ fn dump_slices(
         .iter()
         .map(|data| object::File::parse(data.as_slice()))
         .collect::<Result<Vec<_>, _>>()?;
-    let (file, section_index, addr, len) = pick_item(goal, &files, fmt)?;
+    let items = collect_items(&files, fmt)?;
+    let dynamic_names = files
+        .iter()
+        .map(dynamic_names)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    // used to resolve references when there's no relocation info
+    let mut symbol_names = files
+        .iter()
+        .flat_map(|f| f.symbols())
+        .map(|s| {
+            let name = s.name().unwrap();
+            let name = name.split_once('$').map_or(name, |(p, _)| p);
+            let reloc = Reference {
+                name,
+                name_display: fmt.name_display,
+            };
+            (s.address(), reloc)
+        })
+        .collect::<BTreeMap<_, _>>();
+    for (addr, name) in dynamic_names.iter().flatten() {
+        symbol_names.entry(*addr).or_insert(Reference {
+            name,
+            name_display: fmt.name_display,
+        });
+    }
+
+    match pick_dump_item(goal, fmt, &items) {
+        Some(target) => dump_symbol(target, &symbol_names, fmt, syntax),
+        None => {
+            for (item, target) in items {
+                let name = Reference {
+                    name: &item.mangled_name,
+                    name_display: fmt.name_display,
+                };
+                safeprintln!("\n{}:", color!(name, OwoColorize::bright_green));
+                dump_symbol(target, &symbol_names, fmt, syntax)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Print symbol's binding, visibility, containing section and address range
+fn dump_symbol_header(
+    file: &object::File,
+    symbol_index: SymbolIndex,
+    section: &object::Section,
+    addr: usize,
+    len: usize,
+) -> anyhow::Result<()> {
+    let symbol = file.symbol_by_index(symbol_index)?;
+    let binding = if symbol.is_weak() {
+        "weak"
+    } else if symbol.is_global() {
+        "global"
+    } else {
+        "local"
+    };
+    let visibility = match symbol.scope() {
+        SymbolScope::Unknown => "unknown visibility",
+        SymbolScope::Compilation => "visible to compilation unit",
+        SymbolScope::Linkage => "hidden",
+        SymbolScope::Dynamic => "exported",
+    };
+    let header = format!(
+        "# {binding}, {visibility}, section {}, {addr:#x}..{:#x}",
+        section.name()?,
+        addr + len
+    );
+    safeprintln!("{}", color!(header, OwoColorize::cyan));
+    Ok(())
+}
+
+/// Disassemble a single symbol
+fn dump_symbol(
+    (file, symbol_index, section_index, addr, len): Target,
+    all_symbols: &BTreeMap<u64, Reference>,
+    fmt: &Format,
+    syntax: OutputStyle,
+) -> anyhow::Result<()> {
     let mut opcode_cache = BTreeMap::new();
 
     let section = file.section_by_index(section_index)?;
+    dump_symbol_header(file, symbol_index, &section, addr, len)?;
+    if file.symbol_by_index(symbol_index)?.kind() == SymbolKind::Data {
+        return dump_data(&section, addr, len, fmt);
+    }
     let reloc_map = section.relocations().collect::<BTreeMap<_, _>>();
 
     // if relocation map is present - addresses are going to be base 0 = useless
     //
     // For executable files there will be just one section...
+    let no_symbols = BTreeMap::new();
     let symbol_names = if reloc_map.is_empty() {
-        files
-            .iter()
-            .flat_map(|f| f.symbols())
-            .map(|s| {
-                let name = s.name().unwrap();
-                let name = name.split_once('$').map_or(name, |(p, _)| p);
-                let reloc = Reference {
-                    name,
-                    name_display: fmt.name_display,
-                };
-                (s.address(), reloc)
-            })
-            .collect::<BTreeMap<_, _>>()
+        all_symbols
     } else {
-        BTreeMap::new()
+        &no_symbols
     };
 
     // In ARM ELF files, bit zero of the symbol address indicates its encoding.
@@ -188,6 +392,9 @@ fn dump_slices(
     // "a32") instructions. See ARM Arch ABI, 2024Q3, ELF, section 5.5.3.
     let is_thumb = addr & 1 == 1;
     let addr = addr & !1;
+    let Some((addr, len)) = address_window(fmt, addr, len) else {
+        return Ok(());
+    };
     let start = addr - section.address() as usize;
     let cs = make_capstone(file, syntax, is_thumb)?;
     let code = &section.data()?[start..start + len];
@@ -201,8 +408,11 @@ fn dump_slices(
     }
 
     let insns = cs.disasm_all(code, addr as u64)?;
-    if insns.is_empty() && fmt.verbosity > 0 {
-        safeprintln!("No instructions - empty code block?");
+    if insns.is_empty() {
+        if fmt.verbosity > 0 {
+            safeprintln!("No instructions - empty code block?");
+        }
+        return Ok(());
     }
 
     let max_width = insns.iter().map(|i| i.len()).max().unwrap_or(1);
@@ -252,7 +462,8 @@ fn dump_slices(
         // binary code will have pending relocations if we are dealing with disassembling a library
         // code or with relocations already applied if we are working with a binary
         let mut refn = reloc_info(file, &reloc_map, insn, fmt)
-            .or_else(|| maddr.and_then(|addr| symbol_names.get(&addr).copied()));
+            .or_else(|| maddr.and_then(|addr| symbol_names.get(&addr).copied()))
+            .or_else(|| rip_relative(&cs, insn).and_then(|addr| symbol_names.get(&addr).copied()));
 
         if let Some(id) = local_labels.get(&addr) {
             use owo_colors::OwoColorize;
@@ -265,7 +476,9 @@ fn dump_slices(
 
         let i = crate::asm::Instruction {
             op: insn.mnemonic().unwrap_or("???"),
+            sep: " ",
             args: insn.op_str(),
+            encoding: None,
         };
 
         if let Some(id) = maddr.and_then(|a| local_labels.get(&a)) {
@@ -294,6 +507,68 @@ fn dump_slices(
     Ok(())
 }
 
+/// Print data symbol as a hex dump, 16 bytes per row with their ASCII representation
+fn dump_data(
+    section: &object::Section,
+    addr: usize,
+    len: usize,
+    fmt: &Format,
+) -> anyhow::Result<()> {
+    const WIDTH: usize = 16;
+    let Some((addr, len)) = address_window(fmt, addr, len) else {
+        return Ok(());
+    };
+    let start = addr - section.address() as usize;
+    let Some(bytes) = section.data()?.get(start..start + len) else {
+        let msg = format!("# no data in the file, {len} bytes of zeroes");
+        safeprintln!("{}", color!(msg, OwoColorize::cyan));
+        return Ok(());
+    };
+    for (ix, chunk) in bytes.chunks(WIDTH).enumerate() {
+        let hex = HexDump {
+            max_width: WIDTH,
+            bytes: chunk,
+        };
+        let ascii = hex.ascii();
+        safeprintln!(
+            "{:8x}:    {hex}|{}|",
+            addr + ix * WIDTH,
+            color!(ascii, OwoColorize::bright_yellow)
+        );
+    }
+    Ok(())
+}
+
+/// Narrow symbol's `addr..addr + len` down to `--start-address` and `--stop-address`
+fn address_window(fmt: &Format, addr: usize, len: usize) -> Option<(usize, usize)> {
+    let end = addr + len;
+    let mut from = addr;
+    let mut to = end;
+    if let Some(start) = fmt.start_address.and_then(|a| usize::try_from(a).ok()) {
+        if (addr..end).contains(&start) {
+            from = start;
+        } else {
+            esafeprintln!(
+                "Warning: start address {start:#x} is outside of the symbol {addr:#x}..{end:#x}"
+            );
+        }
+    }
+    if let Some(stop) = fmt.stop_address.and_then(|a| usize::try_from(a).ok()) {
+        if (addr..=end).contains(&stop) {
+            to = stop;
+        } else {
+            esafeprintln!(
+                "Warning: stop address {stop:#x} is outside of the symbol {addr:#x}..{end:#x}"
+            );
+        }
+    }
+    if from >= to {
+        esafeprintln!("Warning: address range {from:#x}..{to:#x} is empty");
+        return None;
+    }
+    Some((from, to - from))
+}
+
 fn get_reference(cs: &Capstone, insn: &Insn) -> Option<u64> {
     use capstone::arch::{
         arm64::Arm64OperandType, x86::X86OperandType, ArchDetail, DetailsArchInsn,
@@ -330,6 +605,21 @@ fn get_reference(cs: &Capstone, insn: &Insn) -> Option<u64> {
     }
 }
 
+/// Address referred by a `[rip + disp]` memory operand
+fn rip_relative(cs: &Capstone, insn: &Insn) -> Option<u64> {
+    use capstone::arch::{x86::X86OperandType, ArchDetail, DetailsArchInsn};
+    let details = cs.insn_detail(insn).ok()?;
+    let ArchDetail::X86Detail(x86) = details.arch_detail() else {
+        return None;
+    };
+    x86.operands().find_map(|op| match op.op_type {
+        X86OperandType::Mem(mem) if cs.reg_name(mem.base()).as_deref() == Some("rip") => {
+            (insn.address() + insn.len() as u64).checked_add_signed(mem.disp())
+        }
+        _ => None,
+    })
+}
+
 impl From<OutputStyle> for capstone::Syntax {
     fn from(value: OutputStyle) -> Self {
         match value {
@@ -377,3 +667,13 @@ fn make_capstone(
     capstone.set_endian(endiannes)?;
     Ok(capstone)
 }
+
+#[test]
+fn hex_dump_ascii_column() {
+    let hex = HexDump {
+        max_width: 4,
+        bytes: b"a b\n",
+    };
+    assert_eq!(hex.ascii(), "a b.");
+    assert_eq!(hex.to_string(), "61 20 62 0a    ");
+}
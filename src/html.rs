@@ -0,0 +1,181 @@
+//! Self-contained HTML page with the dumped code, for `--html`
+//!
+//! The page is made from the same output that goes to the terminal, printed with colors
+//! forced on. ANSI color sequences are then translated into CSS classes.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+const HEADER: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>cargo-show-asm</title>
+<style>
+body { background: #1e1e1e; color: #d4d4d4; }
+pre { font-family: monospace; border-bottom: 1px solid #444; padding-bottom: 1em; }
+a { color: inherit; text-decoration: underline dotted; }
+.c1 { font-weight: bold; }
+.c31 { color: #cd3131; } .c32 { color: #0dbc79; } .c33 { color: #e5e510; } .c34 { color: #2472c8; }
+.c35 { color: #bc3fbc; } .c36 { color: #11a8cd; } .c37 { color: #e5e5e5; } .c90 { color: #767676; }
+.c91 { color: #f14c4c; } .c92 { color: #23d18b; } .c93 { color: #f5f543; } .c94 { color: #3b8eea; }
+.c95 { color: #d670d6; } .c96 { color: #29b8db; } .c97 { color: #ffffff; }
+.c44 { background: #2472c8; }
+</style>
+</head>
+<body>
+"#;
+
+const FOOTER: &str = "</body>\n</html>\n";
+
+/// Instructions that call or tail call a function
+const CALLS: &[&str] = &["call", "callq", "bl", "jmp", "jmpq", "b"];
+
+/// Turn colored output into a page, calls are linked to functions present anywhere on the page
+pub(crate) fn page(output: &str) -> String {
+    let plain = output
+        .lines()
+        .map(|line| {
+            let mut res = Vec::new();
+            crate::strip_colors(line.as_bytes(), &mut false, &mut res);
+            String::from_utf8_lossy(&res).into_owned()
+        })
+        .collect::<Vec<_>>();
+    // name => line with the first label for it
+    let mut anchors = BTreeMap::new();
+    for (ix, line) in plain.iter().enumerate() {
+        if let Some(name) = function_label(line) {
+            anchors.entry(name).or_insert(ix);
+        }
+    }
+
+    let mut out = String::from(HEADER);
+    out.push_str("<pre>\n");
+    for (ix, (line, plain)) in output.lines().zip(&plain).enumerate() {
+        if function_label(plain).and_then(|name| anchors.get(name)) == Some(&ix) {
+            let _ = write!(out, "<span id=\"L{ix}\"></span>");
+        }
+        let html = ansi_to_html(line);
+        match call_target(plain).and_then(|name| anchors.get(name)) {
+            Some(target) => {
+                let _ = writeln!(out, "<a href=\"#L{target}\">{html}</a>");
+            }
+            None => {
+                out.push_str(&html);
+                out.push('\n');
+            }
+        }
+    }
+    out.push_str("</pre>\n");
+    out.push_str(FOOTER);
+    out
+}
+
+/// Line without a trailing comment such as `# tail call to` or the mangled name
+fn code(line: &str) -> &str {
+    let end = [" #", " //"]
+        .iter()
+        .filter_map(|comment| line.find(comment))
+        .min()
+        .unwrap_or(line.len());
+    line[..end].trim_end()
+}
+
+/// Name of a function that starts at this line, local labels start with `.`
+fn function_label(line: &str) -> Option<&str> {
+    let name = code(line).strip_suffix(':')?;
+    let local = name.is_empty() || name.starts_with(['.', ' ', '\t']);
+    (!local).then_some(name)
+}
+
+/// Name of a function called or tail called from this line, instruction can be preceded by
+/// its number with `--offsets` or its bytes with `--encoding`
+fn call_target(line: &str) -> Option<&str> {
+    let mut rest = code(line).trim_start();
+    loop {
+        let (word, tail) = rest.split_once([' ', '\t'])?;
+        if CALLS.contains(&word) {
+            return Some(tail.trim());
+        }
+        if !word.bytes().all(|b| b.is_ascii_hexdigit() || b == b'?') {
+            return None;
+        }
+        rest = tail.trim_start();
+    }
+}
+
+fn escape(input: &str) -> String {
+    let mut res = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '&' => res.push_str("&amp;"),
+            '"' => res.push_str("&quot;"),
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+/// Replace ANSI SGR sequences with `<span>`s, `\x1b[36m` becomes `<span class="c36">`
+fn ansi_to_html(input: &str) -> String {
+    let mut res = String::new();
+    let mut open = 0usize;
+    let mut rest = input;
+    while let Some(start) = rest.find("\x1b[") {
+        res.push_str(&escape(&rest[..start]));
+        let Some(len) = rest[start..].find('m') else {
+            rest = &rest[start..];
+            break;
+        };
+        for code in rest[start + 2..start + len].split(';') {
+            match code {
+                "" | "0" => {
+                    res.push_str(&"</span>".repeat(open));
+                    open = 0;
+                }
+                "22" | "39" | "49" => {
+                    if open > 0 {
+                        res.push_str("</span>");
+                        open -= 1;
+                    }
+                }
+                code => {
+                    let _ = write!(res, "<span class=\"c{code}\">");
+                    open += 1;
+                }
+            }
+        }
+        rest = &rest[start + len + 1..];
+    }
+    res.push_str(&escape(rest));
+    res.push_str(&"</span>".repeat(open));
+    res
+}
+
+#[test]
+fn ansi_is_converted_to_spans() {
+    assert_eq!(
+        ansi_to_html("\x1b[36m// a<b>\x1b[39m c"),
+        "<span class=\"c36\">// a&lt;b&gt;</span> c"
+    );
+    assert_eq!(
+        ansi_to_html("\x1b[95mmov\x1b[39m"),
+        "<span class=\"c95\">mov</span>"
+    );
+    assert_eq!(
+        ansi_to_html("\x1b[1mbold"),
+        "<span class=\"c1\">bold</span>"
+    );
+}
+
+#[test]
+fn calls_are_linked_to_functions() {
+    let output = "foo:\n\tcall\tbar\n\tcall\tbaz\n\tretq\n\nbar: # _ZN3bar\n\tretq\n";
+    let page = page(output);
+    assert!(page.contains("<span id=\"L5\"></span>bar: # _ZN3bar\n"));
+    assert!(page.contains("<a href=\"#L5\">\tcall\tbar</a>\n"));
+    assert!(page.contains("\tcall\tbaz\n"));
+    assert!(!page.contains("href=\"#L0\""));
+}
@@ -1,11 +1,13 @@
 #![doc = include_str!("../README.md")]
 
-use opts::{Format, NameDisplay, ToDump};
+use opts::{Format, NameDisplay, SortBy, ToDump};
 use std::{
     array,
     collections::{BTreeMap, BTreeSet},
     ops::Range,
     path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::{Mutex, PoisonError},
 };
 
 pub mod asm;
@@ -13,6 +15,7 @@ pub mod cached_lines;
 pub mod demangle;
 #[cfg(feature = "disasm")]
 pub mod disasm;
+mod html;
 pub mod llvm;
 pub mod mca;
 pub mod mir;
@@ -35,7 +38,7 @@ macro_rules! color {
 macro_rules! safeprintln {
     ($($x:expr),* $(,)?) => {{
         use std::io::Write;
-        if writeln!(std::io::stdout(), $($x),*).is_err() {
+        if writeln!($crate::output(), $($x),*).is_err() {
             std::process::exit(0);
         }
     }};
@@ -45,7 +48,7 @@ macro_rules! safeprintln {
 macro_rules! safeprint {
     ($($x:expr),* $(,)?) => {{
         use std::io::Write;
-        if write!(std::io::stdout(), $($x),*).is_err() {
+        if write!($crate::output(), $($x),*).is_err() {
             std::process::exit(0);
         }
     }};
@@ -71,6 +74,245 @@ macro_rules! esafeprint {
     }};
 }
 
+/// Stdout settings shared by everything printed with [`safeprint`] and [`safeprintln`]
+struct Stream {
+    /// Replace tabs at the start of lines with this many spaces, 0 keeps them
+    tab_width: usize,
+    line: LineState,
+    pager: Option<Pager>,
+    recording: Option<Recording>,
+}
+
+/// Output is collected and handed to the pager once everything is printed, this way
+/// diagnostics on stderr and cargo's progress don't end up on top of the pager screen
+struct Pager {
+    command: String,
+    buffer: Vec<u8>,
+}
+
+/// Copy of everything printed, used by `--html`. Colors are forced on while recording so
+/// they are removed from what goes to the terminal if it can't show them
+struct Recording {
+    bytes: Vec<u8>,
+    strip_colors: bool,
+    in_escape: bool,
+}
+
+static STREAM: Mutex<Stream> = Mutex::new(Stream {
+    tab_width: 0,
+    line: LineState {
+        at_start: true,
+        in_escape: false,
+    },
+    pager: None,
+    recording: None,
+});
+
+/// Apply output options from `fmt` to stdout used by [`safeprint`] and [`safeprintln`]:
+/// `tab_width` and `pager`. Paged output is shown when the returned guard is dropped
+/// or with [`exit`]
+pub fn setup_output(fmt: &Format) -> OutputGuard {
+    let mut stream = STREAM.lock().unwrap_or_else(PoisonError::into_inner);
+    stream.tab_width = fmt.tab_width;
+    stream.pager = fmt
+        .pager
+        .then(|| opts::pager_command(|name| std::env::var_os(name)))
+        .flatten()
+        .map(|command| Pager {
+            command,
+            buffer: Vec::new(),
+        });
+    OutputGuard
+}
+
+/// Shows the output collected for the pager when dropped, panics included
+#[must_use]
+pub struct OutputGuard;
+
+impl Drop for OutputGuard {
+    fn drop(&mut self) {
+        finish_output();
+    }
+}
+
+/// Send the output collected for the pager to it and wait for the pager to exit, does
+/// nothing if there's no pager
+fn finish_output() {
+    use std::io::Write;
+    let pager = STREAM
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .pager
+        .take();
+    let Some(Pager { command, buffer }) = pager else {
+        return;
+    };
+    match spawn_pager(&command) {
+        Ok(mut child) => {
+            let mut stdin = child.stdin.take().expect("Stdin should be piped");
+            // pager can quit before reading everything
+            let _ = stdin.write_all(&buffer);
+            // closing stdin tells the pager there's no more input
+            drop(stdin);
+            let _ = child.wait();
+        }
+        Err(err) => {
+            esafeprintln!("Failed to start pager {command:?}: {err}");
+            let _ = std::io::stdout().write_all(&buffer);
+        }
+    }
+}
+
+/// [`std::process::exit`] that shows the output collected for the pager first
+pub fn exit(code: i32) -> ! {
+    finish_output();
+    std::process::exit(code)
+}
+
+/// Run `f` with colors forced on and return everything it printed along with its result
+fn record_output<R>(f: impl FnOnce() -> R) -> (R, String) {
+    let strip_colors = !color!("", owo_colors::OwoColorize::red)
+        .to_string()
+        .contains('\x1b');
+    STREAM
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .recording = Some(Recording {
+        bytes: Vec::new(),
+        strip_colors,
+        in_escape: false,
+    });
+    let res = owo_colors::with_override(true, f);
+    let recording = STREAM
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .recording
+        .take();
+    let bytes = recording.map(|r| r.bytes).unwrap_or_default();
+    (res, String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Stdout used by [`safeprint`] and [`safeprintln`], configured with [`setup_output`]
+#[must_use]
+pub fn output() -> Output {
+    Output
+}
+
+pub struct Output;
+
+impl std::io::Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = buf.len();
+        let mut stream = STREAM.lock().unwrap_or_else(PoisonError::into_inner);
+        let Stream {
+            tab_width,
+            line,
+            pager,
+            recording,
+        } = &mut *stream;
+        let mut plain = Vec::new();
+        let buf = match recording {
+            Some(recording) => {
+                recording.bytes.extend_from_slice(buf);
+                if recording.strip_colors {
+                    strip_colors(buf, &mut recording.in_escape, &mut plain);
+                    &plain
+                } else {
+                    buf
+                }
+            }
+            None => buf,
+        };
+        let mut expanded = Vec::new();
+        let bytes = if *tab_width == 0 {
+            buf
+        } else {
+            expand_tabs(buf, *tab_width, line, &mut expanded);
+            &expanded
+        };
+        match pager {
+            Some(pager) => pager.buffer.extend_from_slice(bytes),
+            None => std::io::stdout().write_all(bytes)?,
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stdout().flush()
+    }
+}
+
+fn spawn_pager(command: &str) -> std::io::Result<Child> {
+    let mut words = command.split_whitespace();
+    let mut pager = Command::new(words.next().unwrap_or("less"));
+    pager.args(words).stdin(Stdio::piped());
+    if std::env::var_os("LESS").is_none() {
+        // quit if output fits on one screen, pass colors through, don't clear the screen
+        pager.env("LESS", "FRX");
+    }
+    pager.spawn()
+}
+
+/// Output can arrive in pieces, this keeps track of where the previous piece ended
+struct LineState {
+    /// only tabs and color sequences were printed on this line so far
+    at_start: bool,
+    /// inside of an ANSI color sequence
+    in_escape: bool,
+}
+
+fn expand_tabs(buf: &[u8], width: usize, state: &mut LineState, out: &mut Vec<u8>) {
+    for &byte in buf {
+        if state.in_escape {
+            state.in_escape = !byte.is_ascii_alphabetic();
+            out.push(byte);
+            continue;
+        }
+        match byte {
+            b'\t' if state.at_start => {
+                out.extend(std::iter::repeat_n(b' ', width));
+                continue;
+            }
+            b'\n' => state.at_start = true,
+            0x1b => state.in_escape = true,
+            _ => state.at_start = false,
+        }
+        out.push(byte);
+    }
+}
+
+fn strip_colors(buf: &[u8], in_escape: &mut bool, out: &mut Vec<u8>) {
+    for &byte in buf {
+        if *in_escape {
+            *in_escape = !byte.is_ascii_alphabetic();
+        } else if byte == 0x1b {
+            *in_escape = true;
+        } else {
+            out.push(byte);
+        }
+    }
+}
+
+/// Exit codes for failures scripts might want to tell apart, cargo failures exit with 101
+pub mod exit_code {
+    /// Requested item doesn't exist
+    pub const NOT_FOUND: i32 = 2;
+    /// More than one item matches the request
+    pub const AMBIGUOUS: i32 = 3;
+    /// Rust sources are needed but `rust-src` component is not installed
+    pub const NO_RUST_SRC: i32 = 4;
+}
+
+/// Cargo home directory, `$CARGO_HOME` or `~/.cargo`
+#[must_use]
+pub fn cargo_home() -> Option<PathBuf> {
+    if let Some(home) = std::env::var_os("CARGO_HOME") {
+        return Some(PathBuf::from(home));
+    }
+    #[allow(deprecated)] // deprecation is about Windows quirks, CARGO_HOME is checked first
+    std::env::home_dir().map(|home| home.join(".cargo"))
+}
+
 /// read a set of source files to a set of strings
 ///
 /// perform lossy conversion to utf8
@@ -82,11 +324,23 @@ pub fn read_sources(names: &[PathBuf]) -> anyhow::Result<Vec<String>> {
             // For some reason llvm/rustc can produce non utf8 files...
             // Also there's no (without unsafe) way to reuse allocation
             // from bytes in resulting String...
-            Ok(String::from_utf8_lossy(&bytes).into_owned())
+            Ok(to_text(&bytes))
         })
         .collect()
 }
 
+/// Lossy conversion to UTF-8 with byte order mark removed and CRLF line endings replaced
+/// with LF, files produced or edited on Windows can have both
+fn to_text(bytes: &[u8]) -> String {
+    let text = String::from_utf8_lossy(bytes);
+    let text = text.strip_prefix('\u{feff}').unwrap_or(&text);
+    if text.contains('\r') {
+        text.replace("\r\n", "\n")
+    } else {
+        text.to_owned()
+    }
+}
+
 #[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq)]
 pub struct Item {
     // name and hashed MUST be first two fields - they are
@@ -99,10 +353,20 @@ pub struct Item {
     pub index: usize,
     /// number of lines
     pub len: usize,
+    /// where the item starts in its file: line or address, used by `--sort index`
+    pub position: usize,
     /// number of non-blank lines
     pub non_blank_len: usize,
     /// mangled name
     pub mangled_name: String,
+    /// hash of a normalized body, only calculated with `--group-identical`
+    pub body_hash: Option<u64>,
+    /// alias for a body of a different function, created by `.set` when functions are merged
+    pub merged: bool,
+    /// weak symbol, can be replaced by a strong definition with the same name at link time
+    pub weak: bool,
+    /// data rather than code: a static in `.data`, `.rodata` or `.bss`
+    pub object: bool,
 }
 
 pub fn suggest_name<'a>(
@@ -110,16 +374,35 @@ pub fn suggest_name<'a>(
     fmt: &Format,
     items: impl IntoIterator<Item = &'a Item>,
 ) -> ! {
+    let items = items.into_iter().collect::<Vec<_>>();
+    // cold parts are listed together with their parent function
+    let mangled = items
+        .iter()
+        .map(|item| item.mangled_name.as_str())
+        .collect::<BTreeSet<_>>();
+    let is_cold_part =
+        |item: &Item| split_parent(&item.mangled_name).is_some_and(|p| mangled.contains(p));
+    let mut cold_parts = BTreeMap::<&str, usize>::new();
+    for item in &items {
+        if let Some(parent) = split_parent(&item.mangled_name).filter(|p| mangled.contains(p)) {
+            *cold_parts.entry(parent).or_default() += 1;
+        }
+    }
+
     let mut count = 0usize;
-    let names: BTreeMap<&String, Vec<usize>> =
-        items.into_iter().fold(BTreeMap::new(), |mut m, item| {
+    let mut aliases = BTreeMap::<u64, usize>::new();
+    let names: BTreeMap<&String, Vec<&Item>> =
+        items.iter().fold(BTreeMap::new(), |mut m, &item| {
             count += 1;
+            if let Some(hash) = item.body_hash {
+                *aliases.entry(hash).or_default() += 1;
+            }
             let entry = match fmt.name_display {
-                NameDisplay::Full => &item.hashed,
+                NameDisplay::Full | NameDisplay::Verbose => &item.hashed,
                 NameDisplay::Short => &item.name,
                 NameDisplay::Mangled => &item.mangled_name,
             };
-            m.entry(entry).or_default().push(item.non_blank_len);
+            m.entry(entry).or_default().push(item);
             m
         });
 
@@ -142,17 +425,234 @@ pub fn suggest_name<'a>(
     #[allow(clippy::cast_precision_loss)]
     let width = (count as f64).log10().ceil() as usize;
 
+    // rows are collected in alphabetical order first: indices and which one of several
+    // identical functions gets listed depend on it
     let mut ix = 0;
-    for (name, lens) in &names {
-        safeprintln!(
+    let mut shown = BTreeSet::new();
+    let mut rows = Vec::new();
+    for (name, items) in &names {
+        // only the first item out of several identical ones is listed
+        let (lens, identical) = items
+            .iter()
+            .filter(|item| !is_cold_part(item))
+            .filter(|item| !(fmt.no_merged && item.merged))
+            .filter(|item| item.body_hash.is_none_or(|hash| shown.insert(hash)))
+            .fold((Vec::new(), 0), |(mut lens, identical), item| {
+                lens.push(item.non_blank_len);
+                let same = item.body_hash.map_or(1, |hash| aliases[&hash]);
+                (lens, identical + same - 1)
+            });
+        if !lens.is_empty() {
+            let cold = items
+                .iter()
+                .filter_map(|item| cold_parts.get(item.mangled_name.as_str()))
+                .sum::<usize>();
+            let weak = items.iter().any(|item| item.weak);
+            let object = items.iter().all(|item| item.object);
+            let position = items.iter().map(|item| item.position).min();
+            rows.push((ix, name, lens, identical, cold, weak, object, position));
+        }
+        ix += items.len();
+    }
+    match fmt.sort {
+        SortBy::Name => {}
+        SortBy::Index => rows.sort_by_key(|row| row.7),
+        SortBy::Size => rows.sort_by_key(|row| std::cmp::Reverse(row.2.iter().sum::<usize>())),
+    }
+
+    for (ix, name, lens, identical, cold, weak, object, _) in rows {
+        safeprint!(
             "{ix:width$} {:?} {:?}",
             color!(name, owo_colors::OwoColorize::green),
             color!(lens, owo_colors::OwoColorize::cyan),
         );
-        ix += lens.len();
+        if identical > 0 {
+            safeprint!(
+                " {}",
+                color!(
+                    format_args!("+{identical} identical"),
+                    owo_colors::OwoColorize::yellow
+                )
+            );
+        }
+        if cold > 0 {
+            safeprint!(
+                " {}",
+                color!(
+                    format_args!("+{cold} cold"),
+                    owo_colors::OwoColorize::bright_black
+                )
+            );
+        }
+        if weak {
+            safeprint!(" {}", color!("weak", owo_colors::OwoColorize::magenta));
+        }
+        if object {
+            safeprint!(" {}", color!("object", owo_colors::OwoColorize::magenta));
+        }
+        safeprintln!();
     }
 
-    std::process::exit(1);
+    if fmt.verbosity > 0
+        && names
+            .values()
+            .any(|items| items.len() > 1 && items.iter().any(|i| !demangle::is_v0(&i.mangled_name)))
+    {
+        safeprintln!(
+            "Legacy symbol mangling drops generic parameters so different instantiations can share a name,\n\
+             build with RUSTFLAGS=\"-C symbol-mangling-version=v0\" to tell them apart"
+        );
+    }
+
+    if names.is_empty() {
+        exit(exit_code::NOT_FOUND);
+    }
+    exit(exit_code::AMBIGUOUS);
+}
+
+/// Mangled name of a function this part was split from: `foo.cold.1` or `foo.llvm.123` -> `foo`
+fn split_parent(mangled_name: &str) -> Option<&str> {
+    let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_hexdigit());
+    if let Some((parent, rest)) = mangled_name.split_once(".cold") {
+        let numbered = rest.strip_prefix('.').is_some_and(is_number);
+        return (rest.is_empty() || numbered).then_some(parent);
+    }
+    let (parent, rest) = mangled_name.split_once(".llvm.")?;
+    is_number(rest).then_some(parent)
+}
+
+/// Append parts split from the item at `self_range` such as `foo.cold.1` to the context
+fn add_cold_parts(
+    items: &BTreeMap<Item, Range<usize>>,
+    self_range: &Range<usize>,
+    context: &mut Vec<Range<usize>>,
+) {
+    let Some(parent) = items
+        .iter()
+        .find_map(|(item, range)| (range == self_range).then_some(&item.mangled_name))
+    else {
+        return;
+    };
+    for (item, range) in items {
+        if split_parent(&item.mangled_name) == Some(parent.as_str()) && !context.contains(range) {
+            context.push(range.clone());
+        }
+    }
+}
+
+/// Last path segment of a name without generic parameters: `foo::Bar<T>::baz<U>` -> `baz`
+fn base_name(name: &str) -> &str {
+    let mut name = name;
+    while let Some(rest) = name.strip_suffix('>') {
+        let mut depth = 1;
+        let Some(open) = rest.char_indices().rev().find_map(|(ix, c)| {
+            match c {
+                '>' => depth += 1,
+                '<' => depth -= 1,
+                _ => {}
+            }
+            (depth == 0).then_some(ix)
+        }) else {
+            break;
+        };
+        name = rest[..open].trim_end_matches("::");
+    }
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+/// Search request didn't match anything, but there might be items with the same base name:
+/// a generic function can be present under its generic name, or only in specialized form
+fn suggest_similar<'a>(search: &str, items: impl IntoIterator<Item = &'a Item>) -> bool {
+    let base = base_name(search);
+    let similar = items
+        .into_iter()
+        .filter(|item| base_name(&item.name) == base)
+        .map(|item| item.name.as_str())
+        .collect::<BTreeSet<_>>();
+    if similar.is_empty() {
+        return false;
+    }
+    esafeprintln!(
+        "Function might be generic and present only in a generic or specialized form, similarly named items:"
+    );
+    for name in similar {
+        esafeprintln!("\t{}", color!(name, owo_colors::OwoColorize::green));
+    }
+    true
+}
+
+/// Does the search request look like a Rust path such as `foo` or `crate::module::foo`?
+fn looks_like_path(search: &str) -> bool {
+    let search = search.strip_prefix("::").unwrap_or(search);
+    search.split("::").all(|part| {
+        part.starts_with(|c: char| c.is_alphabetic() || c == '_')
+            && part.chars().all(|c| c.is_alphanumeric() || c == '_')
+    })
+}
+
+/// Search request is probably misspelled, list the closest names along with their indices
+///
+/// Names containing all the characters of the request in order go first, the rest are
+/// ranked by edit distance between their last path segment and the request
+fn suggest_fuzzy<'a>(search: &str, items: impl IntoIterator<Item = &'a Item>) {
+    let search = search.to_lowercase();
+    let threshold = (search.chars().count() / 3).max(2);
+    let mut seen = BTreeSet::new();
+    let mut candidates = items
+        .into_iter()
+        .enumerate()
+        .filter(|(_, item)| seen.insert(item.name.as_str()))
+        .filter_map(|(ix, item)| {
+            let name = item.name.to_lowercase();
+            let score = if is_subsequence(&search, &name) {
+                (0, name.len().saturating_sub(search.len()))
+            } else {
+                let distance = edit_distance(&search, &base_name(&name).to_lowercase());
+                (1, distance)
+            };
+            (score.0 == 0 || score.1 <= threshold).then_some((score, ix, item.name.as_str()))
+        })
+        .collect::<Vec<_>>();
+    if candidates.is_empty() {
+        return;
+    }
+    candidates.sort();
+    esafeprintln!("Closest matches, pick one by name or index:");
+    for (_, ix, name) in candidates.into_iter().take(5) {
+        esafeprintln!("{ix:>5} {}", color!(name, owo_colors::OwoColorize::green));
+    }
+}
+
+/// Are all the characters of `needle` present in `haystack` in the same order?
+fn is_subsequence(needle: &str, haystack: &str) -> bool {
+    let mut haystack = haystack.chars();
+    needle.chars().all(|c| haystack.any(|h| h == c))
+}
+
+/// Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+    let mut cur = vec![0; b.len() + 1];
+    for (i, ca) in a.chars().enumerate() {
+        cur[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(ca != *cb);
+            cur[j + 1] = substitution.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    prev[b.len()]
+}
+
+/// Turn a possibly negative index into a position in a list of `len` items,
+/// negative values count from the end
+fn resolve_index(nth: isize, len: usize) -> Option<usize> {
+    if nth < 0 {
+        len.checked_sub(nth.unsigned_abs())
+    } else {
+        usize::try_from(nth).ok()
+    }
 }
 
 /// Pick an item to dump based on a goal
@@ -168,6 +668,11 @@ pub fn pick_dump_item<K: Clone>(
         // to dump everything just return an empty range
         ToDump::Everything => None,
 
+        ToDump::List => {
+            dump_item_list(fmt, items.keys());
+            exit(0);
+        }
+
         // By index without filtering
         ToDump::ByIndex { value } => {
             if let Some(range) = items.values().nth(value) {
@@ -175,7 +680,7 @@ pub fn pick_dump_item<K: Clone>(
             } else {
                 let actual = items.len();
                 esafeprintln!("You asked to display item #{value} (zero based), but there's only {actual} items");
-                std::process::exit(1);
+                exit(exit_code::NOT_FOUND);
             }
         }
 
@@ -183,7 +688,13 @@ pub fn pick_dump_item<K: Clone>(
         ToDump::Function { function, nth } => {
             let filtered = items
                 .iter()
-                .filter(|(item, _range)| item.name.contains(&function))
+                .filter(|(item, _range)| {
+                    if fmt.exact_path {
+                        item.name == function
+                    } else {
+                        item.name.contains(&function)
+                    }
+                })
                 .collect::<Vec<_>>();
 
             let range = if nth.is_none() && filtered.len() == 1 {
@@ -192,19 +703,30 @@ pub fn pick_dump_item<K: Clone>(
                     .expect("Must have one item as checked above")
                     .1
                     .clone()
-            } else if let Some(range) = nth.and_then(|nth| filtered.get(nth)) {
+            } else if let Some(range) = nth
+                .and_then(|nth| resolve_index(nth, filtered.len()))
+                .and_then(|ix| filtered.get(ix))
+            {
                 range.1.clone()
             } else if let Some(value) = nth {
                 let filtered = filtered.len();
                 esafeprintln!("You asked to display item #{value} (zero based), but there's only {filtered} matching items");
-                std::process::exit(1);
+                exit(exit_code::NOT_FOUND);
             } else {
                 if filtered.is_empty() {
                     esafeprintln!("Can't find any items matching {function:?}");
-                } else {
-                    suggest_name(&function, &fmt, filtered.iter().map(|x| x.0));
+                    if !suggest_similar(&function, items.keys()) {
+                        suggest_fuzzy(&function, items.keys());
+                    }
+                    if looks_like_path(&function) {
+                        esafeprintln!(
+                            "If {function:?} exists it might have been inlined into all of its callers and has no symbol \
+                             of its own, mark it with #[inline(never)] or look at the functions that call it"
+                        );
+                    }
+                    exit(exit_code::NOT_FOUND);
                 }
-                std::process::exit(1);
+                suggest_name(&function, fmt, filtered.iter().map(|x| x.0));
             };
             Some(range)
         }
@@ -217,7 +739,7 @@ pub fn pick_dump_item<K: Clone>(
             } else {
                 // Otherwise, print suggestions and exit
                 let items = items.keys();
-                suggest_name("", &fmt, items);
+                suggest_name("", fmt, items);
             }
         }
     }
@@ -234,11 +756,15 @@ impl RawLines for &str {
 }
 
 /// Recursively scan for references to global objects
+///
+/// Rust functions that are referenced but defined elsewhere, usually in std or other crates,
+/// are collected into `external`
 fn get_context_for<R: RawLines>(
     depth: usize,
     all_stmts: &[R],
     self_range: Range<usize>,
     items: &BTreeMap<Item, Range<usize>>,
+    external: &mut BTreeSet<String>,
 ) -> Vec<Range<usize>> {
     let mut out = Vec::new();
     if depth == 0 {
@@ -255,6 +781,8 @@ fn get_context_for<R: RawLines>(
             .iter()
             .filter_map(R::lines)
             .filter_map(demangle::global_reference)
+            // calls through GOT or PLT refer to the symbol with a relocation suffix
+            .map(demangle::without_indirection)
         {
             if !processed.insert(raw) {
                 continue;
@@ -267,6 +795,8 @@ fn get_context_for<R: RawLines>(
                     pending.push((range.clone(), depth - 1));
                 }
                 out.push(range.clone());
+            } else if demangle::demangled(raw).is_some() {
+                external.insert(raw.to_owned());
             }
         }
     }
@@ -274,66 +804,525 @@ fn get_context_for<R: RawLines>(
     out
 }
 
+/// Keep references to functions from other crates, such as std or registry dependencies.
+/// Functions from the crate being dumped are not external even if they are not in the same
+/// codegen unit or not emitted at all
+fn foreign_references<'a>(names: Vec<String>, local: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let mut local_names = BTreeSet::new();
+    let mut local_crates = BTreeSet::new();
+    for name in local {
+        local_names.insert(name);
+        if let Some(path) = demangle::name(name) {
+            local_crates.insert(crate_of(&path).to_owned());
+        }
+    }
+    names
+        .into_iter()
+        .filter(|name| {
+            !local_names.contains(name.as_str())
+                && demangle::name(name).is_some_and(|path| !local_crates.contains(crate_of(&path)))
+        })
+        .collect()
+}
+
+/// Crate a demangled path belongs to, `<alloc::vec::Vec<T> as core::ops::drop::Drop>::drop`
+/// is in `alloc`
+fn crate_of(path: &str) -> &str {
+    let path = path.trim_start_matches(['<', '&', '*']);
+    let path = ["mut ", "const "]
+        .into_iter()
+        .find_map(|prefix| path.strip_prefix(prefix))
+        .unwrap_or(path);
+    path.split("::").next().unwrap_or(path)
+}
+
 pub trait Dumpable {
     type Line<'a>;
     /// Split source code into multiple lines, code can do some parsing here
     fn split_lines(contents: &str) -> anyhow::Result<Vec<Self::Line<'_>>>;
 
+    /// Split only lines needed to dump functions with names containing `function`, used by
+    /// `--parse-selected`. `None` if this is not supported or nothing matched
+    fn split_selected_lines<'a>(
+        _contents: &'a str,
+        _function: &str,
+    ) -> anyhow::Result<Option<Vec<Self::Line<'a>>>> {
+        Ok(None)
+    }
+
     /// Given a set of lines find all the interesting items
     fn find_items(lines: &[Self::Line<'_>]) -> BTreeMap<Item, Range<usize>>;
 
     /// print all the lines from this range, aplying the required formatting
     fn dump_range(&self, fmt: &Format, lines: &[Self::Line<'_>]) -> anyhow::Result<()>;
 
-    /// starting at an initial range find more ranges to include
-    fn extra_context(
+    /// print lines from `range` of the whole file, for formats that need the rest of the file
+    /// to print them, such as source file names
+    fn dump_file_range(
         &self,
         fmt: &Format,
         lines: &[Self::Line<'_>],
         range: Range<usize>,
-        items: &BTreeMap<Item, Range<usize>>,
+    ) -> anyhow::Result<()> {
+        self.dump_range(fmt, &lines[range])
+    }
+
+    /// Part of the selected item to dump, such as a single basic block with `--bb`
+    fn select_part(_fmt: &Format, _lines: &[Self::Line<'_>], range: Range<usize>) -> Range<usize> {
+        range
+    }
+
+    /// Print facts about the dumped code after it, such as functions it calls with `--calls`
+    fn dump_summary(&self, _fmt: &Format, _lines: &[Self::Line<'_>]) {}
+
+    /// Hash lines of an item such that identical functions get the same hash,
+    /// used to group them with `--group-identical`
+    fn body_hash(_lines: &[Self::Line<'_>]) -> Option<u64> {
+        None
+    }
+
+    /// starting at an initial range find more ranges to include
+    fn extra_context(
+        &self,
+        _fmt: &Format,
+        _lines: &[Self::Line<'_>],
+        _range: Range<usize>,
+        _items: &BTreeMap<Item, Range<usize>>,
     ) -> Vec<Range<usize>> {
-        #![allow(unused_variables)]
+        Vec::new()
+    }
+
+    /// Items with code generated from `line` of a source file whose path ends with `file`,
+    /// used by `--at`
+    fn items_at(
+        _lines: &[Self::Line<'_>],
+        _items: &BTreeMap<Item, Range<usize>>,
+        _file: &Path,
+        _line: u64,
+    ) -> Vec<Item> {
+        Vec::new()
+    }
+
+    /// Symbols referenced by the context that are defined outside of the dumped file,
+    /// collected by [`Dumpable::extra_context`]
+    fn external_references(&self) -> Vec<String> {
         Vec::new()
     }
 }
 
+/// Append items requested with `--also` to the context, skipping those already present
+fn add_named_context(
+    fmt: &Format,
+    items: &BTreeMap<Item, Range<usize>>,
+    self_range: &Range<usize>,
+    context: &mut Vec<Range<usize>>,
+) {
+    for name in &fmt.also {
+        let mut found = false;
+        for (item, range) in items {
+            if !item.name.contains(name.as_str())
+                && item.hashed != *name
+                && item.mangled_name != *name
+            {
+                continue;
+            }
+            found = true;
+            if range != self_range && !context.contains(range) {
+                context.push(range.clone());
+            }
+        }
+        if !found {
+            esafeprintln!("Can't find any items matching {name:?}, ignoring it");
+        }
+    }
+}
+
+/// Print all the functions in the order used by indices, for `--list`
+fn dump_item_list<'a>(fmt: &Format, items: impl ExactSizeIterator<Item = &'a Item>) {
+    #[allow(clippy::cast_sign_loss)]
+    #[allow(clippy::cast_precision_loss)]
+    let width = ((items.len() as f64).log10().ceil() as usize).max(5);
+    safeprintln!("{:>width$}   Lines  Function name", "Index");
+    for (ix, item) in items.enumerate() {
+        let name = match fmt.name_display {
+            NameDisplay::Full | NameDisplay::Verbose => &item.hashed,
+            NameDisplay::Short => &item.name,
+            NameDisplay::Mangled => &item.mangled_name,
+        };
+        safeprintln!(
+            "{ix:>width$} {:>7}  {}",
+            item.non_blank_len,
+            color!(name, owo_colors::OwoColorize::green)
+        );
+    }
+}
+
+/// Lines and copies per name as shown by `display`, largest functions first
+fn lines_by_name<'a>(
+    display: NameDisplay,
+    items: impl IntoIterator<Item = &'a Item>,
+) -> Vec<(&'a str, (usize, usize))> {
+    let mut by_name = BTreeMap::<&str, (usize, usize)>::new();
+    for item in items {
+        let name = match display {
+            NameDisplay::Full | NameDisplay::Verbose => &item.hashed,
+            NameDisplay::Short => &item.name,
+            NameDisplay::Mangled => &item.mangled_name,
+        };
+        let entry = by_name.entry(name).or_default();
+        entry.0 += item.non_blank_len;
+        entry.1 += 1;
+    }
+    let mut rows = by_name.into_iter().collect::<Vec<_>>();
+    rows.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    rows
+}
+
+/// Print functions sorted by their size in lines, similar to `cargo-llvm-lines`
+///
+/// Functions sharing the same name as picked by `--full-name`/`--short-name` are aggregated
+fn dump_lines_report<'a>(fmt: &Format, items: impl IntoIterator<Item = &'a Item>) {
+    let rows = lines_by_name(fmt.name_display, items);
+    let total_lines = rows.iter().map(|(_, (lines, _))| lines).sum::<usize>();
+    let total_copies = rows.iter().map(|(_, (_, copies))| copies).sum::<usize>();
+
+    #[allow(clippy::cast_precision_loss)]
+    let percent = |part: usize, total: usize| 100.0 * part as f64 / total.max(1) as f64;
+    safeprintln!("  Lines            Copies         Function name");
+    safeprintln!("  -----            ------         -------------");
+    safeprintln!(
+        "{total_lines:>7} (100.0%) {total_copies:>6} (100.0%)  {}",
+        color!("(TOTAL)", owo_colors::OwoColorize::bright_cyan)
+    );
+    for (name, (lines, copies)) in rows {
+        safeprintln!(
+            "{lines:>7} ({:>5.1}%) {copies:>6} ({:>5.1}%)  {}",
+            percent(lines, total_lines),
+            percent(copies, total_copies),
+            color!(name, owo_colors::OwoColorize::green)
+        );
+    }
+}
+
+/// Print a file that can't be parsed as is, for --raw. With a function name only lines
+/// between its label and the end of the function are printed, found by a naive label scan
+fn dump_raw(contents: &str, goal: &ToDump) {
+    let ToDump::Function { function, .. } = goal else {
+        for line in contents.lines() {
+            safeprintln!("{line}");
+        }
+        return;
+    };
+    let lines = contents.lines().collect::<Vec<_>>();
+    let regions = raw_regions(&lines, function);
+    if regions.is_empty() {
+        esafeprintln!("Can't find any labels matching {function:?}");
+        exit(exit_code::NOT_FOUND);
+    }
+    for region in regions {
+        for line in &lines[region] {
+            safeprintln!("{line}");
+        }
+        safeprintln!();
+    }
+}
+
+/// Label defined on this line, if any: `foo:` possibly followed by a comment
+fn raw_label(line: &str) -> Option<&str> {
+    let (label, _) = line.split_once(':')?;
+    let valid = !label.is_empty() && !label.contains(|c: char| c.is_whitespace() || c == '"');
+    valid.then_some(label)
+}
+
+/// Line ranges of functions whose label matches `function`. A function ends after a
+/// `.Lfunc_end` label or before the next non-local label
+fn raw_regions(lines: &[&str], function: &str) -> Vec<Range<usize>> {
+    let is_local = |label: &str| {
+        matches!(
+            demangle::label_kind(label),
+            demangle::LabelKind::Local | demangle::LabelKind::Temp
+        )
+    };
+    let mut res = Vec::new();
+    for (start, line) in lines.iter().enumerate() {
+        let Some(label) = raw_label(line) else {
+            continue;
+        };
+        let name = demangle::name(label).unwrap_or_else(|| label.to_owned());
+        if is_local(label) || !name.contains(function) {
+            continue;
+        }
+        let mut end = lines.len();
+        for (ix, line) in lines.iter().enumerate().skip(start + 1) {
+            let Some(label) = raw_label(line) else {
+                continue;
+            };
+            if label.trim_start_matches('.').starts_with("Lfunc_end") {
+                end = ix + 1;
+                break;
+            } else if !is_local(label) {
+                end = ix;
+                break;
+            }
+        }
+        res.push(start..end);
+    }
+    res
+}
+
+/// Total number of lines per function name, copies of generic functions are added up
+fn sizes_by_name<'a>(items: impl IntoIterator<Item = &'a Item>) -> BTreeMap<String, usize> {
+    let mut res = BTreeMap::new();
+    for item in items {
+        *res.entry(item.name.clone()).or_default() += item.non_blank_len;
+    }
+    res
+}
+
+/// Functions present both before and after whose size changed: name, size before and after,
+/// largest changes first
+fn size_changes<'a>(
+    before: &BTreeMap<String, usize>,
+    after: &'a BTreeMap<String, usize>,
+) -> Vec<(&'a str, usize, usize)> {
+    let mut res = after
+        .iter()
+        .filter_map(|(name, &new)| Some((name.as_str(), *before.get(name)?, new)))
+        .filter(|(_, old, new)| old != new)
+        .collect::<Vec<_>>();
+    res.sort_by_key(|&(name, old, new)| (std::cmp::Reverse(old.abs_diff(new)), name));
+    res
+}
+
+/// Compare function sizes with the ones in a file saved from a different build, for --baseline
+fn dump_size_diff<'a, T: Dumpable>(
+    baseline: &Path,
+    items: impl IntoIterator<Item = &'a Item>,
+) -> anyhow::Result<()> {
+    let contents = to_text(&std::fs::read(baseline)?);
+    let lines = T::split_lines(&contents)?;
+    let before = sizes_by_name(T::find_items(&lines).keys());
+    let after = sizes_by_name(items);
+    let changes = size_changes(&before, &after);
+
+    let common = after
+        .keys()
+        .filter(|name| before.contains_key(*name))
+        .count();
+    let added = after.len() - common;
+    let removed = before.len() - common;
+    if changes.is_empty() {
+        safeprintln!("No size changes in {common} common functions");
+    } else {
+        safeprintln!(" Before    After   Change  Function name");
+        safeprintln!(" ------    -----   ------  -------------");
+        for (name, old, new) in changes {
+            #[allow(clippy::cast_possible_wrap)]
+            let change = new as isize - old as isize;
+            let change = if change > 0 {
+                color!(format!("{change:+8}"), owo_colors::OwoColorize::red).to_string()
+            } else {
+                color!(format!("{change:+8}"), owo_colors::OwoColorize::green).to_string()
+            };
+            safeprintln!(
+                "{old:>7} {new:>8} {change}  {}",
+                color!(name, owo_colors::OwoColorize::green)
+            );
+        }
+    }
+    safeprintln!("{common} functions in both, {added} new, {removed} removed");
+    Ok(())
+}
+
+/// Narrow down function's range to lines requested with `--lines`, clamping it if needed
+fn narrow_range(fmt: &Format, range: Range<usize>) -> Range<usize> {
+    let Some(lines) = fmt.lines else {
+        return range;
+    };
+    let len = range.len();
+    let from = lines.from.unwrap_or(1);
+    let to = lines.to.unwrap_or(len);
+    if from > len || to > len {
+        esafeprintln!("Function has only {len} lines, clamping {from}..{to} to fit");
+    }
+    let from = range.start + (from - 1).min(len);
+    let to = range.start + to.min(len);
+    from..to.max(from)
+}
+
+/// Options that look at items other than the one being dumped
+fn needs_whole_file(fmt: &Format) -> bool {
+    fmt.context > 0
+        || fmt.include_constants
+        || !fmt.also.is_empty()
+        || fmt.cold_parts
+        || fmt.llvm_lines
+        || fmt.group_identical
+}
+
 /// Parse a dumpable item from a file and dump it with all the extra context
+///
+/// When crate is split into several codegen units each one comes in a separate file,
+/// items from all of them are searched together
 pub fn dump_function<T: Dumpable>(
     dumpable: &T,
     goal: ToDump,
-    path: &Path,
+    paths: &[PathBuf],
+    fmt: &Format,
+) -> anyhow::Result<()> {
+    let Some(path) = &fmt.html else {
+        return dump_goal(dumpable, goal, paths, fmt);
+    };
+    let (res, output) = record_output(|| dump_goal(dumpable, goal, paths, fmt));
+    res?;
+    if let Err(err) = std::fs::write(path, html::page(&output)) {
+        anyhow::bail!("Couldn't write {}: {err}", path.display());
+    }
+    Ok(())
+}
+
+fn dump_goal<T: Dumpable>(
+    dumpable: &T,
+    goal: ToDump,
+    paths: &[PathBuf],
     fmt: &Format,
 ) -> anyhow::Result<()> {
     // first we need to read the data and do a lossy conversion to a string slice
     // (files generated by rustc/llvm can have non-utf8 characters in them
-    let raw_bytes = std::fs::read(path)?;
-    let contents = String::from_utf8_lossy(&raw_bytes[..]);
+    let contents = paths
+        .iter()
+        .map(|path| {
+            let raw_bytes = std::fs::read(path)?;
+            Ok(to_text(&raw_bytes))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
 
-    let lines = T::split_lines(&contents)?;
-    let items = T::find_items(&lines);
+    let mut files = Vec::new();
+    let mut all_items = BTreeMap::new();
+    // number of items with this name in the files parsed so far
+    let mut seen = BTreeMap::<String, usize>::new();
+    let selected = match &goal {
+        ToDump::Function { function, .. } if fmt.parse_selected && !needs_whole_file(fmt) => {
+            Some(function.as_str())
+        }
+        _ => None,
+    };
+    for (file_ix, contents) in contents.iter().enumerate() {
+        let parsed = match selected {
+            Some(function) => T::split_selected_lines(contents, function),
+            None => Ok(None),
+        }
+        .and_then(|lines| match lines {
+            Some(lines) => Ok(lines),
+            None => T::split_lines(contents),
+        });
+        let lines = match parsed {
+            Ok(lines) => lines,
+            Err(err) if fmt.raw => {
+                esafeprintln!("{err}, showing the file as is");
+                dump_raw(contents, &goal);
+                return Ok(());
+            }
+            Err(err) => return Err(err),
+        };
+        let items = T::find_items(&lines)
+            .into_iter()
+            .map(|(mut item, range)| {
+                // copies of a function from different codegen units must stay separate items,
+                // so indices continue from the previous files
+                item.index += seen.get(&item.name).copied().unwrap_or(0);
+                if fmt.group_identical {
+                    item.body_hash = T::body_hash(&lines[range.clone()]);
+                }
+                (item, range)
+            })
+            .collect::<BTreeMap<_, _>>();
+        for item in items.keys() {
+            *seen.entry(item.name.clone()).or_default() += 1;
+        }
+        for (item, range) in &items {
+            all_items.insert(item.clone(), (file_ix, range.clone()));
+        }
+        files.push((lines, items));
+    }
+
+    if fmt.llvm_lines {
+        dump_lines_report(fmt, all_items.keys());
+        return Ok(());
+    }
+
+    if let Some(baseline) = &fmt.baseline {
+        return dump_size_diff::<T>(baseline, all_items.keys());
+    }
+
+    if let Some((file, line)) = &fmt.at {
+        let found = files
+            .iter()
+            .flat_map(|(lines, items)| T::items_at(lines, items, file, *line))
+            .collect::<BTreeSet<_>>();
+        if found.is_empty() {
+            esafeprintln!(
+                "Can't find any items with code from {}:{line}, it might have no code of its own \
+                 or rustc was asked not to emit debug info",
+                file.display()
+            );
+            exit(exit_code::NOT_FOUND);
+        }
+        all_items.retain(|item, _| found.contains(item));
+    }
 
-    match pick_dump_item(goal, fmt, &items) {
-        Some(range) => {
-            let context = T::extra_context(dumpable, fmt, &lines, range.clone(), &items);
-            dumpable.dump_range(fmt, &lines[range])?;
+    match pick_dump_item(goal, fmt, &all_items) {
+        Some((file_ix, range)) => {
+            let (lines, items) = &files[file_ix];
+            let mut context = T::extra_context(dumpable, fmt, lines, range.clone(), items);
+            add_named_context(fmt, items, &range, &mut context);
+            if fmt.cold_parts {
+                add_cold_parts(items, &range, &mut context);
+            }
+            let shown = narrow_range(fmt, T::select_part(fmt, lines, range));
+            dumpable.dump_file_range(fmt, lines, shown.clone())?;
+            dumpable.dump_summary(fmt, &lines[shown]);
 
-            if !context.is_empty() {
+            let local = all_items.keys().map(|item| item.mangled_name.as_str());
+            let external = foreign_references(dumpable.external_references(), local);
+            if !(fmt.source_json || (context.is_empty() && external.is_empty())) {
                 safeprintln!(
                     "\n======================= Additional context ========================="
                 );
                 for range in context {
                     safeprintln!("");
-                    dumpable.dump_range(fmt, &lines[range])?;
+                    dumpable.dump_file_range(fmt, lines, range)?;
+                }
+                for name in external {
+                    safeprintln!(
+                        "\n{} {}",
+                        color!(
+                            demangle::contents(&name, fmt.name_display),
+                            owo_colors::OwoColorize::green
+                        ),
+                        color!(
+                            "(external, not in this artifact)",
+                            owo_colors::OwoColorize::bright_black
+                        )
+                    );
                 }
             }
         }
         None => {
-            if fmt.rust {
-                // for asm files extra_context loads rust sources
-                T::extra_context(dumpable, fmt, &lines, 0..lines.len(), &items);
+            for ((lines, items), path) in files.iter().zip(paths) {
+                if files.len() > 1 {
+                    safeprintln!(
+                        "\n======================= {} =========================\n",
+                        path.display()
+                    );
+                }
+                if fmt.rust {
+                    // for asm files extra_context loads rust sources
+                    T::extra_context(dumpable, fmt, lines, 0..lines.len(), items);
+                }
+                dumpable.dump_file_range(fmt, lines, 0..lines.len())?;
+                dumpable.dump_summary(fmt, lines);
             }
-            dumpable.dump_range(fmt, &lines)?
         }
     }
     Ok(())
@@ -364,3 +1353,178 @@ impl URange {
         self.start >= other.start && self.end <= other.end
     }
 }
+
+#[test]
+fn leading_tabs_are_expanded() {
+    let mut state = LineState {
+        at_start: true,
+        in_escape: false,
+    };
+    let mut out = Vec::new();
+    expand_tabs(b"\t\x1b[36m\t// a\tb\n\tc", 2, &mut state, &mut out);
+    expand_tabs(b"\td\n", 2, &mut state, &mut out);
+    assert_eq!(out, b"  \x1b[36m  // a\tb\n  c\td\n");
+}
+
+#[test]
+fn colors_are_stripped() {
+    let mut in_escape = false;
+    let mut out = Vec::new();
+    strip_colors(b"\x1b[36m// a\x1b[", &mut in_escape, &mut out);
+    strip_colors(b"39m b\n", &mut in_escape, &mut out);
+    assert_eq!(out, b"// a b\n");
+}
+
+#[test]
+fn bom_and_crlf_are_removed() {
+    assert_eq!(to_text(b"\xef\xbb\xbffoo:\r\n\tretq\r\n"), "foo:\n\tretq\n");
+    assert_eq!(to_text(b"foo:\n\tretq\n"), "foo:\n\tretq\n");
+    let lines =
+        cached_lines::CachedLines::without_ending("\u{feff}fn main() {\r\n}\r\n".to_owned());
+    assert_eq!(lines.iter().collect::<Vec<_>>(), ["fn main() {", "}"]);
+}
+
+#[test]
+fn size_changes_are_sorted() {
+    let sizes = |items: &[(&str, usize)]| {
+        items
+            .iter()
+            .map(|&(name, len)| (name.to_owned(), len))
+            .collect::<BTreeMap<_, _>>()
+    };
+    let before = sizes(&[("foo", 10), ("bar", 20), ("baz", 5), ("gone", 3)]);
+    let after = sizes(&[("foo", 12), ("bar", 14), ("baz", 5), ("new", 7)]);
+    assert_eq!(
+        size_changes(&before, &after),
+        [("bar", 20, 14), ("foo", 10, 12)]
+    );
+}
+
+#[test]
+fn lines_report_merges_copies() {
+    let item = |name: &str, hashed: &str, non_blank_len| Item {
+        name: name.to_owned(),
+        hashed: hashed.to_owned(),
+        index: 0,
+        len: non_blank_len,
+        position: 0,
+        non_blank_len,
+        mangled_name: hashed.to_owned(),
+        body_hash: None,
+        merged: false,
+        weak: false,
+        object: false,
+    };
+    let items = [
+        item("foo", "foo::h1", 10),
+        item("foo", "foo::h2", 5),
+        item("bar", "bar::h3", 12),
+    ];
+    assert_eq!(
+        lines_by_name(NameDisplay::Short, &items),
+        [("foo", (15, 2)), ("bar", (12, 1))]
+    );
+    assert_eq!(
+        lines_by_name(NameDisplay::Full, &items),
+        [
+            ("bar::h3", (12, 1)),
+            ("foo::h1", (10, 1)),
+            ("foo::h2", (5, 1))
+        ]
+    );
+}
+
+#[test]
+fn path_like_requests_are_detected() {
+    assert!(looks_like_path("foo"));
+    assert!(looks_like_path("my_crate::module::Foo::bar"));
+    assert!(!looks_like_path("<T as Foo>::bar"));
+    assert!(!looks_like_path("foo::"));
+    assert!(!looks_like_path("17"));
+}
+
+#[test]
+fn raw_regions_are_found_by_labels() {
+    let lines = [
+        "\t.text",
+        "_ZN4test3foo17h0123456789abcdefE:  # @foo",
+        "\tmovl\t$1, %eax",
+        ".LBB0_1:",
+        "\tretq",
+        ".Lfunc_end0:",
+        "\t.size\tfoo, .Lfunc_end0-foo",
+        "bar:",
+        "\tretq",
+    ];
+    assert_eq!(raw_regions(&lines, "foo"), vec![(1..6)]);
+    assert_eq!(raw_regions(&lines, "bar"), vec![(7..9)]);
+    assert!(raw_regions(&lines, "baz").is_empty());
+}
+
+#[test]
+fn negative_index_counts_from_the_end() {
+    assert_eq!(resolve_index(0, 3), Some(0));
+    assert_eq!(resolve_index(2, 3), Some(2));
+    assert_eq!(resolve_index(-1, 3), Some(2));
+    assert_eq!(resolve_index(-3, 3), Some(0));
+    assert_eq!(resolve_index(-4, 3), None);
+}
+
+#[test]
+fn split_parts_are_matched_with_parent() {
+    assert_eq!(
+        split_parent("_ZN3foo3bar17h0123456789abcdefE.cold"),
+        Some("_ZN3foo3bar17h0123456789abcdefE")
+    );
+    assert_eq!(split_parent("foo.cold.1"), Some("foo"));
+    assert_eq!(split_parent("foo.llvm.1234567"), Some("foo"));
+    assert_eq!(split_parent("foo.colder"), None);
+    assert_eq!(split_parent("foo.llvm."), None);
+    assert_eq!(split_parent("foo"), None);
+}
+
+#[test]
+fn fuzzy_matching_helpers() {
+    assert!(is_subsequence("frmalnum", "isin::base36::from_alphanum"));
+    assert!(!is_subsequence("mulfrom", "isin::base36::from_alphanum"));
+    assert_eq!(edit_distance("from_alphnum", "from_alphanum"), 1);
+    assert_eq!(edit_distance("kitten", "sitting"), 3);
+    assert_eq!(edit_distance("", "abc"), 3);
+}
+
+#[test]
+fn base_name_works() {
+    assert_eq!(base_name("foo::Bar<T>::baz<U>"), "baz");
+    assert_eq!(base_name("<g::Foo<4>>::get"), "get");
+    assert_eq!(
+        base_name("core::ptr::drop_in_place<alloc::vec::Vec<u8>>"),
+        "drop_in_place"
+    );
+    assert_eq!(base_name("main"), "main");
+}
+
+#[test]
+fn only_foreign_references_are_external() {
+    let names = [
+        "_ZN4demo3foo17h0123456789abcdefE",
+        "_ZN4demo3bar17h0123456789abcdefE",
+        "_ZN4core3fmt5write17h0123456789abcdefE",
+        "memcpy",
+    ];
+    let local = [
+        "_ZN4demo3foo17h0123456789abcdefE",
+        "_ZN4demo4main17h0123456789abcdefE",
+    ];
+    let external = foreign_references(names.map(String::from).to_vec(), local.into_iter());
+    assert_eq!(external, ["_ZN4core3fmt5write17h0123456789abcdefE"]);
+
+    assert_eq!(crate_of("core::fmt::write"), "core");
+    assert_eq!(
+        crate_of("<alloc::vec::Vec<T> as core::ops::drop::Drop>::drop"),
+        "alloc"
+    );
+    assert_eq!(
+        crate_of("<&mut std::io::Stdout as std::io::Write>::flush"),
+        "std"
+    );
+}
@@ -2,7 +2,7 @@
 use line_span::LineSpans;
 // https://llvm.org/docs/LangRef.html
 use owo_colors::OwoColorize;
-use regex::Regex;
+use regex::{Captures, Regex};
 
 use crate::Dumpable;
 use crate::{
@@ -17,6 +17,7 @@ use std::{
     io::{BufRead, BufReader},
     ops::Range,
     path::Path,
+    sync::OnceLock,
 };
 
 #[derive(Debug)]
@@ -45,7 +46,6 @@ impl Dumpable for Llvm {
         }
         let mut res = BTreeMap::new();
         let mut current_item = None::<ItemParseState>;
-        let regex = Regex::new("@\"?(_?[a-zA-Z0-9_$.]+)\"?\\(").expect("regexp should be valid");
 
         for (ix, &line) in lines.iter().enumerate() {
             if line.starts_with("; Module") || line.starts_with("; Function Attrs: ") {
@@ -59,13 +59,17 @@ impl Dumpable for Llvm {
                         hashed: String::new(),
                         index: res.len(),
                         len: 0,
+                        position: ix,
                         non_blank_len: 0,
+                        body_hash: None,
+                        merged: false,
+                        weak: false,
+                        object: false,
                     },
                     start: ix,
                 });
             } else if line.starts_with("define ") {
-                if let Some(name) = regex.captures(line).and_then(|c| c.get(1)) {
-                    let name = name.as_str();
+                if let Some(name) = defined_name(line) {
                     let cur = current_item.get_or_insert_with(|| ItemParseState {
                         item: Item {
                             mangled_name: String::new(),
@@ -73,7 +77,12 @@ impl Dumpable for Llvm {
                             hashed: String::new(),
                             index: res.len(),
                             len: 0,
+                            position: ix,
                             non_blank_len: 0,
+                            body_hash: None,
+                            merged: false,
+                            weak: false,
+                            object: false,
                         },
                         start: ix,
                     });
@@ -99,6 +108,19 @@ impl Dumpable for Llvm {
     }
 
     fn dump_range(&self, fmt: &Format, strings: &[&str]) -> anyhow::Result<()> {
+        if fmt.normalize {
+            let renamed = rename_values(strings);
+            let renamed = renamed.iter().map(String::as_str).collect::<Vec<_>>();
+            let fmt = Format {
+                normalize: false,
+                ..fmt.clone()
+            };
+            return self.dump_range(&fmt, &renamed);
+        }
+        if fmt.fold {
+            dump_folded(fmt, strings);
+            return Ok(());
+        }
         for line in strings {
             if line.starts_with("; ") {
                 safeprintln!("{}", color!(line, OwoColorize::bright_cyan));
@@ -111,6 +133,465 @@ impl Dumpable for Llvm {
     }
 }
 
+/// Name of a function defined on this `define ...` line
+fn defined_name(line: &str) -> Option<&str> {
+    static DEFINE: OnceLock<Regex> = OnceLock::new();
+    let regex = DEFINE.get_or_init(|| {
+        Regex::new("@\"?(_?[a-zA-Z0-9_$.]+)\"?\\(").expect("regexp should be valid")
+    });
+    Some(regex.captures(line)?.get(1)?.as_str())
+}
+
+/// Optimized llvm-ir compared against llvm-ir LLVM got as an input
+pub struct LlvmDiff {
+    /// Contents of files produced with `no-prepopulate-passes`
+    before: Vec<String>,
+}
+
+impl LlvmDiff {
+    #[must_use]
+    pub fn new(before: Vec<String>) -> Self {
+        Self { before }
+    }
+
+    /// Lines of a function with this mangled name before any LLVM passes
+    fn find_before(&self, mangled_name: &str) -> Option<Vec<&str>> {
+        self.before.iter().find_map(|contents| {
+            let lines = Llvm::split_lines(contents).ok()?;
+            let range = Llvm::find_items(&lines)
+                .into_iter()
+                .find_map(|(item, range)| (item.mangled_name == mangled_name).then_some(range))?;
+            Some(lines[range].to_vec())
+        })
+    }
+}
+
+impl Dumpable for LlvmDiff {
+    type Line<'a> = &'a str;
+    fn split_lines(contents: &str) -> anyhow::Result<Vec<Self::Line<'_>>> {
+        Llvm::split_lines(contents)
+    }
+
+    fn find_items(lines: &[&str]) -> BTreeMap<Item, Range<usize>> {
+        Llvm::find_items(lines)
+    }
+
+    fn dump_range(&self, fmt: &Format, strings: &[&str]) -> anyhow::Result<()> {
+        let before = strings
+            .iter()
+            .find(|line| line.starts_with("define "))
+            .and_then(|line| defined_name(line))
+            .and_then(|name| self.find_before(name));
+        let Some(before) = before else {
+            let msg = "; not present in llvm-ir before LLVM passes";
+            safeprintln!("{}", color!(msg, OwoColorize::bright_black));
+            return Llvm.dump_range(fmt, strings);
+        };
+        let (shown_before, shown_after) = if fmt.normalize {
+            (rename_values(&before), rename_values(strings))
+        } else {
+            let owned = |lines: &[&str]| lines.iter().map(|l| (*l).to_owned()).collect::<Vec<_>>();
+            (owned(&before), owned(strings))
+        };
+        let (mut removed, mut added) = (0, 0);
+        for change in diff(&normalize(&before), &normalize(strings)) {
+            match change {
+                Change::Same(ix) => {
+                    safeprintln!(" {}", contents(&shown_after[ix], fmt.name_display));
+                }
+                Change::Removed(ix) => {
+                    removed += 1;
+                    let line = format!("-{}", contents(&shown_before[ix], fmt.name_display));
+                    safeprintln!("{}", color!(line, OwoColorize::red));
+                }
+                Change::Added(ix) => {
+                    added += 1;
+                    let line = format!("+{}", contents(&shown_after[ix], fmt.name_display));
+                    safeprintln!("{}", color!(line, OwoColorize::green));
+                }
+            }
+        }
+        let summary = format!("; {removed} lines removed, {added} lines added by LLVM passes");
+        safeprintln!("{}", color!(summary, OwoColorize::bright_black));
+        Ok(())
+    }
+}
+
+/// Rename unnamed values and blocks to `%v0`, `%v1`, ... in order of definition
+///
+/// LLVM numbers them sequentially so any change shifts the numbers of everything after it.
+/// Function arguments come first, followed by instruction results and block labels.
+pub fn rename_values(lines: &[&str]) -> Vec<String> {
+    static DEFINED: OnceLock<Regex> = OnceLock::new();
+    static USED: OnceLock<Regex> = OnceLock::new();
+    let defined = DEFINED
+        .get_or_init(|| Regex::new(r"^(?:\s+%(\d+) = |(\d+):)").expect("regexp should be valid"));
+    let used =
+        USED.get_or_init(|| Regex::new(r"^(\d+):|%(\d+)\b").expect("regexp should be valid"));
+
+    let mut ids = BTreeMap::<&str, usize>::new();
+    for line in lines {
+        let defs = if line.starts_with("define ") {
+            used.captures_iter(line)
+                .filter_map(|caps| caps.get(2))
+                .collect::<Vec<_>>()
+        } else {
+            defined
+                .captures(line)
+                .and_then(|caps| caps.get(1).or_else(|| caps.get(2)))
+                .into_iter()
+                .collect()
+        };
+        for num in defs {
+            let next = ids.len();
+            ids.entry(num.as_str()).or_insert(next);
+        }
+    }
+
+    lines
+        .iter()
+        .map(|line| {
+            used.replace_all(line, |caps: &Captures| match (caps.get(1), caps.get(2)) {
+                (Some(num), _) => match ids.get(num.as_str()) {
+                    Some(id) => format!("v{id}:"),
+                    None => caps[0].to_owned(),
+                },
+                (None, Some(num)) => match ids.get(num.as_str()) {
+                    Some(id) => format!("%v{id}"),
+                    None => caps[0].to_owned(),
+                },
+                (None, None) => caps[0].to_owned(),
+            })
+            .into_owned()
+        })
+        .collect()
+}
+
+/// Rename unnamed values, renumber metadata and attribute groups in order of appearance
+/// and drop debug locations so unchanged instructions compare equal before and after passes
+fn normalize(lines: &[&str]) -> Vec<String> {
+    static NUMBERED: OnceLock<Regex> = OnceLock::new();
+    static DBG: OnceLock<Regex> = OnceLock::new();
+    let numbered =
+        NUMBERED.get_or_init(|| Regex::new(r"([!#])(\d+)\b").expect("regexp should be valid"));
+    let dbg = DBG.get_or_init(|| Regex::new(r", !dbg !\d+").expect("regexp should be valid"));
+
+    let mut ids = BTreeMap::<String, usize>::new();
+    let mut next = BTreeMap::<String, usize>::new();
+    let mut res = Vec::with_capacity(lines.len());
+    for line in rename_values(lines) {
+        let line = dbg.replace_all(&line, "");
+        let line = numbered.replace_all(&line, |caps: &Captures| {
+            let id = *ids.entry(caps[0].to_owned()).or_insert_with(|| {
+                let next = next.entry(caps[1].to_owned()).or_default();
+                *next += 1;
+                *next - 1
+            });
+            format!("{}{id}", &caps[1])
+        });
+        res.push(line.into_owned());
+    }
+    res
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Change {
+    /// Index into both, after
+    Same(usize),
+    /// Index into before
+    Removed(usize),
+    /// Index into after
+    Added(usize),
+}
+
+/// Line diff based on the longest common subsequence, removals go before additions
+fn diff(before: &[String], after: &[String]) -> Vec<Change> {
+    let mut res = Vec::with_capacity(before.len().max(after.len()));
+    diff_ranges(before, after, 0..before.len(), 0..after.len(), &mut res);
+    for changed in res.split_mut(|change| matches!(change, Change::Same(_))) {
+        changed.sort_by_key(|change| matches!(change, Change::Added(_)));
+    }
+    res
+}
+
+/// Hirschberg's algorithm: split `before` in half, find where the longest common subsequence
+/// crosses the split in `after` and diff both halves separately. Needs memory linear in the
+/// size of the inputs, functions can be large enough for a full table not to fit
+fn diff_ranges(
+    before: &[String],
+    after: &[String],
+    mut b: Range<usize>,
+    mut a: Range<usize>,
+    res: &mut Vec<Change>,
+) {
+    while !b.is_empty() && !a.is_empty() && before[b.start] == after[a.start] {
+        res.push(Change::Same(a.start));
+        b.start += 1;
+        a.start += 1;
+    }
+    let mut tail = 0;
+    while b.len() > tail && a.len() > tail && before[b.end - tail - 1] == after[a.end - tail - 1] {
+        tail += 1;
+    }
+    b.end -= tail;
+    a.end -= tail;
+
+    if b.is_empty() {
+        res.extend(a.clone().map(Change::Added));
+    } else if a.is_empty() {
+        res.extend(b.map(Change::Removed));
+    } else if b.len() == 1 {
+        match a.clone().find(|&j| before[b.start] == after[j]) {
+            Some(j) => {
+                res.extend((a.start..j).map(Change::Added));
+                res.push(Change::Same(j));
+                res.extend((j + 1..a.end).map(Change::Added));
+            }
+            None => {
+                res.push(Change::Removed(b.start));
+                res.extend(a.clone().map(Change::Added));
+            }
+        }
+    } else {
+        let split = b.start + b.len() / 2;
+        let forward = lcs_lengths(before[b.start..split].iter(), after[a.clone()].iter());
+        let backward = lcs_lengths(
+            before[split..b.end].iter().rev(),
+            after[a.clone()].iter().rev(),
+        );
+        let len = a.len();
+        let cut = (0..=len)
+            .max_by_key(|&k| (forward[k] + backward[len - k], std::cmp::Reverse(k)))
+            .unwrap_or(0);
+        diff_ranges(before, after, b.start..split, a.start..a.start + cut, res);
+        diff_ranges(before, after, split..b.end, a.start + cut..a.end, res);
+    }
+    res.extend((a.end..a.end + tail).map(Change::Same));
+}
+
+/// Lengths of the longest common subsequence of `before` and every prefix of `after`
+fn lcs_lengths<'a, A>(before: impl Iterator<Item = &'a String>, after: A) -> Vec<usize>
+where
+    A: Iterator<Item = &'a String> + Clone,
+{
+    let mut row = vec![0; after.clone().count() + 1];
+    for x in before {
+        // row[j] from the previous line of `before`
+        let mut diag = 0;
+        for (j, y) in after.clone().enumerate() {
+            let up = row[j + 1];
+            row[j + 1] = if x == y { diag + 1 } else { up.max(row[j]) };
+            diag = up;
+        }
+    }
+    row
+}
+
+#[test]
+fn renumbered_diff() {
+    let before = [
+        "define i32 @foo(i32 %x) {",
+        "start:",
+        "  %0 = add i32 %x, 1, !dbg !12",
+        "  br label %1",
+        "1:",
+        "  ret i32 %0, !dbg !14",
+        "}",
+    ];
+    let after = [
+        "define i32 @foo(i32 %x) {",
+        "start:",
+        "  %5 = add i32 %x, 1, !dbg !40",
+        "  ret i32 %5, !dbg !41",
+        "}",
+    ];
+    let (before, after) = (normalize(&before), normalize(&after));
+    assert_eq!(before[4], "v1:");
+    assert_eq!(after[2], "  %v0 = add i32 %x, 1");
+    assert_eq!(
+        diff(&before, &after),
+        [
+            Change::Same(0),
+            Change::Same(1),
+            Change::Same(2),
+            Change::Removed(3),
+            Change::Removed(4),
+            Change::Same(3),
+            Change::Same(4),
+        ]
+    );
+}
+
+#[test]
+fn diff_finds_longest_common_subsequence() {
+    let lines = |s: &str| s.split(' ').map(String::from).collect::<Vec<_>>();
+    let (before, after) = (lines("a b c a b b a"), lines("c b a b a c"));
+    let changes = diff(&before, &after);
+    let old = changes.iter().filter_map(|change| match *change {
+        Change::Same(ix) => Some(&after[ix]),
+        Change::Removed(ix) => Some(&before[ix]),
+        Change::Added(_) => None,
+    });
+    let new = changes.iter().filter_map(|change| match *change {
+        Change::Same(ix) | Change::Added(ix) => Some(&after[ix]),
+        Change::Removed(_) => None,
+    });
+    assert!(old.eq(&before));
+    assert!(new.eq(&after));
+    let same = changes
+        .iter()
+        .filter(|change| matches!(change, Change::Same(_)))
+        .count();
+    assert_eq!(same, 4);
+}
+
+#[test]
+fn values_renamed_in_order_of_definition() {
+    let lines = [
+        "define i32 @foo(i32 %0, i32 %x) {",
+        "  br label %3",
+        "3:",
+        "  %4 = phi i32 [ %0, %start ], [ %6, %3 ]",
+        "  %6 = add i32 %4, 1",
+        "}",
+    ];
+    assert_eq!(
+        rename_values(&lines),
+        [
+            "define i32 @foo(i32 %v0, i32 %x) {",
+            "  br label %v1",
+            "v1:",
+            "  %v2 = phi i32 [ %v0, %start ], [ %v3, %v1 ]",
+            "  %v3 = add i32 %v2, 1",
+            "}",
+        ]
+    );
+}
+
+/// Is this a basic block label such as `bb3:    ; preds = %bb2`?
+fn is_block_label(line: &str) -> bool {
+    !line.starts_with([' ', ';'])
+        && line
+            .split(';')
+            .next()
+            .is_some_and(|label| label.trim_end().ends_with(':'))
+}
+
+/// Line of a block shown with --fold
+#[derive(Debug, Eq, PartialEq)]
+enum Folded<'a> {
+    Line(&'a str),
+    /// this many instructions are not shown
+    Summary(usize),
+}
+
+/// A block as is if it matches --grep pattern, or label, number of folded lines
+/// and the terminator instruction otherwise
+fn fold_block<'a>(block: &[&'a str], grep: Option<&Regex>) -> Vec<Folded<'a>> {
+    let all = || block.iter().map(|&line| Folded::Line(line)).collect();
+    if block
+        .iter()
+        .any(|line| grep.is_some_and(|regex| regex.is_match(line)))
+    {
+        return all();
+    }
+    let (label, body) = match block.split_first() {
+        Some((label, body)) if is_block_label(label) => (Some(*label), body),
+        _ => (None, block),
+    };
+    // terminator can span several lines: switch with its cases or invoke with its labels,
+    // closing bracket of a switch is indented like an instruction
+    let Some(terminator) = body
+        .iter()
+        .rposition(|line| !line_is_blank(line) && line.trim() != "]")
+    else {
+        return all();
+    };
+    let folded = body[..terminator]
+        .iter()
+        .filter(|line| !line_is_blank(line))
+        .count();
+    label
+        .map(Folded::Line)
+        .into_iter()
+        .chain((folded > 0).then_some(Folded::Summary(folded)))
+        .chain(body[terminator..].iter().map(|&line| Folded::Line(line)))
+        .collect()
+}
+
+fn dump_block(fmt: &Format, block: &[&str]) {
+    for line in fold_block(block, fmt.grep.as_ref()) {
+        match line {
+            Folded::Line(line) => safeprintln!("{}", contents(line, fmt.name_display)),
+            Folded::Summary(folded) => {
+                let summary = format!("  ; {folded} instructions folded");
+                safeprintln!("{}", color!(summary, OwoColorize::bright_black));
+            }
+        }
+    }
+}
+
+#[test]
+fn blocks_are_folded_to_terminators() {
+    let block = [
+        "bb2:                                              ; preds = %start",
+        "  %_5 = load i32, ptr %x, align 4",
+        "  %_4 = add i32 %_5, 1",
+        "",
+        "  store i32 %_4, ptr %x, align 4",
+        "  switch i32 %_4, label %bb4 [",
+        "    i32 0, label %bb3",
+        "  ]",
+    ];
+    assert_eq!(
+        fold_block(&block, None),
+        [
+            Folded::Line(block[0]),
+            Folded::Summary(3),
+            Folded::Line(block[5]),
+            Folded::Line(block[6]),
+            Folded::Line(block[7]),
+        ]
+    );
+    let grep = Regex::new("store").unwrap();
+    assert_eq!(fold_block(&block, Some(&grep)).len(), block.len());
+    assert_eq!(
+        fold_block(&["start:", "  ret void"], None),
+        [Folded::Line("start:"), Folded::Line("  ret void")]
+    );
+    assert!(is_block_label("bb2:   ; preds = %start"));
+    assert!(!is_block_label("  %x = load i32, ptr %y ; comment:"));
+}
+
+/// Dump function with every basic block collapsed to its label and terminator,
+/// blocks matching --grep pattern are shown in full
+fn dump_folded(fmt: &Format, strings: &[&str]) {
+    let mut block_start = None;
+    for (ix, line) in strings.iter().enumerate() {
+        if is_block_label(line) || line.starts_with('}') {
+            if let Some(start) = block_start.take() {
+                dump_block(fmt, &strings[start..ix]);
+            }
+        }
+        if is_block_label(line) {
+            block_start = Some(ix);
+        } else if block_start.is_none() {
+            if line.starts_with("; ") {
+                safeprintln!("{}", color!(line, OwoColorize::bright_cyan));
+            } else {
+                safeprintln!("{}", contents(line, fmt.name_display));
+            }
+            // entry block doesn't always have a label
+            if line.starts_with("define ") {
+                block_start = Some(ix + 1);
+            }
+        }
+    }
+    if let Some(start) = block_start {
+        dump_block(fmt, &strings[start..]);
+    }
+}
+
 /// Returns true if the line should not be counted as meaningful for the function definition.
 ///
 /// LLVM functions can contain whitespace-only lines or lines with labels/comments that are not codegened,
@@ -190,7 +671,7 @@ pub fn collect_or_dump(
                     {
                         let hashed = format!("{hashed:?}");
                         let name_entry = names.entry(name.clone()).or_insert(0);
-                        seen = goal.map_or(true, |goal| {
+                        seen = goal.is_none_or(|goal| {
                             (name.as_ref(), *name_entry) == goal || hashed == goal.0
                         });
 
@@ -200,7 +681,12 @@ pub fn collect_or_dump(
                             hashed,
                             index: *name_entry,
                             len: ix,
+                            position: ix,
                             non_blank_len: 0,
+                            body_hash: None,
+                            merged: false,
+                            weak: false,
+                            object: false,
                         });
                         *name_entry += 1;
 
@@ -224,8 +710,7 @@ pub fn collect_or_dump(
                     if let Some(mut cur) = current_item.take() {
                         cur.len = ix - cur.len;
                         cur.non_blank_len = cur.len;
-                        if goal.map_or(true, |goal| goal.0.is_empty() || cur.name.contains(goal.0))
-                        {
+                        if goal.is_none_or(|goal| goal.0.is_empty() || cur.name.contains(goal.0)) {
                             items.push(cur);
                         }
                     }
@@ -1,22 +1,23 @@
 use anyhow::Context;
-use cargo_metadata::{Artifact, Message, MetadataCommand, Package};
+use cargo_metadata::{Artifact, Message, Metadata, MetadataCommand, Package};
 
 #[cfg(feature = "disasm")]
 use cargo_show_asm::disasm::dump_disasm;
 use cargo_show_asm::{
     asm::Asm,
     dump_function, esafeprintln,
-    llvm::Llvm,
+    llvm::{Llvm, LlvmDiff},
     mca::Mca,
     mir::Mir,
-    opts::{self, CodeSource, OutputType},
-    safeprintln,
+    opts::{self, CodeSource, OutputStyle, OutputType},
+    safeprint, safeprintln,
 };
 use std::{
-    io::BufReader,
+    io::{BufRead, BufReader},
     path::{Path, PathBuf},
     process::{Child, Stdio},
     sync::OnceLock,
+    time::Instant,
 };
 
 fn cargo_path() -> &'static Path {
@@ -36,12 +37,14 @@ macro_rules! no_disasm {
     () => {{
         // Sigh, never type...
         esafeprintln!("This option requires cargo-show-asm to be compiled with \"disasm\" feature");
-        std::process::exit(101)
+        cargo_show_asm::exit(101)
     }};
 }
 
+#[allow(clippy::too_many_arguments)]
 fn spawn_cargo(
     cargo: &opts::Cargo,
+    user_flags: &[String],
     format: &opts::Format,
     syntax: opts::Syntax,
     target_cpu: Option<&str>,
@@ -50,10 +53,9 @@ fn spawn_cargo(
     force_single_cgu: bool,
 ) -> std::io::Result<std::process::Child> {
     use std::ffi::OsStr;
-    use std::fmt::Write;
 
     let mut cmd = std::process::Command::new(cargo_path());
-    let mut rust_flags = std::env::var("RUSTFLAGS").unwrap_or_default();
+    let mut rust_flags = user_flags.to_vec();
 
     // Cargo flags.
     cmd.arg("rustc")
@@ -63,7 +65,7 @@ fn spawn_cargo(
             "--color",
             if format.color { "always" } else { "never" },
         ])
-        .args(std::iter::repeat("-v").take(format.verbosity.saturating_sub(1)))
+        .args(std::iter::repeat_n("-v", format.verbosity.saturating_sub(1)))
         // Workspace location.
         .arg("--manifest-path")
         .arg(&cargo.manifest_path)
@@ -77,7 +79,8 @@ fn spawn_cargo(
         .args(cargo.frozen.then_some("--frozen"))
         .args(cargo.locked.then_some("--locked"))
         .args(cargo.offline.then_some("--offline"))
-        .args(cargo.quiet.then_some("--quiet"))
+        // cargo refuses to take both --quiet and --verbose, verbosity wins
+        .args((cargo.quiet && format.verbosity < 2).then_some("--quiet"))
         .args(cargo.target.iter().flat_map(|t| ["--target", t]))
         .args(cargo.unstable.iter().flat_map(|z| ["-Z", z]))
         .args((syntax.output_type == OutputType::Wasm).then_some("--target=wasm32-unknown-unknown"))
@@ -97,13 +100,12 @@ fn spawn_cargo(
         .args(
             cargo
                 .cli_features
-                .features
-                .iter()
+                .split_features()
                 .flat_map(|feat| ["--features", feat]),
         );
     match &cargo.compile_mode {
         opts::CompileMode::Dev => {}
-        opts::CompileMode::Release => {
+        opts::CompileMode::Release | opts::CompileMode::Unspecified => {
             cmd.arg("--release");
         }
         opts::CompileMode::Custom(profile) => {
@@ -123,7 +125,13 @@ fn spawn_cargo(
         .args(syntax.format().iter().flat_map(|s| ["-C", s]));
 
     if let Some(cpu) = target_cpu {
-        write!(rust_flags, " -Ctarget-cpu={cpu}").unwrap();
+        rust_flags.push(format!("-Ctarget-cpu={cpu}"));
+    }
+
+    // rustflags are passed after `cargo rustc -- args` so flags we rely on must be removed
+    // from them to take effect
+    for emit in remove_flag(&mut rust_flags, "--emit=") {
+        esafeprintln!("Warning: ignoring --emit={emit} from rustflags");
     }
 
     {
@@ -132,22 +140,38 @@ fn spawn_cargo(
             // Debug info is needed to detect function boundaries in asm (Windows/Mac), and to map asm/wasm
             // output to rust source.
             cmd.arg("-Cdebuginfo=2");
+            for level in remove_flag(&mut rust_flags, "-Cdebuginfo=") {
+                if !matches!(level.as_str(), "2" | "full") {
+                    esafeprintln!("Warning: ignoring -Cdebuginfo={level} from rustflags, cargo-show-asm needs full debug info");
+                }
+            }
         }
     }
 
+    if format.encoding && syntax.emit() == Some("asm") {
+        cmd.arg("-Cllvm-args=-show-mc-encoding");
+    }
+
     // current rust does not emit info about generated byproducts, new one will :)
     if force_single_cgu {
         cmd.arg("-Ccodegen-units=1");
+        for units in remove_flag(&mut rust_flags, "-Ccodegen-units=") {
+            if units != "1" {
+                esafeprintln!("Warning: ignoring -Ccodegen-units={units} from rustflags, cargo-show-asm needs a single codegen unit");
+            }
+        }
     }
 
-    if !rust_flags.is_empty() {
+    if rust_flags != user_flags {
         // `args` from `cargo rustc -- args` are passed only to the final compiler instance.
-        // `RUSTFLAGS` envvar is useful for passing flags to all compiler instances.
-        cmd.env("RUSTFLAGS", rust_flags.trim_start());
+        // `CARGO_ENCODED_RUSTFLAGS` envvar is useful for passing flags to all compiler instances,
+        // it keeps flags with spaces intact and takes priority over `RUSTFLAGS`.
+        // It replaces rustflags from `.cargo/config.toml`, those are already included
+        cmd.env("CARGO_ENCODED_RUSTFLAGS", rust_flags.join("\x1f"));
     }
 
-    if format.verbosity >= 2 {
-        safeprintln!("Running: {cmd:?}");
+    if format.verbosity >= 1 {
+        esafeprintln!("Running: {}", shell_command(&cmd));
     }
 
     cmd.stdin(Stdio::null())
@@ -156,6 +180,301 @@ fn spawn_cargo(
         .spawn()
 }
 
+/// Command line that can be pasted into a shell: environment overrides, program and arguments
+fn shell_command(cmd: &std::process::Command) -> String {
+    let env = cmd.get_envs().filter_map(|(key, val)| {
+        Some(format!(
+            "{}={}",
+            key.to_string_lossy(),
+            shell_quote(&val?.to_string_lossy())
+        ))
+    });
+    let program = std::iter::once(cmd.get_program());
+    let args = program
+        .chain(cmd.get_args())
+        .map(|arg| shell_quote(&arg.to_string_lossy()));
+    env.chain(args).collect::<Vec<_>>().join(" ")
+}
+
+/// Quote a word for a POSIX shell if it contains anything special
+fn shell_quote(word: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-./=:,+@%".contains(c);
+    if !word.is_empty() && word.chars().all(plain) {
+        word.to_owned()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+/// Default target set with `CARGO_BUILD_TARGET` or `build.target` in `.cargo/config.toml`
+fn configured_target(manifest_path: &Path) -> Option<String> {
+    if let Some(target) = std::env::var("CARGO_BUILD_TARGET")
+        .ok()
+        .filter(|t| !t.is_empty())
+    {
+        return Some(target);
+    }
+    // build.target can also be a list of targets, those are not supported
+    serde_json::from_value(cargo_config(manifest_path, &["build", "target"])?).ok()
+}
+
+/// Rustflags cargo would use for `target`, the first one present: `CARGO_ENCODED_RUSTFLAGS`,
+/// `RUSTFLAGS`, matching `target.<triple>.rustflags` and `target.'cfg(..)'.rustflags` joined
+/// together, `build.rustflags`. `-C` and `--emit` are joined with their values
+fn configured_rustflags(manifest_path: &Path, target: Option<&str>) -> Vec<String> {
+    if let Ok(encoded) = std::env::var("CARGO_ENCODED_RUSTFLAGS") {
+        let flags = encoded.split('\x1f').filter(|f| !f.is_empty());
+        return join_flags(flags.map(String::from).collect());
+    }
+    if let Ok(flags) = std::env::var("RUSTFLAGS") {
+        return join_flags(flags.split_whitespace().map(String::from).collect());
+    }
+
+    let entries = cargo_config_entries(manifest_path);
+    let targets = entries
+        .iter()
+        .filter_map(|(key, value)| match key.as_slice() {
+            [table, name, flags] if table == "target" && flags == "rustflags" => {
+                Some((name.as_str(), value))
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let mut flags = Vec::new();
+    if !targets.is_empty() {
+        let triple = match target {
+            Some(target) => Some(target.to_owned()),
+            None => host_target().ok(),
+        };
+        let cfg = if targets.iter().any(|(name, _)| name.starts_with("cfg(")) {
+            rustc_cfg(target, &[]).unwrap_or_default()
+        } else {
+            String::new()
+        };
+        let cfg = cfg.lines().collect::<Vec<_>>();
+        for (name, value) in targets {
+            let matches = match name.strip_prefix("cfg(") {
+                Some(expr) => cfg_matches(expr.strip_suffix(')').unwrap_or(expr), &cfg),
+                None => Some(name) == triple.as_deref(),
+            };
+            if matches {
+                flags.extend(config_flags(value));
+            }
+        }
+    }
+    if flags.is_empty() {
+        let build = entries
+            .iter()
+            .find_map(|(key, value)| (key == &["build", "rustflags"]).then_some(value));
+        flags.extend(build.into_iter().flat_map(config_flags));
+    }
+    join_flags(flags)
+}
+
+/// rustflags in cargo config are either a string or a list of strings
+fn config_flags(value: &serde_json::Value) -> Vec<String> {
+    match value {
+        serde_json::Value::String(flags) => flags.split_whitespace().map(String::from).collect(),
+        flags => serde_json::from_value(flags.clone()).unwrap_or_default(),
+    }
+}
+
+/// Does `cfg(..)` predicate from a target table hold for `rustc --print=cfg` output
+fn cfg_matches(expr: &str, cfg: &[&str]) -> bool {
+    matches!(eval_cfg(expr, cfg), Some((res, rest)) if res && rest.trim().is_empty())
+}
+
+/// Evaluate one cfg predicate: `name`, `name = "value"`, `all(..)`, `any(..)` or `not(..)`,
+/// returns the rest of the input
+fn eval_cfg<'a>(input: &'a str, cfg: &[&str]) -> Option<(bool, &'a str)> {
+    let input = input.trim_start();
+    let end = input
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(input.len());
+    let (name, rest) = input.split_at(end);
+    if name.is_empty() {
+        return None;
+    }
+    let rest = rest.trim_start();
+    if let Some(mut rest) = rest.strip_prefix('(') {
+        let mut values = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(after) = rest.strip_prefix(')') {
+                rest = after;
+                break;
+            }
+            let (value, after) = eval_cfg(rest, cfg)?;
+            values.push(value);
+            rest = after.trim_start();
+            rest = rest.strip_prefix(',').unwrap_or(rest);
+        }
+        let res = match (name, values.as_slice()) {
+            ("all", _) => values.iter().all(|v| *v),
+            ("any", _) => values.iter().any(|v| *v),
+            ("not", [value]) => !value,
+            _ => return None,
+        };
+        Some((res, rest))
+    } else if let Some(rest) = rest.strip_prefix('=') {
+        let (value, rest) = rest.trim_start().strip_prefix('"')?.split_once('"')?;
+        let pair = format!("{name}=\"{value}\"");
+        Some((cfg.contains(&pair.as_str()), rest))
+    } else {
+        Some((cfg.contains(&name), rest))
+    }
+}
+
+/// Host target triple as reported by `rustc -vV`
+fn host_target() -> anyhow::Result<String> {
+    let output = std::process::Command::new(rust_path())
+        .arg("-vV")
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        anyhow::bail!("'{:?} -vV' exited with {}", rust_path(), output.status);
+    }
+    std::str::from_utf8(&output.stdout)?
+        .lines()
+        .find_map(|line| line.strip_prefix("host: "))
+        .map(String::from)
+        .context("rustc didn't report the host target")
+}
+
+/// `rustc --print=cfg` output for the target and flags
+fn rustc_cfg(target: Option<&str>, flags: &[String]) -> anyhow::Result<String> {
+    let output = std::process::Command::new(rust_path())
+        .arg("--print=cfg")
+        .args(target.iter().flat_map(|t| ["--target", t]))
+        .args(flags)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .stdout(Stdio::piped())
+        .output()?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "'{:?} --print=cfg' exited with {}",
+            rust_path(),
+            output.status,
+        );
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Turn `-C opt=val` and `--emit kind` into `-Copt=val` and `--emit=kind`
+fn join_flags(flags: Vec<String>) -> Vec<String> {
+    let mut res = Vec::new();
+    let mut flags = flags.into_iter();
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "-C" | "--codegen" => res.extend(flags.next().map(|v| format!("-C{v}"))),
+            "--emit" => res.extend(flags.next().map(|v| format!("--emit={v}"))),
+            _ => match flag.strip_prefix("--codegen=") {
+                Some(v) => res.push(format!("-C{v}")),
+                None => res.push(flag),
+            },
+        }
+    }
+    res
+}
+
+/// Remove flags starting with `prefix` such as `-Ccodegen-units=`, returns their values
+fn remove_flag(flags: &mut Vec<String>, prefix: &str) -> Vec<String> {
+    let mut removed = Vec::new();
+    flags.retain(|flag| {
+        // codegen options can be spelled with underscores
+        let (name, value) = flag.split_once('=').unwrap_or((flag.as_str(), ""));
+        if format!("{}=", name.replace('_', "-")) == prefix {
+            removed.push(value.to_owned());
+            false
+        } else {
+            true
+        }
+    });
+    removed
+}
+
+/// Value from cargo configuration files, as seen from the directory of the manifest:
+/// a string or a list of strings
+fn cargo_config(manifest_path: &Path, key: &[&str]) -> Option<serde_json::Value> {
+    cargo_config_entries(manifest_path)
+        .into_iter()
+        .find_map(|(k, value)| (k == key).then_some(value))
+}
+
+/// Entries from all cargo configuration files, a key set in a more specific file hides
+/// the same key from the less specific ones
+fn cargo_config_entries(manifest_path: &Path) -> Vec<(Vec<String>, serde_json::Value)> {
+    let mut res: Vec<(Vec<String>, serde_json::Value)> = Vec::new();
+    for file in config_files(manifest_path) {
+        let Ok(contents) = std::fs::read_to_string(file) else {
+            continue;
+        };
+        for (key, value) in config_entries(&contents) {
+            if !res.iter().any(|(k, _)| *k == key) {
+                res.push((key, value));
+            }
+        }
+    }
+    res
+}
+
+/// Cargo configuration files, the most specific first: `.cargo/config.toml` or `.cargo/config`
+/// in the manifest directory and its parents, then the one in cargo home
+fn config_files(manifest_path: &Path) -> Vec<PathBuf> {
+    let dir = manifest_path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_owned());
+    let dirs = dir
+        .ancestors()
+        .map(|dir| dir.join(".cargo"))
+        .chain(cargo_show_asm::cargo_home());
+    let mut files = Vec::new();
+    for dir in dirs {
+        let file = ["config.toml", "config"]
+            .into_iter()
+            .map(|name| dir.join(name))
+            .find(|file| file.is_file());
+        if let Some(file) = file.filter(|file| !files.contains(file)) {
+            files.push(file);
+        }
+    }
+    files
+}
+
+/// Keys with string or string list values from a cargo config file, keys include the table:
+/// `[build] target = "..."` gives `["build", "target"]`. Files that fail to parse give nothing
+fn config_entries(contents: &str) -> Vec<(Vec<String>, serde_json::Value)> {
+    fn flatten(
+        prefix: &[String],
+        table: toml::Table,
+        res: &mut Vec<(Vec<String>, serde_json::Value)>,
+    ) {
+        for (name, value) in table {
+            let key = [prefix, &[name]].concat();
+            match value {
+                toml::Value::Table(table) => flatten(&key, table, res),
+                value @ (toml::Value::String(_) | toml::Value::Array(_)) => {
+                    if let Ok(value) = serde_json::to_value(value) {
+                        res.push((key, value));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut res = Vec::new();
+    if let Ok(table) = contents.parse::<toml::Table>() {
+        flatten(&[], table, &mut res);
+    }
+    res
+}
+
 fn sysroot() -> anyhow::Result<PathBuf> {
     let output = std::process::Command::new(rust_path())
         .arg("--print=sysroot")
@@ -176,31 +495,95 @@ fn sysroot() -> anyhow::Result<PathBuf> {
     ))
 }
 
+/// `target_feature` values rustc enables for the target, cpu and features requested by the
+/// user, either with rustflags or with `-C`
+fn enabled_target_features(
+    cargo: &opts::Cargo,
+    user_flags: &[String],
+    target_cpu: Option<&str>,
+) -> anyhow::Result<Vec<String>> {
+    let mut flags = user_flags.to_vec();
+    flags.extend(cargo.codegen.iter().map(|c| format!("-C{c}")));
+    flags.extend(target_cpu.map(|cpu| format!("-Ctarget-cpu={cpu}")));
+    flags.retain(|flag| is_target_flag(flag));
+    let cfg = rustc_cfg(cargo.target.as_deref(), &flags)?;
+    Ok(target_features(&cfg))
+}
+
+/// Flags that change the set of enabled target features
+fn is_target_flag(flag: &str) -> bool {
+    // codegen options can be spelled with underscores
+    let flag = flag.replace('_', "-");
+    flag.starts_with("-Ctarget-cpu=") || flag.starts_with("-Ctarget-feature=")
+}
+
+/// Pick `target_feature="..."` lines from `rustc --print=cfg` output
+fn target_features(cfg: &str) -> Vec<String> {
+    cfg.lines()
+        .filter_map(|line| line.strip_prefix("target_feature=\"")?.strip_suffix('"'))
+        .map(String::from)
+        .collect()
+}
+
 #[allow(clippy::too_many_lines)]
 fn main() -> anyhow::Result<()> {
-    let opts = opts::options().run();
+    let mut opts = opts::options().run();
     owo_colors::set_override(opts.format.color);
+    let _output = cargo_show_asm::setup_output(&opts.format);
+
+    // only assembly has source locations to look for
+    let disasm_file = match &opts.code_source {
+        CodeSource::File { file } => file.extension().is_none_or(|ext| ext != "s"),
+        _ => false,
+    };
+    if opts.format.at.is_some()
+        && (disasm_file || !matches!(opts.syntax.output_type, OutputType::Asm | OutputType::Wasm))
+    {
+        anyhow::bail!("--at only works with assembly output");
+    }
 
     let cargo = match opts.code_source {
         CodeSource::FromCargo { ref cargo } => cargo,
         CodeSource::File { ref file } => {
             if opts.format.verbosity > 0 {
-                esafeprintln!("Processing a given single file");
+                esafeprintln!("Processing a given file or directory");
             }
             match file.extension() {
                 Some(ext) if ext == "s" => {
-                    let nope = PathBuf::new();
-                    let asm = Asm::new(&nope, &nope);
-                    let mut format = opts.format;
-                    // For standalone file we don't know the matching
-                    // system root so don't even try to dump it
-                    format.rust = false;
-                    dump_function(&asm, opts.to_dump, file, &format)?;
+                    // Standalone file doesn't come with a workspace, use current
+                    // directory and whatever sysroot rustc reports instead
+                    let sysroot = match &opts.format.sysroot {
+                        Some(sysroot) => sysroot.clone(),
+                        None => sysroot().unwrap_or_else(|err| {
+                            esafeprintln!("Can't detect sysroot, standard library sources won't be available: {err}");
+                            PathBuf::new()
+                        }),
+                    };
+                    let workspace = std::env::current_dir()?;
+                    let files = std::slice::from_ref(file);
+                    if opts.syntax.output_type == OutputType::Mca {
+                        let mca = Mca::new(
+                            &opts.mca_arg,
+                            opts.syntax.style(),
+                            opts.mca_triple.as_deref(),
+                            opts.target_cpu.first().map(String::as_str),
+                            opts.mca_per_line,
+                        );
+                        dump_function(&mca, opts.to_dump, files, &opts.format)?;
+                    } else {
+                        let asm = Asm::new(&workspace, &sysroot).with_modified(files);
+                        dump_function(&asm, opts.to_dump, files, &opts.format)?;
+                    }
                 }
                 _ => {
                     #[cfg(feature = "disasm")]
                     {
-                        dump_disasm(opts.to_dump, file, &opts.format, opts.syntax.output_style)?
+                        let files = if file.is_dir() {
+                            object_files(file)?
+                        } else {
+                            vec![file.clone()]
+                        };
+                        dump_disasm(opts.to_dump, &files, &opts.format, opts.syntax.style())?
                     }
                     #[cfg(not(feature = "disasm"))]
                     {
@@ -210,11 +593,48 @@ fn main() -> anyhow::Result<()> {
             }
             return Ok(());
         }
+        CodeSource::DemangleOnly => {
+            // lines without mangled names pass through unchanged
+            for line in std::io::stdin().lock().lines() {
+                let line = line?;
+                safeprintln!(
+                    "{}",
+                    cargo_show_asm::demangle::contents(&line, opts.format.name_display)
+                );
+            }
+            return Ok(());
+        }
+        CodeSource::VersionVerbose => {
+            safeprintln!("cargo-show-asm {}", env!("CARGO_PKG_VERSION"));
+            #[cfg(feature = "disasm")]
+            safeprintln!("capstone {}", cargo_show_asm::disasm::capstone_version());
+            #[cfg(not(feature = "disasm"))]
+            safeprintln!("capstone: not available without disasm feature");
+            // includes host target and LLVM version
+            let output = std::process::Command::new(rust_path())
+                .args(["--version", "--verbose"])
+                .stdin(Stdio::null())
+                .stderr(Stdio::inherit())
+                .output()?;
+            if !output.status.success() {
+                anyhow::bail!(
+                    "'{:?} --version --verbose' exited with {}",
+                    rust_path(),
+                    output.status
+                );
+            }
+            safeprint!("{}", String::from_utf8_lossy(&output.stdout));
+            return Ok(());
+        }
     };
 
-    let sysroot = sysroot()?;
+    // when cross compiling rust-src might live in a sysroot other than the host one
+    let sysroot = match &opts.format.sysroot {
+        Some(sysroot) => sysroot.clone(),
+        None => sysroot()?,
+    };
     if opts.format.verbosity > 0 {
-        esafeprintln!("Found sysroot: {}", sysroot.display());
+        esafeprintln!("Using sysroot: {}", sysroot.display());
     }
 
     let unstable = cargo
@@ -230,6 +650,215 @@ fn main() -> anyhow::Result<()> {
         .no_deps()
         .exec()?;
 
+    if opts.select_fragment.workspace && opts.select_fragment.package.is_some() {
+        anyhow::bail!("--workspace and --package can't be used at the same time");
+    }
+    if opts.both_syntaxes
+        && !matches!(
+            opts.syntax.output_type,
+            OutputType::Asm | OutputType::Mca | OutputType::Disasm
+        )
+    {
+        anyhow::bail!("--both-syntaxes only works with assembly output");
+    }
+    if opts.select_fragment.workspace && opts.syntax.output_type == OutputType::Disasm {
+        anyhow::bail!("--workspace is not supported with --disasm");
+    }
+
+    let targets = if opts.select_fragment.workspace {
+        let targets = metadata
+            .workspace_packages()
+            .into_iter()
+            .filter_map(|package| {
+                let focus = match opts.select_fragment.focus {
+                    Some(ref focus) => Some(focus.clone()),
+                    None => workspace_focus(package),
+                };
+                if focus.is_none() && opts.format.verbosity > 0 {
+                    esafeprintln!("Skipping {}, can't pick a single target", package.name);
+                }
+                Some((package, focus?))
+            })
+            .collect::<Vec<_>>();
+        if targets.is_empty() {
+            anyhow::bail!("No targets found");
+        }
+        targets
+    } else {
+        vec![pick_package_focus(&opts, cargo, &metadata)?]
+    };
+    // with --workspace there's no single package to take defaults from, use the root one
+    let manifest_package = match targets.as_slice() {
+        [(package, _)] => Some(*package),
+        _ => metadata.root_package(),
+    };
+
+    let manifest_defaults = match manifest_package {
+        Some(package) => opts::ManifestDefaults::from_package(package)?,
+        None => opts::ManifestDefaults::default(),
+    };
+    let mut cargo = cargo.clone();
+    manifest_defaults.apply(&mut opts);
+    cargo.compile_mode = cargo.compile_mode.or_manifest(&manifest_defaults);
+    if cargo.target.is_none() {
+        cargo.target = configured_target(&cargo.manifest_path);
+        if let (Some(target), 1..) = (&cargo.target, opts.format.verbosity) {
+            esafeprintln!("Using configured target {target}");
+        }
+    }
+    // resolved once, cargo is invoked several times with --target-cpu, --both-syntaxes
+    // and --llvm-diff
+    let user_flags = configured_rustflags(&cargo.manifest_path, cargo.target.as_deref());
+
+    // Pending on this https://github.com/rust-lang/rust/pull/122597
+
+    #[cfg(feature = "disasm")]
+    let force_single_cgu = opts.syntax.output_type != OutputType::Disasm;
+
+    #[cfg(not(feature = "disasm"))]
+    let force_single_cgu = true;
+
+    // the same function compiled for several CPUs, asm file ends up in the same place so
+    // each one needs a separate rebuild
+    let target_cpus = if opts.target_cpu.is_empty() {
+        vec![None]
+    } else {
+        opts.target_cpu
+            .iter()
+            .map(|cpu| Some(cpu.as_str()))
+            .collect()
+    };
+
+    // same as with several CPUs each syntax needs a separate rebuild
+    let styles = if opts.both_syntaxes {
+        vec![Some(OutputStyle::Intel), Some(OutputStyle::Att)]
+    } else {
+        vec![opts.syntax.output_style]
+    };
+
+    for target_cpu in target_cpus {
+        if let (Some(cpu), 2..) = (target_cpu, opts.target_cpu.len()) {
+            safeprintln!("\n======================= target-cpu: {cpu} =========================\n");
+        }
+
+        if opts.format.verbosity > 0 {
+            match enabled_target_features(&cargo, &user_flags, target_cpu) {
+                Ok(features) => esafeprintln!("Enabled target features: {}", features.join(" ")),
+                Err(err) => esafeprintln!("Couldn't get enabled target features: {err}"),
+            }
+        }
+
+        for &output_style in &styles {
+            if opts.both_syntaxes {
+                let name = match output_style {
+                    Some(OutputStyle::Att) => "AT&T",
+                    _ => "Intel",
+                };
+                safeprintln!(
+                    "\n======================= syntax: {name} =========================\n"
+                );
+            }
+            let syntax = opts::Syntax {
+                output_style,
+                ..opts.syntax
+            };
+
+            let build = |syntax: opts::Syntax| -> anyhow::Result<Vec<PathBuf>> {
+                let mut asm_paths = Vec::new();
+                for (focus_package, focus_artifact) in &targets {
+                    let cargo_child = spawn_cargo(
+                        &cargo,
+                        &user_flags,
+                        &opts.format,
+                        syntax,
+                        target_cpu,
+                        focus_package,
+                        focus_artifact,
+                        force_single_cgu,
+                    )?;
+                    asm_paths.extend(cargo_to_asm_paths(
+                        cargo_child,
+                        focus_artifact,
+                        &opts,
+                        cargo.quiet,
+                    )?);
+                }
+                Ok(asm_paths)
+            };
+
+            let build_start = Instant::now();
+            // both versions of llvm-ir end up in the same files so the unoptimized one
+            // must be read before the second build overwrites it
+            let before = if syntax.output_type == OutputType::LlvmDiff {
+                let input = opts::Syntax {
+                    output_type: OutputType::LlvmInput,
+                    ..syntax
+                };
+                build(input)?
+                    .iter()
+                    .map(std::fs::read_to_string)
+                    .collect::<Result<Vec<_>, _>>()?
+            } else {
+                Vec::new()
+            };
+            let asm_paths = build(syntax)?;
+            if opts.format.verbosity > 0 {
+                esafeprintln!("Cargo build took {:.2?}", build_start.elapsed());
+            }
+
+            if opts.format.verbosity > 2 {
+                safeprintln!("goal: {:?}", opts.to_dump);
+            }
+
+            let to_dump = opts.to_dump.clone();
+            let dump_start = Instant::now();
+            match syntax.output_type {
+                OutputType::Asm | OutputType::Wasm => {
+                    let asm = Asm::new(metadata.workspace_root.as_std_path(), &sysroot)
+                        .with_modified(&asm_paths);
+                    dump_function(&asm, to_dump, &asm_paths, &opts.format)
+                }
+                OutputType::Llvm | OutputType::LlvmInput => {
+                    dump_function(&Llvm, to_dump, &asm_paths, &opts.format)
+                }
+                OutputType::LlvmDiff => {
+                    let diff = LlvmDiff::new(before);
+                    dump_function(&diff, to_dump, &asm_paths, &opts.format)
+                }
+                OutputType::Mir => dump_function(&Mir, to_dump, &asm_paths, &opts.format),
+                OutputType::Mca => {
+                    let mca = Mca::new(
+                        &opts.mca_arg,
+                        syntax.style(),
+                        opts.mca_triple.as_deref().or(cargo.target.as_deref()),
+                        target_cpu,
+                        opts.mca_per_line,
+                    );
+                    dump_function(&mca, to_dump, &asm_paths, &opts.format)
+                }
+                #[cfg(not(feature = "disasm"))]
+                OutputType::Disasm => no_disasm!(),
+
+                #[cfg(feature = "disasm")]
+                OutputType::Disasm => {
+                    let asm_path = std::slice::from_ref(&asm_paths[0]);
+                    dump_disasm(to_dump, asm_path, &opts.format, syntax.style())
+                }
+            }?;
+            if opts.format.verbosity > 0 {
+                esafeprintln!("Parsing and dumping took {:.2?}", dump_start.elapsed());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Package and target to work on when --workspace is not given
+fn pick_package_focus<'a>(
+    opts: &opts::Options,
+    cargo: &opts::Cargo,
+    metadata: &'a Metadata,
+) -> anyhow::Result<(&'a Package, opts::Focus)> {
     let focus_package = match opts.select_fragment.package {
         Some(ref name) => metadata
             .packages
@@ -251,10 +880,17 @@ fn main() -> anyhow::Result<()> {
 
     let focus_artifact = match opts.select_fragment.focus {
         Some(ref focus) => focus.clone(),
-        None => match focus_package.targets.len() {
-            0 => anyhow::bail!("No targets found"),
-            1 => opts::Focus::try_from(&focus_package.targets[0])?,
-            _ => {
+        None => match (focus_package.targets.len(), &focus_package.default_run) {
+            (0, _) => anyhow::bail!("No targets found"),
+            (1, _) => opts::Focus::try_from(&focus_package.targets[0])?,
+            // same as `cargo run` does
+            (_, Some(bin)) => {
+                if opts.format.verbosity > 0 {
+                    esafeprintln!("Using default-run target {bin:?} from the manifest");
+                }
+                opts::Focus::Bin(bin.clone())
+            }
+            (_, None) => {
                 esafeprintln!(
                     "{} defines multiple targets, you need to specify which one to use:",
                     focus_package.name
@@ -268,67 +904,33 @@ fn main() -> anyhow::Result<()> {
             }
         },
     };
+    Ok((focus_package, focus_artifact))
+}
 
-    // Pending on this https://github.com/rust-lang/rust/pull/122597
-
-    #[cfg(feature = "disasm")]
-    let force_single_cgu = opts.syntax.output_type != OutputType::Disasm;
-
-    #[cfg(not(feature = "disasm"))]
-    let force_single_cgu = true;
-
-    let cargo_child = spawn_cargo(
-        cargo,
-        &opts.format,
-        opts.syntax,
-        opts.target_cpu.as_deref(),
-        focus_package,
-        &focus_artifact,
-        force_single_cgu,
-    )?;
-
-    let asm_path = cargo_to_asm_path(cargo_child, &focus_artifact, &opts)?;
-
-    if opts.format.verbosity > 2 {
-        safeprintln!("goal: {:?}", opts.to_dump);
+/// Target to use for a package with --workspace: library if there's one, otherwise
+/// the only target or default-run binary
+fn workspace_focus(package: &Package) -> Option<opts::Focus> {
+    use cargo_metadata::TargetKind as T;
+    if let Some(lib) = package.targets.iter().find(|t| {
+        t.kind
+            .iter()
+            .any(|k| matches!(k, T::Lib | T::RLib | T::CDyLib | T::ProcMacro))
+    }) {
+        return opts::Focus::try_from(lib).ok();
     }
-
-    match opts.syntax.output_type {
-        OutputType::Asm | OutputType::Wasm => {
-            let asm = Asm::new(metadata.workspace_root.as_std_path(), &sysroot);
-            dump_function(&asm, opts.to_dump, &asm_path, &opts.format)
-        }
-        OutputType::Llvm | OutputType::LlvmInput => {
-            dump_function(&Llvm, opts.to_dump, &asm_path, &opts.format)
-        }
-        OutputType::Mir => dump_function(&Mir, opts.to_dump, &asm_path, &opts.format),
-        OutputType::Mca => {
-            let mca = Mca::new(
-                &opts.mca_arg,
-                opts.syntax.output_style,
-                cargo.target.as_deref(),
-                opts.target_cpu.as_deref(),
-            );
-            dump_function(&mca, opts.to_dump, &asm_path, &opts.format)
-        }
-        #[cfg(not(feature = "disasm"))]
-        OutputType::Disasm => no_disasm!(),
-
-        #[cfg(feature = "disasm")]
-        OutputType::Disasm => dump_disasm(
-            opts.to_dump,
-            &asm_path,
-            &opts.format,
-            opts.syntax.output_style,
-        ),
+    match (package.targets.as_slice(), &package.default_run) {
+        ([target], _) => opts::Focus::try_from(target).ok(),
+        (_, Some(bin)) => Some(opts::Focus::Bin(bin.clone())),
+        _ => None,
     }
 }
 
-fn cargo_to_asm_path(
+fn cargo_to_asm_paths(
     mut cargo: Child,
     focus_artifact: &opts::Focus,
     opts: &crate::opts::Options,
-) -> anyhow::Result<PathBuf> {
+    quiet: bool,
+) -> anyhow::Result<Vec<PathBuf>> {
     let mut result_artifact = None;
     let mut success = false;
     for msg in Message::parse_stream(BufReader::new(cargo.stdout.take().unwrap())) {
@@ -344,41 +946,59 @@ fn cargo_to_asm_path(
         }
     }
     // add some spacing between cargo's output and ours
-    esafeprintln!();
+    if !quiet || opts.format.verbosity > 0 {
+        esafeprintln!();
+    }
     if !success {
         let status = cargo.wait()?;
         esafeprintln!("Cargo failed with {status}");
-        std::process::exit(101);
+        cargo_show_asm::exit(101);
     }
     let artifact = result_artifact.context("No artifact found")?;
 
     if opts.format.verbosity > 0 {
         esafeprintln!("Artifact files: {:?}", artifact.filenames);
+        if artifact.fresh {
+            esafeprintln!("Artifact is fresh, nothing was rebuilt");
+        } else {
+            esafeprintln!("Artifact was rebuilt");
+        }
     }
 
-    let asm_path = match opts.syntax.ext() {
+    let asm_paths = match opts.syntax.ext() {
         Some(expect_ext) => locate_asm_path_via_artifact(&artifact, expect_ext)?,
         None => {
             if let Some(executable) = artifact.executable {
-                executable.into()
+                vec![executable.into()]
             } else if let Some(rlib) = artifact
                 .filenames
                 .iter()
                 .find(|f| f.extension() == Some("rlib"))
             {
-                rlib.into()
+                vec![rlib.into()]
             } else {
                 todo!("{:?}", artifact);
             }
         }
     };
+    if asm_paths.len() > 1 {
+        esafeprintln!(
+            "Crate was split into {} codegen units, searching all of them",
+            asm_paths.len()
+        );
+    }
     if opts.format.verbosity > 0 {
-        esafeprintln!("Working with file: {}", asm_path.display());
+        for asm_path in &asm_paths {
+            esafeprintln!("Working with file: {}", asm_path.display());
+        }
     }
-    Ok(asm_path)
+    Ok(asm_paths)
 }
 
-fn locate_asm_path_via_artifact(artifact: &Artifact, expect_ext: &str) -> anyhow::Result<PathBuf> {
+fn locate_asm_path_via_artifact(
+    artifact: &Artifact,
+    expect_ext: &str,
+) -> anyhow::Result<Vec<PathBuf>> {
     // For lib, test, bench, lib-type example, `filenames` hint the file stem of the asm file.
     // We could locate asm files precisely.
     //
@@ -389,7 +1009,7 @@ fn locate_asm_path_via_artifact(artifact: &Artifact, expect_ext: &str) -> anyhow
     // Asm files:
     // [..]/target/debug/deps/foo-01234567.s
     // [..]/target/debug/deps/example/foo-01234567.s
-    if let Some(path) = artifact
+    if let Some(files) = artifact
         .filenames
         .iter()
         .filter(|path| {
@@ -400,17 +1020,16 @@ fn locate_asm_path_via_artifact(artifact: &Artifact, expect_ext: &str) -> anyhow
         })
         .find_map(|path| {
             let path = path.with_extension(expect_ext);
-            if path.exists() {
-                return Some(path);
+            let files = asm_files(path.as_std_path());
+            if !files.is_empty() {
+                return Some(files);
             }
             let path = path.with_file_name(path.file_name()?.strip_prefix("lib")?);
-            if path.exists() {
-                return Some(path);
-            }
-            None
+            let files = asm_files(path.as_std_path());
+            (!files.is_empty()).then_some(files)
         })
     {
-        return Ok(path.into_std_path_buf());
+        return Ok(files);
     }
 
     // then there's rlib with filenames as following:
@@ -425,7 +1044,7 @@ fn locate_asm_path_via_artifact(artifact: &Artifact, expect_ext: &str) -> anyhow
     if let Some(rlib_path) = artifact
         .filenames
         .iter()
-        .find(|f| f.extension().map_or(false, |e| e == "rlib"))
+        .find(|f| f.extension().is_some_and(|e| e == "rlib"))
     {
         let deps_dir = rlib_path.with_file_name("deps");
 
@@ -440,8 +1059,9 @@ fn locate_asm_path_via_artifact(artifact: &Artifact, expect_ext: &str) -> anyhow
                     .strip_prefix("lib")
                     .unwrap();
                 let asm_file = maybe_origin.with_file_name(name).with_extension(expect_ext);
-                if asm_file.exists() {
-                    return Ok(asm_file);
+                let files = asm_files(&asm_file);
+                if !files.is_empty() {
+                    return Ok(files);
                 }
             }
         }
@@ -458,7 +1078,7 @@ fn locate_asm_path_via_artifact(artifact: &Artifact, expect_ext: &str) -> anyhow
     //    if artifact.target.kind.iter().any(|k| k == "cdylib") {
     if let Some(cdylib_path) = artifact.filenames.iter().find(|f| {
         f.extension()
-            .map_or(false, |e| ["so", "dylib", "dll"].contains(&e))
+            .is_some_and(|e| ["so", "dylib", "dll"].contains(&e))
     }) {
         let deps_dir = cdylib_path.with_file_name("deps");
         for entry in deps_dir.read_dir()? {
@@ -472,8 +1092,9 @@ fn locate_asm_path_via_artifact(artifact: &Artifact, expect_ext: &str) -> anyhow
                 let name = name.strip_prefix("lib").unwrap_or(name);
                 // on windows this is xx.dll -> xx.s, no lib....
                 let asm_file = maybe_origin.with_file_name(name).with_extension(expect_ext);
-                if asm_file.exists() {
-                    return Ok(asm_file);
+                let files = asm_files(&asm_file);
+                if !files.is_empty() {
+                    return Ok(files);
                 }
             }
         }
@@ -505,8 +1126,9 @@ fn locate_asm_path_via_artifact(artifact: &Artifact, expect_ext: &str) -> anyhow
             let maybe_origin = entry?.path();
             if same_contents(&exe_path, &maybe_origin)? {
                 let asm_file = maybe_origin.with_extension(expect_ext);
-                if asm_file.exists() {
-                    return Ok(asm_file);
+                let files = asm_files(&asm_file);
+                if !files.is_empty() {
+                    return Ok(files);
                 }
             }
         }
@@ -515,8 +1137,174 @@ fn locate_asm_path_via_artifact(artifact: &Artifact, expect_ext: &str) -> anyhow
     anyhow::bail!("Cannot locate the path to the asm file");
 }
 
+/// Object files and rlibs in a directory such as `target/release/deps`, for `--file DIR`
+#[cfg(feature = "disasm")]
+fn object_files(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in dir.read_dir()? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_some_and(|ext| ext == "o" || ext == "rlib")
+        {
+            files.push(path);
+        }
+    }
+    if files.is_empty() {
+        anyhow::bail!("There are no .o or .rlib files in {}", dir.display());
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Files present for an asm file `path`
+///
+/// With several codegen units rustc doesn't produce `foo-01234567.s`, instead each unit
+/// gets a separate file: `foo-01234567.foo.a1b2c3d4-cgu.0.rcgu.s`. If both kinds are
+/// present - whichever is newer comes from the most recent build.
+fn asm_files(path: &Path) -> Vec<PathBuf> {
+    let mut units = Vec::new();
+    if let (Some(dir), Some(stem), Some(ext)) = (
+        path.parent(),
+        path.file_stem().and_then(|s| s.to_str()),
+        path.extension().and_then(|e| e.to_str()),
+    ) {
+        let prefix = format!("{stem}.");
+        let suffix = format!(".rcgu.{ext}");
+        for entry in dir.read_dir().into_iter().flatten().flatten() {
+            let unit = entry.path();
+            if unit
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(&prefix) && name.ends_with(&suffix))
+            {
+                units.push(unit);
+            }
+        }
+    }
+    units.sort();
+
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    if path.exists() && modified(path) >= units.iter().filter_map(|unit| modified(unit)).max() {
+        return vec![path.to_owned()];
+    }
+    units
+}
+
 fn same_contents<A: AsRef<Path>, B: AsRef<Path>>(a: &A, b: &B) -> anyhow::Result<bool> {
     Ok(same_file::is_same_file(a, b)?
         || (std::fs::metadata(a)?.len() == std::fs::metadata(b)?.len()
             && std::fs::read(a)? == std::fs::read(b)?))
 }
+
+#[test]
+fn rustflags_are_joined_and_removed() {
+    let flags = [
+        "-C",
+        "codegen-units=16",
+        "--emit",
+        "asm",
+        "-Ctarget_cpu=native",
+        "-Zfoo",
+    ];
+    let mut flags = join_flags(flags.into_iter().map(String::from).collect());
+    assert_eq!(
+        flags,
+        [
+            "-Ccodegen-units=16",
+            "--emit=asm",
+            "-Ctarget_cpu=native",
+            "-Zfoo"
+        ]
+    );
+    assert_eq!(remove_flag(&mut flags, "-Ccodegen-units="), ["16"]);
+    assert_eq!(remove_flag(&mut flags, "-Ctarget-cpu="), ["native"]);
+    assert_eq!(flags, ["--emit=asm", "-Zfoo"]);
+}
+
+#[test]
+fn target_features_are_found() {
+    let cfg = "debug_assertions\n\
+               target_arch=\"x86_64\"\n\
+               target_feature=\"avx2\"\n\
+               target_feature=\"fxsr\"\n\
+               target_os=\"linux\"\n";
+    assert_eq!(target_features(cfg), ["avx2", "fxsr"]);
+    assert!(is_target_flag("-Ctarget_cpu=native"));
+    assert!(is_target_flag("-Ctarget-feature=+avx512f"));
+    assert!(!is_target_flag("-Ccodegen-units=16"));
+}
+
+#[test]
+fn cargo_config_is_parsed() {
+    let config = r#"
+# comment
+[build]
+target = "x86_64-unknown-linux-musl" # trailing comment
+jobs = 4
+rustflags = [
+    "-C", "target-cpu=native", # one per line
+    '-Zshare-generics',
+]
+
+[target.'cfg(target_os = "linux")']
+rustflags = ["--cfg", "linux"]
+
+[target.x86_64-unknown-linux-gnu]
+linker = "clang"
+
+[env]
+DATA = { value = "data", relative = true }
+PATH_ESCAPED = "a\"b\\c"
+"#;
+    let entries = config_entries(config);
+    let get = |key: &[&str]| {
+        entries
+            .iter()
+            .find_map(|(k, value)| (k == key).then_some(value.clone()))
+    };
+    assert_eq!(
+        get(&["build", "target"]),
+        Some(serde_json::json!("x86_64-unknown-linux-musl"))
+    );
+    assert_eq!(get(&["build", "jobs"]), None);
+    assert_eq!(
+        get(&["build", "rustflags"]),
+        Some(serde_json::json!([
+            "-C",
+            "target-cpu=native",
+            "-Zshare-generics"
+        ]))
+    );
+    assert_eq!(
+        get(&["target", "cfg(target_os = \"linux\")", "rustflags"]),
+        Some(serde_json::json!(["--cfg", "linux"]))
+    );
+    assert_eq!(
+        get(&["target", "x86_64-unknown-linux-gnu", "linker"]),
+        Some(serde_json::json!("clang"))
+    );
+    assert_eq!(get(&["env", "DATA"]), None);
+    assert_eq!(
+        get(&["env", "PATH_ESCAPED"]),
+        Some(serde_json::json!("a\"b\\c"))
+    );
+}
+
+#[test]
+fn cfg_predicates_are_evaluated() {
+    let cfg = ["unix", "target_os=\"linux\"", "target_arch=\"x86_64\""];
+    assert!(cfg_matches("unix", &cfg));
+    assert!(!cfg_matches("windows", &cfg));
+    assert!(cfg_matches("target_os = \"linux\"", &cfg));
+    assert!(!cfg_matches("target_os = \"macos\"", &cfg));
+    assert!(cfg_matches(
+        "all(unix, target_arch = \"x86_64\", not(windows))",
+        &cfg
+    ));
+    assert!(!cfg_matches("all(unix, windows)", &cfg));
+    assert!(cfg_matches("any(windows, unix)", &cfg));
+    assert!(!cfg_matches("any()", &cfg));
+    assert!(!cfg_matches("not(unix, windows)", &cfg));
+    assert!(!cfg_matches("unix junk", &cfg));
+}
@@ -1,11 +1,15 @@
 use crate::{
-    asm::Statement,
-    demangle, esafeprintln,
+    asm::{Directive, Statement},
+    color, demangle, esafeprintln,
     opts::{Format, OutputStyle},
     safeprintln, Dumpable,
 };
+use owo_colors::OwoColorize;
 use std::{
+    collections::BTreeMap,
     io::{BufRead, BufReader},
+    ops::Range,
+    path::PathBuf,
     process::{Command, Stdio},
 };
 
@@ -15,6 +19,8 @@ pub struct Mca<'a> {
     output_style: OutputStyle,
     target_triple: Option<&'a str>,
     target_cpu: Option<&'a str>,
+    /// run llvm-mca separately for instructions of each source line
+    per_line: bool,
 }
 impl<'a> Mca<'a> {
     pub fn new(
@@ -22,30 +28,18 @@ impl<'a> Mca<'a> {
         output_style: OutputStyle,
         target_triple: Option<&'a str>,
         target_cpu: Option<&'a str>,
+        per_line: bool,
     ) -> Self {
         Self {
             args: mca_args,
             output_style,
             target_triple,
             target_cpu,
+            per_line,
         }
     }
-}
-
-impl Dumpable for Mca<'_> {
-    type Line<'a> = Statement<'a>;
 
-    fn split_lines(contents: &str) -> anyhow::Result<Vec<Self::Line<'_>>> {
-        crate::asm::parse_file(contents)
-    }
-
-    fn find_items(
-        lines: &[Self::Line<'_>],
-    ) -> std::collections::BTreeMap<crate::Item, std::ops::Range<usize>> {
-        crate::asm::find_items(lines)
-    }
-
-    fn dump_range(&self, fmt: &Format, lines: &[Self::Line<'_>]) -> anyhow::Result<()> {
+    fn run(&self, fmt: &Format, lines: &[Statement]) -> anyhow::Result<()> {
         use std::io::Write;
 
         let mut mca = Command::new("llvm-mca");
@@ -65,7 +59,7 @@ impl Dumpable for Mca<'_> {
             Err(err) => {
                 esafeprintln!("Failed to start llvm-mca, do you have it installed? The error was");
                 esafeprintln!("{err}");
-                std::process::exit(1);
+                crate::exit(1);
             }
         };
 
@@ -107,4 +101,107 @@ impl Dumpable for Mca<'_> {
 
         Ok(())
     }
+
+    /// Run llvm-mca for each chunk of instructions from the same source line, `file_names`
+    /// come from `.file` directives of the whole file
+    fn run_per_line(
+        &self,
+        fmt: &Format,
+        lines: &[Statement],
+        file_names: &BTreeMap<u64, PathBuf>,
+    ) -> anyhow::Result<()> {
+        for (pos, range) in source_line_chunks(lines) {
+            let header = match pos {
+                Some((file, line)) => match file_names.get(&file) {
+                    Some(name) => format!("# {} : {line}", name.display()),
+                    None => format!("# file #{file} : {line}"),
+                },
+                None => String::from("# no source line"),
+            };
+            safeprintln!("\n{}", color!(header, OwoColorize::cyan));
+            self.run(fmt, &lines[range])?;
+        }
+        Ok(())
+    }
+}
+
+/// `(file, line)` of a source line and statements with its code
+type SourceLineChunk = (Option<(u64, u64)>, Range<usize>);
+
+/// Split statements into consecutive chunks of instructions that come from the same
+/// source line: `(file, line)` of the `.loc` directive, `None` for code before the first one.
+/// Line 0 marks compiler generated code and doesn't start a new chunk
+fn source_line_chunks(lines: &[Statement]) -> Vec<SourceLineChunk> {
+    let mut res = Vec::new();
+    let mut current = None;
+    let mut start = 0;
+    let mut has_code = false;
+    for (ix, line) in lines.iter().enumerate() {
+        match line {
+            Statement::Directive(Directive::Loc(loc)) if loc.line != 0 => {
+                let pos = Some((loc.file, loc.line));
+                if pos != current {
+                    if has_code {
+                        res.push((current, start..ix));
+                    }
+                    current = pos;
+                    start = ix;
+                    has_code = false;
+                }
+            }
+            Statement::Instruction(instr) if !instr.op.starts_with('#') => has_code = true,
+            _ => {}
+        }
+    }
+    if has_code {
+        res.push((current, start..lines.len()));
+    }
+    res
+}
+
+impl Dumpable for Mca<'_> {
+    type Line<'a> = Statement<'a>;
+
+    fn split_lines(contents: &str) -> anyhow::Result<Vec<Self::Line<'_>>> {
+        crate::asm::parse_file(contents)
+    }
+
+    fn find_items(
+        lines: &[Self::Line<'_>],
+    ) -> std::collections::BTreeMap<crate::Item, std::ops::Range<usize>> {
+        crate::asm::find_items(lines)
+    }
+
+    fn dump_range(&self, fmt: &Format, lines: &[Self::Line<'_>]) -> anyhow::Result<()> {
+        self.dump_file_range(fmt, lines, 0..lines.len())
+    }
+
+    fn dump_file_range(
+        &self,
+        fmt: &Format,
+        lines: &[Self::Line<'_>],
+        range: Range<usize>,
+    ) -> anyhow::Result<()> {
+        if self.per_line {
+            self.run_per_line(fmt, &lines[range], &crate::asm::file_names(lines))
+        } else {
+            self.run(fmt, &lines[range])
+        }
+    }
+}
+
+#[test]
+fn instructions_are_chunked_by_source_line() {
+    let lines = crate::asm::parse_file(
+        "\tpushq\t%rax\n\t.loc\t1 2 3\n\tmovq\t%rdi, %rax\n\t.loc\t1 0 3\n\taddq\t%rsi, %rax\n\t.loc\t1 2 5\n\tshlq\t%rax\n\t.loc\t1 4 3\n\t.loc\t1 5 3\n\tpopq\t%rcx\n",
+    )
+    .unwrap();
+    assert_eq!(
+        source_line_chunks(&lines),
+        [
+            (None, 0..1),
+            (Some((1, 2)), 1..7),
+            (Some((1, 5)), 8..lines.len())
+        ]
+    );
 }
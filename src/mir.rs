@@ -1,5 +1,5 @@
 use crate::Dumpable;
-use crate::{color, opts::Format, safeprintln, Item};
+use crate::{color, esafeprintln, exit, exit_code, opts::Format, safeprintln, Item};
 use line_span::LineSpans;
 use owo_colors::OwoColorize;
 use std::{collections::BTreeMap, ops::Range};
@@ -45,7 +45,12 @@ impl Dumpable for Mir {
                     hashed: name.to_owned(),
                     index: res.len(),
                     len: start,
+                    position: start,
                     non_blank_len: 0,
+                    body_hash: None,
+                    merged: false,
+                    weak: false,
+                    object: false,
                 });
             }
         }
@@ -53,6 +58,25 @@ impl Dumpable for Mir {
         res
     }
 
+    fn select_part(fmt: &Format, lines: &[&str], range: Range<usize>) -> Range<usize> {
+        let Some(bb) = &fmt.bb else {
+            return range;
+        };
+        let strings = &lines[range.clone()];
+        if let Some(block) = find_block(strings, bb) {
+            return range.start + block.start..range.start + block.end;
+        }
+        let blocks = strings
+            .iter()
+            .filter_map(|line| block_name(line))
+            .collect::<Vec<_>>();
+        esafeprintln!(
+            "Can't find basic block {bb:?}, available blocks are: {}",
+            blocks.join(", ")
+        );
+        exit(exit_code::NOT_FOUND);
+    }
+
     fn dump_range(&self, _fmt: &Format, strings: &[&str]) -> anyhow::Result<()> {
         for line in strings {
             if let Some(ix) = line.rfind("//") {
@@ -71,3 +95,36 @@ impl Dumpable for Mir {
             .collect::<Vec<_>>())
     }
 }
+
+/// Name of a basic block if this line starts one: `    bb3: {` or `    bb4 (cleanup): {`
+fn block_name(line: &str) -> Option<&str> {
+    let line = line.strip_prefix("    ")?.strip_suffix(": {")?;
+    let name = line.split_once(' ').map_or(line, |(name, _)| name);
+    name.starts_with("bb").then_some(name)
+}
+
+/// Lines of a basic block with a given name, including the closing brace
+fn find_block(lines: &[&str], bb: &str) -> Option<Range<usize>> {
+    let start = lines.iter().position(|line| block_name(line) == Some(bb))?;
+    let len = lines[start..].iter().position(|line| *line == "    }")?;
+    Some(start..start + len + 1)
+}
+
+#[test]
+fn basic_blocks() {
+    let lines = [
+        "fn foo() -> () {",
+        "    bb0: {",
+        "        goto -> bb1;",
+        "    }",
+        "",
+        "    bb1 (cleanup): {",
+        "        return;",
+        "    }",
+        "}",
+    ];
+    assert_eq!(block_name(lines[5]), Some("bb1"));
+    assert_eq!(find_block(&lines, "bb0"), Some(1..4));
+    assert_eq!(find_block(&lines, "bb1"), Some(5..8));
+    assert_eq!(find_block(&lines, "bb2"), None);
+}
@@ -11,6 +11,36 @@ fn check_target_dir(path: PathBuf) -> anyhow::Result<PathBuf> {
     }
 }
 
+fn parse_address(addr: String) -> Result<u64, std::num::ParseIntError> {
+    match addr.strip_prefix("0x").or_else(|| addr.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => addr.parse(),
+    }
+}
+
+fn parse_regex(pattern: String) -> Result<regex::Regex, regex::Error> {
+    regex::Regex::new(&pattern)
+}
+
+fn check_triple(triple: String) -> Result<String, &'static str> {
+    if triple.trim().is_empty() {
+        Err("target triple can't be empty")
+    } else {
+        Ok(triple)
+    }
+}
+
+fn parse_location(location: String) -> Result<(PathBuf, u64), &'static str> {
+    let (file, line) = location
+        .rsplit_once(':')
+        .ok_or("location must look like FILE:LINE")?;
+    let line = line.parse().map_err(|_| "line must be a number")?;
+    if file.is_empty() {
+        return Err("file name can't be empty");
+    }
+    Ok((PathBuf::from(file), line))
+}
+
 #[derive(Clone, Debug, Bpaf)]
 #[bpaf(options("asm"), version)]
 #[allow(clippy::struct_excessive_bools)]
@@ -32,16 +62,25 @@ pub struct Options {
     pub select_fragment: SelectFragment,
 
     // how to compile
-    #[bpaf(external)]
+    #[bpaf(external, hide_usage)]
     pub code_source: CodeSource,
 
     // how to display
     /// Pass parameter to llvm-mca for mca targets
     #[bpaf(short('M'), long)]
     pub mca_arg: Vec<String>,
-    /// Generate code for a specific CPU
+    /// Run llvm-mca separately for instructions of each Rust source line
+    #[bpaf(hide_usage)]
+    pub mca_per_line: bool,
+    /// Target triple for llvm-mca, defaults to the build target or the host for --file
+    #[bpaf(argument("TRIPLE"), parse(check_triple), optional, hide_usage)]
+    pub mca_triple: Option<String>,
+    /// Generate code for a specific CPU, repeat to compare several CPUs
     #[bpaf(external)]
-    pub target_cpu: Option<String>,
+    pub target_cpu: Vec<String>,
+    /// Show assembly in both Intel and AT&T syntax, one after another
+    #[bpaf(hide_usage)]
+    pub both_syntaxes: bool,
     #[bpaf(external)]
     pub format: Format,
     #[bpaf(external(syntax_compat))]
@@ -60,12 +99,20 @@ pub enum CodeSource {
     },
     File {
         /// Disassemble or process this file instead of calling cargo,
-        ///  requires cargo-show-asm to be compiled with disasm feature
+        /// requires cargo-show-asm to be compiled with disasm feature
         ///
-        /// You can specify executable, rlib or an object file
+        /// You can specify executable, rlib or an object file, assembly files with `.s`
+        /// extension are processed the same way as ones generated by cargo. For a directory
+        /// all the object files and rlibs in it are disassembled together
         #[bpaf(argument("PATH"), hide_usage)]
         file: PathBuf,
     },
+
+    /// Demangle and highlight Rust names in lines read from stdin instead of calling cargo
+    DemangleOnly,
+
+    /// Print versions of cargo-show-asm, Capstone, rustc and LLVM for bug reports
+    VersionVerbose,
 }
 
 #[derive(Clone, Debug, Bpaf)]
@@ -78,6 +125,10 @@ pub struct SelectFragment {
     #[bpaf(long, short, argument("SPEC"))]
     pub package: Option<String>,
 
+    /// Build all the packages in the workspace and search for items in all of them
+    #[bpaf(hide_usage)]
+    pub workspace: bool,
+
     #[bpaf(external, optional)]
     pub focus: Option<Focus>,
 }
@@ -141,6 +192,9 @@ pub enum ToDump {
     /// Dump the whole file
     Everything,
 
+    /// List available functions with their indices and sizes in lines
+    List,
+
     #[bpaf(hide)]
     ByIndex {
         /// Dump name with this index
@@ -153,25 +207,26 @@ pub enum ToDump {
         #[bpaf(positional("FUNCTION"))]
         function: String,
 
-        /// Select specific function when there's several with the same name
+        /// Select specific function when there's several with the same name,
+        /// negative values count from the end: -1 is the last one
         #[bpaf(positional("INDEX"))]
-        nth: Option<usize>,
+        nth: Option<isize>,
     },
 
     #[bpaf(skip)]
     Unspecified,
 }
 
-fn target_cpu() -> impl Parser<Option<String>> {
+fn target_cpu() -> impl Parser<Vec<String>> {
     let native = long("native")
         .help("Optimize for the CPU running the compiler")
         .req_flag("native".to_string());
     let cpu = long("target-cpu")
-        .help("Optimize code for a specific CPU, see 'rustc --print target-cpus'")
+        .help("Optimize code for a specific CPU, see 'rustc --print target-cpus', can be used multiple times")
         .argument::<String>("CPU");
     construct!([native, cpu])
         .custom_usage(&[("TARGET-CPU", Style::Metavar)])
-        .optional()
+        .many()
 }
 
 #[derive(Bpaf, Clone, Debug)]
@@ -187,8 +242,18 @@ pub struct CliFeatures {
     pub features: Vec<String>,
 }
 
+impl CliFeatures {
+    /// Individual features, split the same way cargo does: by commas and spaces
+    pub fn split_features(&self) -> impl Iterator<Item = &str> {
+        self.features
+            .iter()
+            .flat_map(|f| f.split([',', ' ']))
+            .filter(|f| !f.is_empty())
+    }
+}
+
 #[derive(Bpaf, Clone, Debug)]
-#[bpaf(fallback(CompileMode::Release))]
+#[bpaf(fallback(CompileMode::Unspecified))]
 pub enum CompileMode {
     /// Compile in release mode (default)
     Release,
@@ -199,6 +264,94 @@ pub enum CompileMode {
         #[bpaf(env("CARGO_SHOW_ASM_PROFILE"), long("profile"), argument("PROFILE"))]
         String,
     ),
+    /// Nothing specified, use profile from the manifest or release
+    #[bpaf(skip)]
+    Unspecified,
+}
+
+impl CompileMode {
+    /// Resolve unspecified compile mode using manifest defaults, falls back to release
+    #[must_use]
+    pub fn or_manifest(self, defaults: &ManifestDefaults) -> Self {
+        match self {
+            CompileMode::Unspecified => match defaults.profile.as_deref() {
+                None | Some("release") => CompileMode::Release,
+                Some("dev") => CompileMode::Dev,
+                Some(profile) => CompileMode::Custom(profile.to_owned()),
+            },
+            mode => mode,
+        }
+    }
+}
+
+/// Defaults taken from `[package.metadata.show-asm]` table of the package manifest
+///
+/// Command line options and environment variables take priority over those
+#[derive(Debug, Clone, Default)]
+pub struct ManifestDefaults {
+    /// Profile to use, same as `--profile`
+    pub profile: Option<String>,
+    /// Same as `--target-cpu`
+    pub target_cpu: Option<String>,
+    /// Same as `--simplify`
+    pub simplify: bool,
+    /// Same as `--rust`
+    pub rust: bool,
+    /// `"intel"` or `"att"`, same as `--intel` and `--att`
+    pub syntax: Option<OutputStyle>,
+}
+
+impl ManifestDefaults {
+    pub fn from_package(package: &cargo_metadata::Package) -> anyhow::Result<Self> {
+        match package.metadata.get("show-asm") {
+            Some(table) => Self::from_metadata(table),
+            None => Ok(Self::default()),
+        }
+    }
+
+    fn from_metadata(table: &serde_json::Value) -> anyhow::Result<Self> {
+        let string = |name: &str| match table.get(name) {
+            None => Ok(None),
+            Some(serde_json::Value::String(val)) => Ok(Some(val.clone())),
+            Some(val) => Err(anyhow::anyhow!(
+                "show-asm: {name} must be a string, got {val}"
+            )),
+        };
+        let flag = |name: &str| match table.get(name) {
+            None => Ok(false),
+            Some(serde_json::Value::Bool(val)) => Ok(*val),
+            Some(val) => Err(anyhow::anyhow!(
+                "show-asm: {name} must be a bool, got {val}"
+            )),
+        };
+        let syntax = match string("syntax")?.as_deref() {
+            None => None,
+            Some("intel") => Some(OutputStyle::Intel),
+            Some("att") => Some(OutputStyle::Att),
+            Some(val) => {
+                anyhow::bail!("show-asm: syntax must be \"intel\" or \"att\", got {val:?}")
+            }
+        };
+        Ok(Self {
+            profile: string("profile")?,
+            target_cpu: string("target-cpu")?,
+            simplify: flag("simplify")?,
+            rust: flag("rust")?,
+            syntax,
+        })
+    }
+
+    /// Fill in the values not specified on a command line
+    pub fn apply(&self, opts: &mut Options) {
+        if opts.target_cpu.is_empty() {
+            opts.target_cpu.extend(self.target_cpu.iter().cloned());
+        }
+        // metadata is only consulted when neither --simplify nor --no-simplify is given
+        let fmt = &mut opts.format;
+        fmt.simplify = !fmt.no_simplify && (fmt.simplify || self.simplify);
+        fmt.rust = !fmt.no_rust && (fmt.rust || self.rust);
+        opts.syntax.output_style = opts.syntax.output_style.or(self.syntax);
+    }
 }
 
 fn verbosity() -> impl Parser<usize> {
@@ -234,22 +387,97 @@ fn manifest_path() -> impl Parser<PathBuf> {
 }
 
 #[allow(clippy::struct_excessive_bools)]
-#[derive(Debug, Clone, Bpaf, Copy)]
+#[derive(Debug, Clone, Bpaf)]
 /// Postprocessing options:
 pub struct Format {
     /// Print interleaved Rust code
     pub rust: bool,
 
-    /// Include other called functions, recursively, up to COUNT depth
+    /// Don't print Rust code, overrides `rust = true` from package metadata
+    #[bpaf(hide_usage)]
+    pub no_rust: bool,
+
+    /// Show flags such as prologue_end or discriminator next to Rust source locations
+    #[bpaf(hide_usage)]
+    pub loc_flags: bool,
+
+    /// Start Rust source location comments with STR instead of //
+    #[bpaf(argument("STR"), fallback("//".to_owned()), hide_usage)]
+    pub source_prefix: String,
+
+    /// List Rust source lines within the function that produced no assembly
+    #[bpaf(hide_usage)]
+    pub dead_lines: bool,
+
+    /// Don't repeat a Rust source line if it was printed within the last N statements
+    #[bpaf(argument("N"), fallback(0), hide_usage)]
+    pub source_window: usize,
+
+    /// Include other called functions, recursively, up to COUNT depth. Functions defined
+    /// outside of the file, such as ones from std, are listed by name
     #[bpaf(short, long, argument("COUNT"), fallback(0), display_fallback)]
     pub context: usize,
 
+    /// Include a function with this name as additional context, can be used multiple times
+    #[bpaf(argument("FUNCTION"), hide_usage)]
+    pub also: Vec<String>,
+
+    /// Include parts split from the function such as foo.cold.1 as additional context
+    #[bpaf(hide_usage)]
+    pub cold_parts: bool,
+
     #[bpaf(external(color_detection), hide_usage)]
     pub color: bool,
 
+    /// Highlight registers, aliasing registers such as rax and eax share the color
+    #[bpaf(hide_usage)]
+    pub color_regs: bool,
+
+    /// Highlight immediate values and numeric offsets such as $0x10 or #42
+    #[bpaf(hide_usage)]
+    pub color_imms: bool,
+
+    #[bpaf(external, hide_usage)]
+    pub rewrite: Option<OutputStyle>,
+
+    /// Experimental: rewrite x86 instructions, sections and data declarations towards NASM syntax
+    #[bpaf(hide_usage)]
+    pub nasm: bool,
+
+    /// List all the functions sorted by number of lines instead of dumping one, best used
+    /// with --llvm. Copies of generic functions are aggregated unless --full-name is given
+    #[bpaf(hide_usage)]
+    pub llvm_lines: bool,
+
+    /// Report changes in function sizes compared to FILE, an assembly or llvm-ir file saved
+    /// from an earlier build. Only functions present in both are compared
+    #[bpaf(argument("FILE"), optional, hide_usage)]
+    pub baseline: Option<PathBuf>,
+
+    /// Show only this basic block of a MIR function, for example bb3
+    #[bpaf(argument("BLOCK"), optional, hide_usage)]
+    pub bb: Option<String>,
+
+    /// Collapse LLVM IR basic blocks to their label and terminator, except for blocks
+    /// matching --grep pattern
+    #[bpaf(hide_usage)]
+    pub fold: bool,
+
+    /// Rename unnamed LLVM IR values and blocks to %v0, %v1, ... in order of definition
+    #[bpaf(hide_usage)]
+    pub normalize: bool,
+
+    /// Highlight matches of this regular expression in instruction operands
+    #[bpaf(argument::<String>("PATTERN"), parse(parse_regex), optional, hide_usage)]
+    pub grep: Option<regex::Regex>,
+
     #[bpaf(hide_usage, external)]
     pub name_display: NameDisplay,
 
+    /// Append the mangled symbol name as a comment to function labels and .globl directives
+    #[bpaf(hide_usage)]
+    pub show_mangled: bool,
+
     #[bpaf(external, hide_usage)]
     pub redundant_labels: RedundantLabels,
 
@@ -260,13 +488,138 @@ pub struct Format {
     /// Try to strip some of the non-assembly instruction information
     pub simplify: bool,
 
+    /// Don't strip anything, overrides `simplify = true` from package metadata
+    #[bpaf(hide_usage)]
+    pub no_simplify: bool,
+
+    /// Prefix instructions with their sequential number within the function
+    #[bpaf(hide_usage)]
+    pub offsets: bool,
+
+    /// Prefix instructions with their encoding, asks LLVM to annotate assembly with
+    /// -show-mc-encoding
+    #[bpaf(hide_usage)]
+    pub encoding: bool,
+
+    /// Annotate local labels with a rough count of registers used in the block that follows
+    #[bpaf(hide_usage)]
+    pub reg_pressure: bool,
+
+    /// Mark instructions that use the stack, stores and loads of stack slots are marked as
+    /// spills and reloads
+    #[bpaf(hide_usage)]
+    pub stack: bool,
+
+    /// Mark instructions that do nothing: `nop` padding and moves of a register to itself
+    #[bpaf(hide_usage)]
+    pub highlight_nops: bool,
+
+    /// Summarize how many instructions use vector registers and the widest vector width
+    #[bpaf(hide_usage)]
+    pub simd_summary: bool,
+
+    /// Print a deduplicated list of functions called by the selected function to stderr
+    #[bpaf(hide_usage)]
+    pub calls: bool,
+
+    /// Skip `#DEBUG_VALUE:` comments, independent of --simplify
+    #[bpaf(hide_usage)]
+    pub no_debug_values: bool,
+
+    /// Fold Windows exception handling code and data that follow the function body into one line
+    #[bpaf(hide_usage)]
+    pub no_eh: bool,
+
+    /// Keep call frame information directives (.cfi_*) even with --simplify
+    #[bpaf(hide_usage)]
+    pub cfi: bool,
+
+    /// Group functions with identical bodies in the list of suggestions
+    #[bpaf(hide_usage)]
+    pub group_identical: bool,
+
+    /// Hide merged function aliases from the list of suggestions, they can still be picked by name
+    #[bpaf(hide_usage)]
+    pub no_merged: bool,
+
+    /// Order of the list of suggestions: name (default), index or size, biggest first
+    #[bpaf(argument("KEY"), fallback(SortBy::Name), hide_usage)]
+    pub sort: SortBy,
+
+    /// Select the function whose full demangled path without the hash is equal to FUNCTION
+    /// instead of containing it
+    #[bpaf(hide_usage)]
+    pub exact_path: bool,
+
+    /// Select functions with instructions generated from this line of a source file,
+    /// FILE can be a part of the path such as src/lib.rs. Assembly output only
+    #[bpaf(argument::<String>("FILE:LINE"), parse(parse_location), optional, hide_usage)]
+    pub at: Option<(PathBuf, u64)>,
+
     /// Include sections containing string literals and other constants
     pub include_constants: bool,
 
+    /// Parse only functions matching the search request, faster on big files. Ignored
+    /// with options that need the whole file such as --context
+    #[bpaf(hide_usage)]
+    pub parse_selected: bool,
+
+    /// Print instructions grouped by Rust source line as JSON
+    #[bpaf(hide_usage)]
+    pub source_json: bool,
+
+    /// Print parsed statements as JSON, one object per line
+    #[bpaf(hide_usage)]
+    pub ndjson: bool,
+
+    /// Also write the dumped code into FILE as an HTML page with calls linked to functions
+    #[bpaf(argument("FILE"), optional, hide_usage)]
+    pub html: Option<PathBuf>,
+
+    /// Show only lines FROM to TO of the function, 1-based and inclusive, either end can be omitted
+    #[bpaf(argument("FROM..TO"), optional, hide_usage)]
+    pub lines: Option<LineRange>,
+
+    /// With --disasm start disassembling at ADDR, hex with 0x prefix or decimal
+    #[bpaf(argument::<String>("ADDR"), parse(parse_address), optional, hide_usage)]
+    pub start_address: Option<u64>,
+
+    /// With --disasm stop disassembling at ADDR, hex with 0x prefix or decimal
+    #[bpaf(argument::<String>("ADDR"), parse(parse_address), optional, hide_usage)]
+    pub stop_address: Option<u64>,
+
+    /// With --disasm also list data symbols such as statics and show them as a hex dump
+    #[bpaf(hide_usage)]
+    pub data: bool,
+
     /// Keep blank lines
     #[bpaf(short('b'), long, hide_usage)]
     pub keep_blank: bool,
 
+    /// Print the file as is if it can't be parsed, only lines between the function label and
+    /// its end if a function is given
+    #[bpaf(hide_usage)]
+    pub raw: bool,
+
+    /// Keep whitespace between instructions and their operands as emitted by the compiler
+    #[bpaf(hide_usage)]
+    pub raw_format: bool,
+
+    /// Replace tabs at the start of output lines with N spaces, 0 keeps the tabs
+    #[bpaf(argument("N"), fallback(0), hide_usage)]
+    pub tab_width: usize,
+
+    #[bpaf(external(pager_detection), hide_usage)]
+    pub pager: bool,
+
+    /// Look for standard library sources under PATH instead of the sysroot reported by rustc
+    #[bpaf(argument("PATH"), optional, hide_usage)]
+    pub sysroot: Option<PathBuf>,
+
+    /// Don't show source lines from this registry crate with --rust, can be used several times
+    #[bpaf(argument("CRATE"), hide_usage)]
+    pub hide_source_from: Vec<String>,
+
     #[bpaf(external)]
     pub sources_from: SourcesFrom,
 }
@@ -301,8 +654,16 @@ pub enum RedundantLabels {
 pub enum NameDisplay {
     #[bpaf(long("full-name"))]
     /// Include full demangled name instead of just prefix
+    ///
+    /// For v0 mangled symbols this also includes crate disambiguators and const generic
+    /// values with their types
     Full,
 
+    /// Include full demangled name, legacy mangled names are followed by the mangled
+    /// symbol: unlike v0 they don't keep generic parameters
+    #[bpaf(long("verbose-name"))]
+    Verbose,
+
     /// Include demangled names without hash suffix (default)
     #[bpaf(long("short-name"))]
     Short,
@@ -312,6 +673,66 @@ pub enum NameDisplay {
     Mangled,
 }
 
+/// Order of functions in the list of suggestions
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SortBy {
+    Name,
+    /// Position in the file, the order the compiler emitted functions in
+    Index,
+    /// Number of lines, biggest first
+    Size,
+}
+
+impl std::str::FromStr for SortBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(Self::Name),
+            "index" => Ok(Self::Index),
+            "size" => Ok(Self::Size),
+            _ => Err(format!("Expected name, index or size, got {s:?}")),
+        }
+    }
+}
+
+/// A range of lines within a function, 1-based and inclusive
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct LineRange {
+    pub from: Option<usize>,
+    pub to: Option<usize>,
+}
+
+impl std::str::FromStr for LineRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, to) = s
+            .split_once("..")
+            .ok_or_else(|| format!("Expected a range in FROM..TO form, got {s:?}"))?;
+        let bound = |b: &str| -> Result<Option<usize>, String> {
+            if b.is_empty() {
+                return Ok(None);
+            }
+            match b.parse::<usize>() {
+                Ok(0) => Err("Line numbers start from 1".to_owned()),
+                Ok(n) => Ok(Some(n)),
+                Err(e) => Err(format!("{b:?} is not a valid line number: {e}")),
+            }
+        };
+        let range = Self {
+            from: bound(from)?,
+            to: bound(to.strip_prefix('=').unwrap_or(to))?,
+        };
+        if let (Some(from), Some(to)) = (range.from, range.to) {
+            if from > to {
+                return Err(format!("Range {s:?} is empty"));
+            }
+        }
+        Ok(range)
+    }
+}
+
 #[derive(Debug, Clone, Bpaf, Eq, PartialEq, Copy)]
 #[bpaf(fallback(OutputType::Asm))]
 pub enum OutputType {
@@ -323,6 +744,8 @@ pub enum OutputType {
     Llvm,
     /// Show llvm-ir before any LLVM passes
     LlvmInput,
+    /// Show what LLVM passes changed in llvm-ir
+    LlvmDiff,
     /// Show MIR
     Mir,
     /// Show WASM, needs wasm32-unknown-unknown target installed
@@ -332,7 +755,6 @@ pub enum OutputType {
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Bpaf)]
-#[bpaf(fallback(OutputStyle::Intel))]
 pub enum OutputStyle {
     /// Use Intel style for assembly
     Intel,
@@ -340,42 +762,58 @@ pub enum OutputStyle {
     Att,
 }
 
+fn rewrite() -> impl Parser<Option<OutputStyle>> {
+    let intel = long("rewrite-intel")
+        .help("Rewrite AT&T style x86 instructions into Intel style")
+        .req_flag(OutputStyle::Intel);
+    let att = long("rewrite-att")
+        .help("Rewrite Intel style x86 instructions into AT&T style")
+        .req_flag(OutputStyle::Att);
+    construct!([intel, att]).optional()
+}
+
 #[derive(Debug, Clone, Copy, Bpaf)]
 #[bpaf(custom_usage(&[("OUTPUT-FORMAT", Style::Metavar)]))]
 /// Pick output type:
 pub struct Syntax {
     #[bpaf(external)]
     pub output_type: OutputType,
-    #[bpaf(external)]
-    pub output_style: OutputStyle,
+    /// Intel unless specified otherwise, use [`Syntax::style`]
+    #[bpaf(external, optional)]
+    pub output_style: Option<OutputStyle>,
 }
 
 fn syntax_compat() -> impl Parser<Syntax> {
     let mca_att = long("mca-att")
         .req_flag(Syntax {
             output_type: OutputType::Mca,
-            output_style: OutputStyle::Att,
+            output_style: Some(OutputStyle::Att),
         })
         .hide();
     let mca_intel = long("mca-intel")
         .req_flag(Syntax {
             output_type: OutputType::Mca,
-            output_style: OutputStyle::Intel,
+            output_style: Some(OutputStyle::Intel),
         })
         .hide();
     construct!([syntax(), mca_att, mca_intel])
 }
 
 impl Syntax {
+    #[must_use]
+    pub fn style(&self) -> OutputStyle {
+        self.output_style.unwrap_or(OutputStyle::Intel)
+    }
+
     #[must_use]
     pub fn format(&self) -> Option<&str> {
         match self.output_type {
-            OutputType::Asm | OutputType::Mca => match self.output_style {
+            OutputType::Asm | OutputType::Mca => match self.style() {
                 OutputStyle::Intel => Some("llvm-args=-x86-asm-syntax=intel"),
                 OutputStyle::Att => Some("llvm-args=-x86-asm-syntax=att"),
             },
             OutputType::LlvmInput => Some("no-prepopulate-passes"),
-            OutputType::Llvm | OutputType::Mir | OutputType::Wasm => None,
+            OutputType::Llvm | OutputType::LlvmDiff | OutputType::Mir | OutputType::Wasm => None,
 
             OutputType::Disasm => None,
         }
@@ -385,7 +823,7 @@ impl Syntax {
     pub fn emit(&self) -> Option<&str> {
         match self.output_type {
             OutputType::Asm | OutputType::Wasm | OutputType::Mca => Some("asm"),
-            OutputType::Llvm | OutputType::LlvmInput => Some("llvm-ir"),
+            OutputType::Llvm | OutputType::LlvmInput | OutputType::LlvmDiff => Some("llvm-ir"),
             OutputType::Mir => Some("mir"),
 
             OutputType::Disasm => None,
@@ -396,7 +834,7 @@ impl Syntax {
     pub fn ext(&self) -> Option<&str> {
         match self.output_type {
             OutputType::Asm | OutputType::Wasm | OutputType::Mca => Some("s"),
-            OutputType::Llvm | OutputType::LlvmInput => Some("ll"),
+            OutputType::Llvm | OutputType::LlvmInput | OutputType::LlvmDiff => Some("ll"),
             OutputType::Mir => Some("mir"),
 
             OutputType::Disasm => None,
@@ -412,10 +850,57 @@ fn color_detection() -> impl Parser<bool> {
         .help("Disable color highlighting")
         .req_flag(false);
     construct!([yes, no]).fallback_with::<_, &str>(|| {
-        Ok(supports_color::on(supports_color::Stream::Stdout).is_some())
+        Ok(resolve_color(
+            |name| std::env::var_os(name),
+            || supports_color::on(supports_color::Stream::Stdout).is_some(),
+        ))
+    })
+}
+
+/// Decide on color highlighting in absence of explicit `--color`/`--no-color`
+///
+/// `CLICOLOR_FORCE` wins over `NO_COLOR`, which wins over `CLICOLOR=0`, which wins
+/// over terminal detection, see <https://no-color.org> and <https://bixense.com/clicolors>
+fn resolve_color(
+    env: impl Fn(&str) -> Option<std::ffi::OsString>,
+    is_terminal: impl FnOnce() -> bool,
+) -> bool {
+    if env("CLICOLOR_FORCE").is_some_and(|val| !val.is_empty() && val != "0") {
+        true
+    } else if env("NO_COLOR").is_some_and(|val| !val.is_empty())
+        || env("CLICOLOR").is_some_and(|val| val == "0")
+    {
+        false
+    } else {
+        is_terminal()
+    }
+}
+
+fn pager_detection() -> impl Parser<bool> {
+    let yes = long("pager")
+        .help("Send output through a pager, default when printing to a terminal")
+        .req_flag(true);
+    let no = long("no-pager")
+        .help("Print output directly, without a pager")
+        .req_flag(false);
+    construct!([yes, no]).fallback_with::<_, &str>(|| {
+        use std::io::IsTerminal;
+        Ok(std::io::stdout().is_terminal())
     })
 }
 
+/// Pager command from `CARGO_SHOW_ASM_PAGER` or `PAGER`, `less -R` if neither is set.
+/// An empty value disables the pager
+pub fn pager_command(env: impl Fn(&str) -> Option<std::ffi::OsString>) -> Option<String> {
+    let command = env("CARGO_SHOW_ASM_PAGER")
+        .or_else(|| env("PAGER"))
+        .map_or_else(
+            || "less -R".to_owned(),
+            |val| val.to_string_lossy().into_owned(),
+        );
+    (!command.trim().is_empty()).then_some(command)
+}
+
 #[derive(Debug, Clone, Bpaf)]
 /// Pick artifact for analysis:
 #[bpaf(custom_usage(&[("ARTIFACT", Style::Metavar)]))]
@@ -423,6 +908,9 @@ pub enum Focus {
     /// Show results from library code
     Lib,
 
+    /// Show results from a procedural macro crate
+    ProcMacro,
+
     Test(
         /// Show results from an integration test
         #[bpaf(long("test"), argument("TEST"))]
@@ -476,6 +964,7 @@ impl TryFrom<&'_ cargo_metadata::Target> for Focus {
         let name = target.name.clone();
         match kind {
             T::Lib | T::RLib | T::CDyLib => Ok(Focus::Lib),
+            T::ProcMacro => Ok(Focus::ProcMacro),
             T::Test => Ok(Focus::Test(name)),
             T::Bench => Ok(Focus::Bench(name)),
             T::Example => Ok(Focus::Example(name)),
@@ -491,6 +980,7 @@ impl Focus {
     pub fn as_parts(&self) -> (&str, Option<&str>) {
         match self {
             Focus::Lib => ("lib", None),
+            Focus::ProcMacro => ("proc-macro", None),
             Focus::Test(name) => ("test", Some(name)),
             Focus::TestList => ("test", None),
             Focus::Bench(name) => ("bench", Some(name)),
@@ -504,6 +994,8 @@ impl Focus {
 
     pub fn as_cargo_args(&self) -> impl Iterator<Item = String> {
         let (kind, name) = self.as_parts();
+        // proc-macro is a library as far as cargo's target selection goes
+        let kind = if kind == "proc-macro" { "lib" } else { kind };
         std::iter::once(format!("--{kind}")).chain(name.map(ToOwned::to_owned))
     }
 
@@ -515,8 +1007,7 @@ impl Focus {
         let kind = <cargo_metadata::TargetKind as std::str::FromStr>::from_str(kind)
             .expect("cargo_metadata made me do it");
         let kind_matches = artifact.target.kind.contains(&kind);
-        (somewhat_matches || kind_matches)
-            && name.map_or(true, |name| artifact.target.name == *name)
+        (somewhat_matches || kind_matches) && name.is_none_or(|name| artifact.target.name == *name)
     }
 }
 
@@ -543,6 +1034,47 @@ fn write_updated(new_val: &str, path: impl AsRef<std::path::Path>) -> std::io::R
     }
 }
 
+#[test]
+fn compile_mode_from_manifest() {
+    let defaults =
+        ManifestDefaults::from_metadata(&serde_json::json!({ "profile": "dev" })).unwrap();
+    assert!(matches!(
+        CompileMode::Unspecified.or_manifest(&defaults),
+        CompileMode::Dev
+    ));
+    assert!(matches!(
+        CompileMode::Release.or_manifest(&defaults),
+        CompileMode::Release
+    ));
+    assert!(matches!(
+        CompileMode::Unspecified.or_manifest(&ManifestDefaults::default()),
+        CompileMode::Release
+    ));
+    assert!(ManifestDefaults::from_metadata(&serde_json::json!({ "profile": 1 })).is_err());
+
+    let defaults = ManifestDefaults::from_metadata(&serde_json::json!({
+        "target-cpu": "native",
+        "simplify": true,
+        "syntax": "att",
+    }))
+    .unwrap();
+    let mut opts = options().run_inner(&["--intel"]).unwrap();
+    defaults.apply(&mut opts);
+    assert_eq!(opts.target_cpu, ["native"]);
+    assert!(opts.format.simplify);
+    assert!(!opts.format.rust);
+    assert_eq!(opts.syntax.style(), OutputStyle::Intel);
+
+    let mut opts = options().run_inner(&["--no-simplify"]).unwrap();
+    defaults.apply(&mut opts);
+    assert!(!opts.format.simplify);
+
+    let conflict = options().run_inner(&["--release", "--dev"]).unwrap_err();
+    assert!(conflict
+        .unwrap_stderr()
+        .contains("cannot be used at the same time"));
+}
+
 #[cfg(unix)]
 #[test]
 fn docs_are_up_to_date() {
@@ -551,3 +1083,174 @@ fn docs_are_up_to_date() {
     let docs = readme.replacen("<USAGE>", &usage, 1);
     assert!(write_updated(&docs, "README.md").unwrap());
 }
+
+#[test]
+fn line_range_parsing() {
+    let r = |s: &str| s.parse::<LineRange>();
+    assert_eq!(
+        r("100..200"),
+        Ok(LineRange {
+            from: Some(100),
+            to: Some(200)
+        })
+    );
+    assert_eq!(
+        r("10.."),
+        Ok(LineRange {
+            from: Some(10),
+            to: None
+        })
+    );
+    assert_eq!(
+        r("..=5"),
+        Ok(LineRange {
+            from: None,
+            to: Some(5)
+        })
+    );
+    assert!(r("0..5").is_err());
+    assert!(r("5..2").is_err());
+    assert!(r("5").is_err());
+}
+
+#[test]
+fn features_are_split() {
+    let features = CliFeatures {
+        no_default_features: false,
+        all_features: false,
+        features: vec!["a,b c".into(), "dep/feat".into(), " ,d".into()],
+    };
+    assert_eq!(
+        features.split_features().collect::<Vec<_>>(),
+        ["a", "b", "c", "dep/feat", "d"]
+    );
+}
+
+#[test]
+fn color_resolution() {
+    let resolve = |vars: &[(&str, &str)], is_terminal: bool| {
+        resolve_color(
+            |name| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, val)| val.into())
+            },
+            || is_terminal,
+        )
+    };
+    assert!(resolve(&[], true));
+    assert!(!resolve(&[], false));
+    assert!(!resolve(&[("NO_COLOR", "1")], true));
+    assert!(resolve(&[("NO_COLOR", "")], true));
+    assert!(!resolve(&[("CLICOLOR", "0")], true));
+    assert!(resolve(&[("CLICOLOR", "1")], true));
+    assert!(!resolve(&[("CLICOLOR", "1")], false));
+    assert!(resolve(&[("CLICOLOR_FORCE", "1")], false));
+    assert!(!resolve(&[("CLICOLOR_FORCE", "0")], false));
+    assert!(resolve(
+        &[("CLICOLOR_FORCE", "1"), ("NO_COLOR", "1")],
+        false
+    ));
+    assert!(!resolve(&[("NO_COLOR", "1"), ("CLICOLOR", "1")], true));
+
+    // explicit flags win over everything
+    assert!(!options().run_inner(&["--no-color"]).unwrap().format.color);
+    assert!(options().run_inner(&["--color"]).unwrap().format.color);
+}
+
+#[test]
+fn proc_macro_focus() {
+    let focus = focus().to_options().run_inner(&["--proc-macro"]).unwrap();
+    assert_eq!(focus.as_parts(), ("proc-macro", None));
+    assert_eq!(focus.as_cargo_args().collect::<Vec<_>>(), ["--lib"]);
+}
+
+#[test]
+fn demangle_only_skips_cargo() {
+    let opts = options().run_inner(&["--demangle-only"]).unwrap();
+    assert!(matches!(opts.code_source, CodeSource::DemangleOnly));
+}
+
+#[test]
+fn version_verbose_skips_cargo() {
+    let opts = options().run_inner(&["--version-verbose"]).unwrap();
+    assert!(matches!(opts.code_source, CodeSource::VersionVerbose));
+}
+
+#[test]
+fn addresses_are_parsed() {
+    let opts = options()
+        .run_inner(&[
+            "--disasm",
+            "--start-address",
+            "0x1234",
+            "--stop-address",
+            "4864",
+        ])
+        .unwrap();
+    assert_eq!(opts.format.start_address, Some(0x1234));
+    assert_eq!(opts.format.stop_address, Some(0x1300));
+    assert!(options()
+        .run_inner(&["--disasm", "--start-address", "0xzz"])
+        .is_err());
+}
+
+#[test]
+fn sort_key_is_parsed() {
+    let opts = options().run_inner(&["--sort", "size"]).unwrap();
+    assert_eq!(opts.format.sort, SortBy::Size);
+    let opts = options().run_inner(&[]).unwrap();
+    assert_eq!(opts.format.sort, SortBy::Name);
+    assert!(options().run_inner(&["--sort", "length"]).is_err());
+}
+
+#[test]
+fn mca_triple_is_validated() {
+    let opts = options()
+        .run_inner(&["--mca-triple", "aarch64-unknown-linux-gnu"])
+        .unwrap();
+    assert_eq!(
+        opts.mca_triple.as_deref(),
+        Some("aarch64-unknown-linux-gnu")
+    );
+    assert!(options().run_inner(&["--mca-triple", ""]).is_err());
+}
+
+#[test]
+fn pager_resolution() {
+    let resolve = |vars: &[(&str, &str)]| {
+        pager_command(|name| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, val)| val.into())
+        })
+    };
+    assert_eq!(resolve(&[]).as_deref(), Some("less -R"));
+    assert_eq!(resolve(&[("PAGER", "more")]).as_deref(), Some("more"));
+    assert_eq!(
+        resolve(&[("PAGER", "more"), ("CARGO_SHOW_ASM_PAGER", "bat -p")]).as_deref(),
+        Some("bat -p")
+    );
+    assert_eq!(
+        resolve(&[("CARGO_SHOW_ASM_PAGER", ""), ("PAGER", "more")]),
+        None
+    );
+
+    assert!(!options().run_inner(&["--no-pager"]).unwrap().format.pager);
+    assert!(options().run_inner(&["--pager"]).unwrap().format.pager);
+}
+
+#[test]
+fn locations_are_parsed() {
+    let at = |args: &[&str]| options().run_inner(args).map(|opts| opts.format.at);
+    assert_eq!(
+        at(&["--at", "src/lib.rs:42"]).unwrap(),
+        Some((PathBuf::from("src/lib.rs"), 42))
+    );
+    assert_eq!(
+        at(&["--at", "C:\\src\\lib.rs:7"]).unwrap(),
+        Some((PathBuf::from("C:\\src\\lib.rs"), 7))
+    );
+    assert!(at(&["--at", "src/lib.rs"]).is_err());
+    assert!(at(&["--at", ":42"]).is_err());
+}